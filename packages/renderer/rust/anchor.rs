@@ -0,0 +1,104 @@
+use crate::{
+    errors::ErrorWithBacktrace,
+    payloads::payloads::{Anchor, Layer},
+    text::measure_text_layer,
+};
+
+// The point of the canvas that the same point of the layer is pinned to.
+// Offsets towards the center are positive: with BottomRight, x = 20 and y = 20
+// leave a 20px margin to the right and bottom edge. With a centered axis,
+// positive offsets move right or down.
+fn resolve(
+    anchor: &mut Option<Anchor>,
+    (x, y): (f64, f64),
+    (width, height): (u32, u32),
+    (canvas_width, canvas_height): (u32, u32),
+) -> Option<(f64, f64)> {
+    let anchor = anchor.take()?;
+    let free_x = canvas_width as f64 - width as f64;
+    let free_y = canvas_height as f64 - height as f64;
+
+    let resolved_x = match anchor {
+        Anchor::TopLeft | Anchor::Left | Anchor::BottomLeft => x,
+        Anchor::Top | Anchor::Center | Anchor::Bottom => free_x / 2.0 + x,
+        Anchor::TopRight | Anchor::Right | Anchor::BottomRight => free_x - x,
+    };
+    let resolved_y = match anchor {
+        Anchor::TopLeft | Anchor::Top | Anchor::TopRight => y,
+        Anchor::Left | Anchor::Center | Anchor::Right => free_y / 2.0 + y,
+        Anchor::BottomLeft | Anchor::Bottom | Anchor::BottomRight => free_y - y,
+    };
+    Some((resolved_x, resolved_y))
+}
+
+// Turns anchored positions into absolute ones, once the size of the canvas is known.
+// Layers that are drawn at whole pixels are rounded.
+pub fn resolve_anchors(
+    canvas_width: u32,
+    canvas_height: u32,
+    layers: &mut [Layer],
+) -> Result<(), ErrorWithBacktrace> {
+    let canvas = (canvas_width, canvas_height);
+
+    for layer in layers {
+        match layer {
+            Layer::PngImage(layer) | Layer::JpgImage(layer) => {
+                let position = (layer.x as f64, layer.y as f64);
+                let size = (layer.width, layer.height);
+                if let Some((x, y)) = resolve(&mut layer.anchor, position, size, canvas) {
+                    (layer.x, layer.y) = (x as f32, y as f32);
+                }
+            }
+            Layer::Solid(layer) => {
+                let position = (layer.x as f64, layer.y as f64);
+                let size = (layer.width, layer.height);
+                if let Some((x, y)) = resolve(&mut layer.anchor, position, size, canvas) {
+                    (layer.x, layer.y) = (x as f32, y as f32);
+                }
+            }
+            Layer::Gradient(layer) => {
+                let position = (layer.x as f64, layer.y as f64);
+                let size = (layer.width, layer.height);
+                if let Some((x, y)) = resolve(&mut layer.anchor, position, size, canvas) {
+                    (layer.x, layer.y) = (x.round() as i32, y.round() as i32);
+                }
+            }
+            Layer::Video(layer) => {
+                let position = (layer.x as f64, layer.y as f64);
+                let size = (layer.width, layer.height);
+                if let Some((x, y)) = resolve(&mut layer.anchor, position, size, canvas) {
+                    (layer.x, layer.y) = (x.round() as i32, y.round() as i32);
+                }
+            }
+            Layer::Blur(layer) => {
+                let position = (layer.x as f64, layer.y as f64);
+                let size = (layer.width, layer.height);
+                if let Some((x, y)) = resolve(&mut layer.anchor, position, size, canvas) {
+                    (layer.x, layer.y) = (x.round() as i32, y.round() as i32);
+                }
+            }
+            Layer::Ellipse(layer) => {
+                let position = (layer.x as f64, layer.y as f64);
+                let size = (layer.width, layer.height);
+                if let Some((x, y)) = resolve(&mut layer.anchor, position, size, canvas) {
+                    (layer.x, layer.y) = (x.round() as i32, y.round() as i32);
+                }
+            }
+            // The text is laid out to know its size
+            Layer::Text(layer) => {
+                if layer.anchor.is_none() {
+                    continue;
+                }
+                let position = (layer.x as f64, layer.y as f64);
+                let size = measure_text_layer(layer)?;
+                if let Some((x, y)) = resolve(&mut layer.anchor, position, size, canvas) {
+                    (layer.x, layer.y) = (x.round() as i32, y.round() as i32);
+                }
+            }
+            // Takes the rectangle of the layer that it masks
+            Layer::Mask(_) => {}
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,135 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use lazy_static::lazy_static;
+
+use crate::{assets::SourceImage, errors::ErrorWithBacktrace, payloads::payloads::AssetCacheStats};
+
+// Can be changed with asset_cache_size_in_bytes of StartLongRunningProcess
+pub const DEFAULT_ASSET_CACHE_SIZE_IN_BYTES: u64 = 256 * 1024 * 1024;
+
+struct CacheEntry {
+    image: Arc<SourceImage>,
+    size_in_bytes: u64,
+    last_used: u64,
+}
+
+pub struct AssetCacheState {
+    entries: HashMap<String, CacheEntry>,
+    size_in_bytes: u64,
+    capacity_in_bytes: u64,
+    // Incremented on every access, the entry with the lowest value is evicted first
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl AssetCacheState {
+    fn evict_until(&mut self, capacity_in_bytes: u64) {
+        while self.size_in_bytes > capacity_in_bytes {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(src, _)| src.clone());
+            match oldest.and_then(|src| self.entries.remove(&src)) {
+                Some(entry) => self.size_in_bytes -= entry.size_in_bytes,
+                None => break,
+            }
+        }
+    }
+}
+
+// Decoded originals of image sources, shared by all commands of the process.
+// Sizing, cropping and flipping happen on a copy, so layers that use the same
+// source with a different size all hit the same entry.
+// Entries are keyed by `src` only: a file that changes while the process is
+// running keeps being served from the cache until it is evicted.
+pub struct AssetCache {
+    state: Mutex<AssetCacheState>,
+}
+
+impl AssetCache {
+    pub fn get_instance() -> &'static AssetCache {
+        lazy_static! {
+            static ref INSTANCE: AssetCache = AssetCache {
+                state: Mutex::new(AssetCacheState {
+                    entries: HashMap::new(),
+                    size_in_bytes: 0,
+                    capacity_in_bytes: DEFAULT_ASSET_CACHE_SIZE_IN_BYTES,
+                    clock: 0,
+                    hits: 0,
+                    misses: 0,
+                }),
+            };
+        }
+        &INSTANCE
+    }
+
+    pub fn set_capacity(&self, capacity_in_bytes: u64) -> Result<(), ErrorWithBacktrace> {
+        let mut state = self.state.lock()?;
+        state.capacity_in_bytes = capacity_in_bytes;
+        state.evict_until(capacity_in_bytes);
+        Ok(())
+    }
+
+    pub fn get(&self, src: &str) -> Result<Option<Arc<SourceImage>>, ErrorWithBacktrace> {
+        let mut state = self.state.lock()?;
+        state.clock += 1;
+        let clock = state.clock;
+        let image = state.entries.get_mut(src).map(|entry| {
+            entry.last_used = clock;
+            entry.image.clone()
+        });
+        match image {
+            Some(_) => state.hits += 1,
+            None => state.misses += 1,
+        }
+        Ok(image)
+    }
+
+    // Images that are bigger than the whole cache are not stored
+    pub fn insert(&self, src: &str, image: Arc<SourceImage>) -> Result<(), ErrorWithBacktrace> {
+        let size_in_bytes = (image.image.data.len() + src.len()) as u64;
+        let mut state = self.state.lock()?;
+        if size_in_bytes > state.capacity_in_bytes {
+            return Ok(());
+        }
+
+        state.clock += 1;
+        let entry = CacheEntry {
+            image,
+            size_in_bytes,
+            last_used: state.clock,
+        };
+        if let Some(previous) = state.entries.insert(src.to_string(), entry) {
+            state.size_in_bytes -= previous.size_in_bytes;
+        }
+        state.size_in_bytes += size_in_bytes;
+
+        let capacity_in_bytes = state.capacity_in_bytes;
+        state.evict_until(capacity_in_bytes);
+        Ok(())
+    }
+
+    // Frees the memory, the counters are kept
+    pub fn clear(&self) -> Result<(), ErrorWithBacktrace> {
+        let mut state = self.state.lock()?;
+        state.entries.clear();
+        state.size_in_bytes = 0;
+        Ok(())
+    }
+
+    pub fn get_stats(&self) -> Result<AssetCacheStats, ErrorWithBacktrace> {
+        let state = self.state.lock()?;
+        Ok(AssetCacheStats {
+            hits: state.hits,
+            misses: state.misses,
+            entries: state.entries.len(),
+            size_in_bytes: state.size_in_bytes,
+            capacity_in_bytes: state.capacity_in_bytes,
+        })
+    }
+}
@@ -0,0 +1,162 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use rayon::prelude::*;
+
+use crate::{
+    asset_cache::AssetCache,
+    errors::ErrorWithBacktrace,
+    image::{check_source_pixels, check_source_size, decode_image, RgbaImage},
+    source::{get_mime_type_from_bytes, is_data_uri, read_source},
+};
+
+// A decoded source, together with the format that was detected from its data
+pub struct SourceImage {
+    pub image: RgbaImage,
+    pub mime_type: Option<&'static str>,
+}
+
+// A source whose size is known, but that might not be decoded yet
+pub enum PendingSource {
+    Decoded(Arc<SourceImage>),
+    Read {
+        bytes: Vec<u8>,
+        width: u32,
+        height: u32,
+    },
+}
+
+impl PendingSource {
+    pub fn size(&self) -> (u32, u32) {
+        match self {
+            PendingSource::Decoded(loaded) => (loaded.image.width, loaded.image.height),
+            PendingSource::Read { width, height, .. } => (*width, *height),
+        }
+    }
+
+    // Sources that had to be decoded are added to the AssetCache, except for
+    // data URIs, whose key would be as large as the data that they encode
+    pub fn decode(
+        self,
+        src: &str,
+        max_source_pixels: u64,
+    ) -> Result<Arc<SourceImage>, ErrorWithBacktrace> {
+        let bytes = match self {
+            PendingSource::Decoded(loaded) => return Ok(loaded),
+            PendingSource::Read { bytes, .. } => bytes,
+        };
+        let loaded = Arc::new(SourceImage {
+            image: decode_image(src, &bytes, max_source_pixels)?,
+            mime_type: get_mime_type_from_bytes(&bytes),
+        });
+        if !is_data_uri(src) {
+            AssetCache::get_instance().insert(src, loaded.clone())?;
+        }
+        Ok(loaded)
+    }
+}
+
+// Enough for an uncompressed 16-bit RGBA image with max_source_pixels, plus
+// room for metadata such as color profiles
+fn get_max_source_bytes(max_source_pixels: u64) -> u64 {
+    max_source_pixels
+        .saturating_mul(8)
+        .saturating_add(16 * 1024 * 1024)
+}
+
+// Hits of the AssetCache skip reading the source. Otherwise only the header is
+// looked at, so that the size can be checked before anything is decoded.
+fn read_pending_source(
+    src: &str,
+    network_timeout: Duration,
+    max_source_pixels: u64,
+) -> Result<PendingSource, ErrorWithBacktrace> {
+    let cached = match is_data_uri(src) {
+        true => None,
+        false => AssetCache::get_instance().get(src)?,
+    };
+    if let Some(cached) = cached {
+        // It was checked against the limit of the command that decoded it,
+        // which can be higher than the current one
+        check_source_pixels(
+            src,
+            cached.image.width,
+            cached.image.height,
+            max_source_pixels,
+        )?;
+        return Ok(PendingSource::Decoded(cached));
+    }
+
+    let bytes = read_source(
+        src,
+        network_timeout,
+        get_max_source_bytes(max_source_pixels),
+    )?;
+    let (width, height) = check_source_size(src, &bytes, max_source_pixels)?;
+    Ok(PendingSource::Read {
+        bytes,
+        width,
+        height,
+    })
+}
+
+fn load_source(
+    src: &str,
+    network_timeout: Duration,
+    max_source_pixels: u64,
+) -> Result<Arc<SourceImage>, ErrorWithBacktrace> {
+    read_pending_source(src, network_timeout, max_source_pixels)?.decode(src, max_source_pixels)
+}
+
+// Images that a ComposeBatch decodes once and shares between all of its outputs
+#[derive(Default)]
+pub struct DecodedAssets {
+    images: HashMap<String, Arc<SourceImage>>,
+}
+
+impl DecodedAssets {
+    pub fn decode(
+        srcs: &[String],
+        network_timeout: Duration,
+        max_source_pixels: u64,
+    ) -> Result<DecodedAssets, ErrorWithBacktrace> {
+        let images = srcs
+            .par_iter()
+            .map(|src| {
+                Ok((
+                    src.clone(),
+                    load_source(src, network_timeout, max_source_pixels)?,
+                ))
+            })
+            .collect::<Result<HashMap<String, Arc<SourceImage>>, ErrorWithBacktrace>>()?;
+
+        Ok(DecodedAssets { images })
+    }
+
+    // Shared images are returned as they are, other sources are only read
+    pub fn read(
+        &self,
+        src: &str,
+        network_timeout: Duration,
+        max_source_pixels: u64,
+    ) -> Result<PendingSource, ErrorWithBacktrace> {
+        match self.images.get(src) {
+            Some(image) => Ok(PendingSource::Decoded(image.clone())),
+            None => read_pending_source(src, network_timeout, max_source_pixels),
+        }
+    }
+
+    // Shared and cached images are handed out as they are, layers copy them
+    // only if they crop, flip or adjust them.
+    // Sources that are not in the asset list go through the AssetCache.
+    pub fn get_or_decode(
+        &self,
+        src: &str,
+        network_timeout: Duration,
+        max_source_pixels: u64,
+    ) -> Result<Arc<SourceImage>, ErrorWithBacktrace> {
+        match self.images.get(src) {
+            Some(image) => Ok(image.clone()),
+            None => load_source(src, network_timeout, max_source_pixels),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+extern crate ffmpeg_next as remotionffmpeg;
+
+use remotionffmpeg::{codec, filter, format, frame, media, ChannelLayout, Rational};
+
+use crate::errors::ErrorWithBacktrace;
+use crate::payloads::payloads::{AudioWaveform, EnvelopeBucket};
+
+// Downmixes to mono 32-bit float samples and keeps only the requested window
+fn waveform_filter(
+    decoder: &codec::decoder::Audio,
+    time_base: Rational,
+    start: f64,
+    duration: f64,
+    sample_rate: u32,
+) -> Result<filter::Graph, ErrorWithBacktrace> {
+    let mut graph = filter::Graph::new();
+
+    // Some containers don't store a channel layout, only the amount of channels
+    let channel_layout = match decoder.channel_layout().bits() {
+        0 => ChannelLayout::default(decoder.channels() as i32),
+        _ => decoder.channel_layout(),
+    };
+    let args = format!(
+        "time_base={}:sample_rate={}:sample_fmt={}:channel_layout=0x{:x}",
+        time_base,
+        decoder.rate(),
+        decoder.format().name(),
+        channel_layout.bits()
+    );
+
+    let abuffer_filter = filter::find("abuffer")
+        .ok_or_else(|| ErrorWithBacktrace::from("Expected abuffer filter"))?;
+    let abuffer_sink_filter = filter::find("abuffersink")
+        .ok_or_else(|| ErrorWithBacktrace::from("Expected abuffersink filter"))?;
+
+    graph.add(&abuffer_filter, "in", &args)?;
+    graph.add(&abuffer_sink_filter, "out", "")?;
+
+    let spec = format!(
+        "atrim=start={}:duration={},aformat=sample_fmts=flt:sample_rates={}:channel_layouts=mono",
+        start, duration, sample_rate
+    );
+    graph.output("in", 0)?.input("out", 0)?.parse(&spec)?;
+    graph.validate()?;
+
+    Ok(graph)
+}
+
+fn collect_filtered_samples(
+    graph: &mut filter::Graph,
+    samples: &mut Vec<f32>,
+) -> Result<(), ErrorWithBacktrace> {
+    let mut filtered = frame::Audio::empty();
+    let mut sink = graph
+        .get("out")
+        .ok_or_else(|| ErrorWithBacktrace::from("Could not get out filter"))?;
+    while sink.sink().frame(&mut filtered).is_ok() {
+        samples.extend_from_slice(filtered.plane::<f32>(0));
+    }
+    Ok(())
+}
+
+fn decode_frames(
+    decoder: &mut codec::decoder::Audio,
+    graph: &mut filter::Graph,
+    samples: &mut Vec<f32>,
+) -> Result<(), ErrorWithBacktrace> {
+    let mut decoded = frame::Audio::empty();
+    while decoder.receive_frame(&mut decoded).is_ok() {
+        let timestamp = decoded.timestamp();
+        decoded.set_pts(timestamp);
+        graph
+            .get("in")
+            .ok_or_else(|| ErrorWithBacktrace::from("Could not get in filter"))?
+            .source()
+            .add(&decoded)?;
+        collect_filtered_samples(graph, samples)?;
+    }
+    Ok(())
+}
+
+// Splits the samples into evenly sized buckets and keeps the lowest and highest value of each
+fn get_envelope(samples: &[f32], buckets: u32) -> Vec<EnvelopeBucket> {
+    let buckets = buckets as usize;
+    (0..buckets)
+        .map(|i| {
+            let bucket = &samples[i * samples.len() / buckets..(i + 1) * samples.len() / buckets];
+            match bucket.is_empty() {
+                true => EnvelopeBucket { min: 0.0, max: 0.0 },
+                false => EnvelopeBucket {
+                    min: bucket.iter().cloned().fold(f32::INFINITY, f32::min),
+                    max: bucket.iter().cloned().fold(f32::NEG_INFINITY, f32::max),
+                },
+            }
+        })
+        .collect()
+}
+
+pub fn get_audio_waveform(
+    input: &str,
+    start: f64,
+    duration: f64,
+    sample_rate: u32,
+    envelope_buckets: Option<u32>,
+) -> Result<AudioWaveform, ErrorWithBacktrace> {
+    if !start.is_finite() || start < 0.0 {
+        return Err(ErrorWithBacktrace::from(format!(
+            "start must be a non-negative number, but got {}",
+            start
+        )));
+    }
+    if !duration.is_finite() || duration <= 0.0 {
+        return Err(ErrorWithBacktrace::from(format!(
+            "duration must be a positive number, but got {}",
+            duration
+        )));
+    }
+    if sample_rate == 0 {
+        return Err(ErrorWithBacktrace::from("sample_rate must be positive"));
+    }
+    if envelope_buckets == Some(0) {
+        return Err(ErrorWithBacktrace::from(
+            "envelope_buckets must be positive",
+        ));
+    }
+
+    remotionffmpeg::init()?;
+    let mut ictx = format::input(&input)?;
+
+    let (stream_index, time_base, mut decoder) = match ictx.streams().best(media::Type::Audio) {
+        Some(stream) => {
+            let context = codec::context::Context::from_parameters(stream.parameters())?;
+            let mut decoder = context.decoder().audio()?;
+            decoder.set_parameters(stream.parameters())?;
+            (stream.index(), stream.time_base(), decoder)
+        }
+        // Not an error, a video without sound simply has no waveform
+        None => {
+            return Ok(AudioWaveform {
+                has_audio: false,
+                sample_rate,
+                samples: vec![],
+                envelope: envelope_buckets.map(|_| vec![]),
+            })
+        }
+    };
+
+    let mut graph = waveform_filter(&decoder, time_base, start, duration, sample_rate)?;
+
+    // Seeks to the keyframe before the window, atrim drops the samples before it
+    if start > 0.0 {
+        let position = (start * remotionffmpeg::ffi::AV_TIME_BASE as f64) as i64;
+        ictx.seek(position, ..position)?;
+    }
+
+    let end = start + duration;
+    let mut samples: Vec<f32> = vec![];
+    loop {
+        match ictx.get_next_packet() {
+            Ok((stream, packet)) => {
+                if stream.index() != stream_index {
+                    continue;
+                }
+                if let Some(pts) = packet.pts() {
+                    if pts as f64 * f64::from(time_base) > end {
+                        break;
+                    }
+                }
+                decoder.send_packet(&packet)?;
+                decode_frames(&mut decoder, &mut graph, &mut samples)?;
+            }
+            Err(remotionffmpeg::Error::Eof) => break,
+            Err(err) => Err(err)?,
+        }
+    }
+
+    decoder.send_eof()?;
+    decode_frames(&mut decoder, &mut graph, &mut samples)?;
+    graph
+        .get("in")
+        .ok_or_else(|| ErrorWithBacktrace::from("Could not get in filter"))?
+        .source()
+        .flush()?;
+    collect_filtered_samples(&mut graph, &mut samples)?;
+
+    Ok(match envelope_buckets {
+        Some(buckets) => AudioWaveform {
+            has_audio: true,
+            sample_rate,
+            samples: vec![],
+            envelope: Some(get_envelope(&samples, buckets)),
+        },
+        None => AudioWaveform {
+            has_audio: true,
+            sample_rate,
+            samples,
+            envelope: None,
+        },
+    })
+}
@@ -0,0 +1,55 @@
+use crate::{compositor::alpha_compositing, payloads::payloads::BlendMode};
+
+// Separable blend functions of the W3C compositing spec, on channels from 0 to 1.
+// `backdrop` is the canvas, `source` is the layer.
+fn blend_channel(mode: BlendMode, backdrop: f32, source: f32) -> f32 {
+    match mode {
+        BlendMode::Normal => source,
+        BlendMode::Multiply => backdrop * source,
+        BlendMode::Screen => backdrop + source - backdrop * source,
+        BlendMode::Overlay => match backdrop <= 0.5 {
+            true => 2.0 * backdrop * source,
+            false => 1.0 - 2.0 * (1.0 - backdrop) * (1.0 - source),
+        },
+        BlendMode::Add => (backdrop + source).min(1.0),
+    }
+}
+
+// Blends `source` over `backdrop`. Where the canvas is transparent, the layer
+// is drawn as is, and the blended color is mixed in according to the canvas alpha.
+// Normal goes through alpha_compositing() so its output stays the same.
+pub fn blend_pixel(backdrop: [u8; 4], source: [u8; 4], mode: BlendMode) -> [u8; 4] {
+    if mode == BlendMode::Normal {
+        let (r, g, b, a) = alpha_compositing(
+            backdrop[0],
+            backdrop[1],
+            backdrop[2],
+            backdrop[3],
+            source[0],
+            source[1],
+            source[2],
+            source[3],
+        );
+        return [r, g, b, a];
+    }
+
+    if source[3] == 0 {
+        return backdrop;
+    }
+
+    let source_alpha = source[3] as f32 / 255.0;
+    let backdrop_alpha = backdrop[3] as f32 / 255.0;
+    let alpha = source_alpha + backdrop_alpha * (1.0 - source_alpha);
+
+    let mut result = [0; 4];
+    for channel in 0..3 {
+        let cb = backdrop[channel] as f32 / 255.0;
+        let cs = source[channel] as f32 / 255.0;
+        let mixed = (1.0 - backdrop_alpha) * cs + backdrop_alpha * blend_channel(mode, cb, cs);
+        let color = (source_alpha * mixed + backdrop_alpha * (1.0 - source_alpha) * cb) / alpha;
+        result[channel] = (color * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+    result[3] = (alpha * 255.0).round().clamp(0.0, 255.0) as u8;
+
+    result
+}
@@ -0,0 +1,200 @@
+use rayon::prelude::*;
+
+use crate::payloads::payloads::BlurLayer;
+
+// Above this radius, three box blurs are used to approximate the Gaussian blur,
+// which is independent of the radius in speed
+const BOX_BLUR_THRESHOLD: u32 = 16;
+
+type Pixel = [f32; 4];
+
+// The kernel reaches `radius` pixels in both directions and covers 3 standard deviations
+fn get_sigma(radius: u32) -> f32 {
+    radius as f32 / 3.0
+}
+
+fn gaussian_kernel(radius: u32) -> Vec<f32> {
+    let sigma = get_sigma(radius);
+    let kernel: Vec<f32> = (-(radius as i64)..=radius as i64)
+        .map(|offset| (-((offset * offset) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+
+    kernel.iter().map(|weight| weight / sum).collect()
+}
+
+// Sizes of three box blurs whose combination approximates a Gaussian blur.
+// http://blog.ivank.net/fastest-gaussian-blur.html
+fn box_radii_for_gauss(sigma: f32) -> [usize; 3] {
+    let passes = 3.0;
+    let ideal_width = (12.0 * sigma * sigma / passes + 1.0).sqrt();
+    let mut lower_width = ideal_width.floor() as i64;
+    if lower_width % 2 == 0 {
+        lower_width -= 1;
+    }
+    let upper_width = lower_width + 2;
+
+    let lower = lower_width as f32;
+    let ideal_count =
+        (12.0 * sigma * sigma - passes * lower * lower - 4.0 * passes * lower - 3.0 * passes)
+            / (-4.0 * lower - 4.0);
+    let count = ideal_count.round() as usize;
+
+    let mut radii = [0; 3];
+    for (index, radius) in radii.iter_mut().enumerate() {
+        let width = match index < count {
+            true => lower_width,
+            false => upper_width,
+        };
+        *radius = ((width - 1) / 2).max(0) as usize;
+    }
+    radii
+}
+
+fn clamp_index(index: i64, len: usize) -> usize {
+    index.clamp(0, len as i64 - 1) as usize
+}
+
+fn gaussian_row(kernel: &[f32], row: &[Pixel], out: &mut [Pixel]) {
+    let radius = (kernel.len() / 2) as i64;
+    for (x, pixel) in out.iter_mut().enumerate() {
+        let mut sum = [0.0; 4];
+        for (offset, weight) in kernel.iter().enumerate() {
+            let source = &row[clamp_index(x as i64 + offset as i64 - radius, row.len())];
+            for channel in 0..4 {
+                sum[channel] += source[channel] * weight;
+            }
+        }
+        *pixel = sum;
+    }
+}
+
+// Sliding window, so the cost does not depend on the radius
+fn box_row(radius: usize, row: &[Pixel], out: &mut [Pixel]) {
+    let radius = radius as i64;
+    let scale = 1.0 / (2 * radius + 1) as f32;
+
+    let mut sum = [0.0; 4];
+    for offset in -radius..=radius {
+        let source = &row[clamp_index(offset, row.len())];
+        for channel in 0..4 {
+            sum[channel] += source[channel];
+        }
+    }
+
+    for (x, pixel) in out.iter_mut().enumerate() {
+        for channel in 0..4 {
+            pixel[channel] = sum[channel] * scale;
+        }
+
+        let entering = &row[clamp_index(x as i64 + radius + 1, row.len())];
+        let leaving = &row[clamp_index(x as i64 - radius, row.len())];
+        for channel in 0..4 {
+            sum[channel] += entering[channel] - leaving[channel];
+        }
+    }
+}
+
+fn transpose(data: &[Pixel], width: usize, height: usize) -> Vec<Pixel> {
+    let mut transposed = vec![[0.0; 4]; data.len()];
+    for y in 0..height {
+        for x in 0..width {
+            transposed[x * height + y] = data[y * width + x];
+        }
+    }
+    transposed
+}
+
+fn for_each_row<F>(data: &[Pixel], width: usize, row_operation: &F) -> Vec<Pixel>
+where
+    F: Fn(&[Pixel], &mut [Pixel]) + Sync,
+{
+    let mut out = vec![[0.0; 4]; data.len()];
+    out.par_chunks_mut(width)
+        .zip(data.par_chunks(width))
+        .for_each(|(out_row, row)| row_operation(row, out_row));
+    out
+}
+
+// Blurs the rows, then the columns
+fn separable_pass<F>(data: &[Pixel], width: usize, height: usize, row_operation: F) -> Vec<Pixel>
+where
+    F: Fn(&[Pixel], &mut [Pixel]) + Sync,
+{
+    let horizontal = for_each_row(data, width, &row_operation);
+    let vertical = for_each_row(
+        &transpose(&horizontal, width, height),
+        height,
+        &row_operation,
+    );
+    transpose(&vertical, height, width)
+}
+
+// Blurs the pixels that are already on the canvas within the rectangle of the layer.
+// Pixels outside of the rectangle are neither read nor written, the edges are extended.
+// Blurring happens on premultiplied values so transparent pixels don't darken the result.
+pub fn draw_blur_layer(img: &mut [u8], canvas_width: u32, canvas_height: u32, layer: &BlurLayer) {
+    let min_x = (layer.x as i64).clamp(0, canvas_width as i64) as usize;
+    let min_y = (layer.y as i64).clamp(0, canvas_height as i64) as usize;
+    let max_x = (layer.x as i64 + layer.width as i64).clamp(0, canvas_width as i64) as usize;
+    let max_y = (layer.y as i64 + layer.height as i64).clamp(0, canvas_height as i64) as usize;
+
+    if layer.radius == 0 || min_x >= max_x || min_y >= max_y {
+        return;
+    }
+
+    let width = max_x - min_x;
+    let height = max_y - min_y;
+    let canvas_width = canvas_width as usize;
+
+    let mut region: Vec<Pixel> = Vec::with_capacity(width * height);
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            let index = (y * canvas_width + x) * 4;
+            let alpha = img[index + 3] as f32;
+            region.push([
+                img[index] as f32 * alpha / 255.0,
+                img[index + 1] as f32 * alpha / 255.0,
+                img[index + 2] as f32 * alpha / 255.0,
+                alpha,
+            ]);
+        }
+    }
+
+    let blurred = match layer.radius > BOX_BLUR_THRESHOLD {
+        true => {
+            let mut blurred = region;
+            for radius in box_radii_for_gauss(get_sigma(layer.radius)) {
+                blurred = separable_pass(&blurred, width, height, |row, out| {
+                    box_row(radius, row, out)
+                });
+            }
+            blurred
+        }
+        false => {
+            let kernel = gaussian_kernel(layer.radius);
+            separable_pass(&region, width, height, |row, out| {
+                gaussian_row(&kernel, row, out)
+            })
+        }
+    };
+
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            let pixel = blurred[(y - min_y) * width + (x - min_x)];
+            let index = (y * canvas_width + x) * 4;
+            let alpha = pixel[3].round().clamp(0.0, 255.0);
+            if alpha == 0.0 {
+                img[index..index + 4].copy_from_slice(&[0, 0, 0, 0]);
+                continue;
+            }
+
+            for channel in 0..3 {
+                img[index + channel] = (pixel[channel] * 255.0 / pixel[3])
+                    .round()
+                    .clamp(0.0, 255.0) as u8;
+            }
+            img[index + 3] = alpha as u8;
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{
+    compositor::Bitmap,
+    payloads::payloads::{BlendMode, Border, BorderPosition},
+    shapes::{ellipse_coverage, rounded_rect_coverage},
+};
+
+// The outline that a border follows
+pub enum BorderShape {
+    // A corner radius of 0 gives sharp corners
+    RoundedRect(u32),
+    Ellipse,
+}
+
+// Coverage of the shape shrunk by `inset` on every side, or grown if it is negative.
+// Corners stay concentric, so a stroke along them has the same width everywhere.
+fn coverage(shape: &BorderShape, x: i64, y: i64, width: u32, height: u32, inset: i64) -> f32 {
+    let shape_width = width as i64 - 2 * inset;
+    let shape_height = height as i64 - 2 * inset;
+    if shape_width <= 0 || shape_height <= 0 {
+        return 0.0;
+    }
+
+    let (x, y) = (x - inset, y - inset);
+    let (shape_width, shape_height) = (shape_width as u32, shape_height as u32);
+    match shape {
+        BorderShape::RoundedRect(0) => rounded_rect_coverage(x, y, shape_width, shape_height, 0),
+        BorderShape::RoundedRect(radius) => rounded_rect_coverage(
+            x,
+            y,
+            shape_width,
+            shape_height,
+            (*radius as i64 - inset).max(0) as u32,
+        ),
+        BorderShape::Ellipse => ellipse_coverage(x, y, shape_width, shape_height),
+    }
+}
+
+// A bitmap with the stroke in the color of the border, drawn right after the layer.
+// The stroke is the difference between the shape and the shape shrunk (Inner) or
+// grown (Outer) by the border width, so it is anti-aliased like the shape itself.
+// For an Outer border, the bitmap is padded by the border width on each side.
+// Returns None for a border width of 0.
+pub fn prepare_border(
+    border: &Border,
+    shape: &BorderShape,
+    x: f32,
+    y: f32,
+    width: u32,
+    height: u32,
+    opacity: f32,
+    rotation: f32,
+    blend_mode: BlendMode,
+) -> Option<Bitmap> {
+    if border.width == 0 {
+        return None;
+    }
+
+    let stroke = border.width as i64;
+    let (padding, outer_inset, inner_inset) = match border.position {
+        BorderPosition::Inner => (0, 0, stroke),
+        BorderPosition::Outer => (border.width, -stroke, 0),
+    };
+    let border_width = width + 2 * padding;
+    let border_height = height + 2 * padding;
+    let color = border.color.0;
+
+    let mut data = vec![0; border_width as usize * border_height as usize * 4];
+    for (index, pixel) in data.chunks_exact_mut(4).enumerate() {
+        let local_x = (index % border_width as usize) as i64 - padding as i64;
+        let local_y = (index / border_width as usize) as i64 - padding as i64;
+        let outer = coverage(shape, local_x, local_y, width, height, outer_inset);
+        if outer == 0.0 {
+            continue;
+        }
+        let inner = coverage(shape, local_x, local_y, width, height, inner_inset);
+        let alpha = (outer - inner).max(0.0) * color[3] as f32;
+
+        pixel[..3].copy_from_slice(&color[..3]);
+        pixel[3] = alpha.round().clamp(0.0, 255.0) as u8;
+    }
+
+    Some(Bitmap {
+        x: x - padding as f32,
+        y: y - padding as f32,
+        width: border_width,
+        height: border_height,
+        data,
+        opacity,
+        rotation,
+        blend_mode,
+    })
+}
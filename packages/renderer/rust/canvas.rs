@@ -0,0 +1,70 @@
+use std::ops::Range;
+
+use crate::{
+    blend_mode::blend_pixel, compositor::alpha_compositing, payloads::payloads::BlendMode,
+};
+
+// A horizontal band of the canvas, covering the rows `y..y + height`.
+// Bands are drawn in parallel, so a layer may only touch the rows of its band.
+// All coordinates are canvas coordinates.
+pub struct Band<'a> {
+    pub data: &'a mut [u8],
+    pub canvas_width: u32,
+    pub y: u32,
+    pub height: u32,
+}
+
+impl<'a> Band<'a> {
+    // The rows between `from` and `to` that are inside of this band
+    pub fn rows(&self, from: i64, to: i64) -> Range<i64> {
+        from.max(self.y as i64)..to.min(self.y as i64 + self.height as i64)
+    }
+
+    // The columns between `from` and `to` that are inside of the canvas
+    pub fn columns(&self, from: i64, to: i64) -> Range<i64> {
+        from.max(0)..to.min(self.canvas_width as i64)
+    }
+
+    pub fn contains(&self, x: i64, y: i64) -> bool {
+        self.rows(y, y + 1).contains(&y) && self.columns(x, x + 1).contains(&x)
+    }
+
+    fn index(&self, x: i64, y: i64) -> usize {
+        (((y - self.y as i64) * self.canvas_width as i64 + x) * 4) as usize
+    }
+
+    // Blends the pixel over the canvas, the pixel must be inside of the band
+    pub fn blend(&mut self, x: i64, y: i64, pixel: [u8; 4]) {
+        let index = self.index(x, y);
+        let new_pixel = alpha_compositing(
+            self.data[index],
+            self.data[index + 1],
+            self.data[index + 2],
+            self.data[index + 3],
+            pixel[0],
+            pixel[1],
+            pixel[2],
+            pixel[3],
+        );
+
+        self.data[index] = new_pixel.0;
+        self.data[index + 1] = new_pixel.1;
+        self.data[index + 2] = new_pixel.2;
+        self.data[index + 3] = new_pixel.3;
+    }
+
+    pub fn blend_with_mode(&mut self, x: i64, y: i64, pixel: [u8; 4], mode: BlendMode) {
+        if mode == BlendMode::Normal {
+            return self.blend(x, y, pixel);
+        }
+
+        let index = self.index(x, y);
+        let backdrop = [
+            self.data[index],
+            self.data[index + 1],
+            self.data[index + 2],
+            self.data[index + 3],
+        ];
+        self.data[index..index + 4].copy_from_slice(&blend_pixel(backdrop, pixel, mode));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::asset_cache::AssetCache;
+use crate::commands::{execute_command, writes_to_stdout};
+use crate::errors::{self, error_to_json, ErrorCode, ErrorWithBacktrace};
+use crate::ffmpeg;
+use crate::global_printer::{self, _print_verbose, set_verbose_logging};
+use crate::memory::{get_ideal_maximum_frame_cache_size, is_about_to_run_out_of_memory};
+use std::env;
+
+use crate::payloads::payloads::{parse_cli, parse_nonce, CliInputCommand, CliInputCommandPayload};
+
+fn mainfn() -> Result<(), ErrorWithBacktrace> {
+    let args = env::args();
+
+    let first_arg = args
+        .skip(1)
+        .next()
+        .ok_or(ErrorWithBacktrace::with_code(ErrorCode::Parse, "No input"))?;
+
+    let opts: CliInputCommand = parse_init_command(&first_arg)?;
+
+    match opts.payload {
+        CliInputCommandPayload::StartLongRunningProcess(payload) => {
+            set_verbose_logging(payload.verbose);
+            errors::set_backtraces(payload.verbose);
+            if let Some(asset_cache_size) = payload.asset_cache_size_in_bytes {
+                AssetCache::get_instance().set_capacity(asset_cache_size)?;
+            }
+
+            let max_video_cache_size = payload
+                .maximum_frame_cache_size_in_bytes
+                .unwrap_or(get_ideal_maximum_frame_cache_size());
+
+            _print_verbose(&format!(
+                "Starting Rust process. Max video cache size: {}MB, max concurrency = {}",
+                max_video_cache_size / 1024 / 1024,
+                payload.concurrency
+            ))?;
+
+            start_long_running_process(payload.concurrency, max_video_cache_size)?;
+        }
+        _ => {
+            if writes_to_stdout(&opts.payload) {
+                // Errors still end up on stderr through handle_global_error()
+                execute_command(opts.payload, None)?;
+                return Ok(());
+            }
+
+            let data = execute_command(opts.payload, None)?;
+            global_printer::synchronized_write_buf(0, &opts.nonce, &data)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn parse_init_command(json: &str) -> Result<CliInputCommand, ErrorWithBacktrace> {
+    let cli_input: CliInputCommand = serde_json::from_str(json)?;
+
+    Ok(cli_input)
+}
+
+fn start_long_running_process(
+    threads: usize,
+    maximum_frame_cache_size_in_bytes: u128,
+) -> Result<(), ErrorWithBacktrace> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?;
+
+    loop {
+        let mut input = String::new();
+        let matched = match std::io::stdin().read_line(&mut input) {
+            Ok(0) => {
+                break;
+            }
+            Ok(_) => input,
+            Err(_) => {
+                break;
+            }
+        };
+
+        input = matched.trim().to_string();
+        if input == "EOF" {
+            break;
+        }
+        if input.is_empty() {
+            continue;
+        }
+
+        // A malformed command should not take down the other commands in the batch
+        let opts: CliInputCommand = match parse_cli(&input) {
+            Ok(opts) => opts,
+            Err(err) => {
+                global_printer::synchronized_write_buf(
+                    1,
+                    &parse_nonce(&input),
+                    &error_to_json(err)?.as_bytes(),
+                )?;
+                continue;
+            }
+        };
+
+        // Raw bytes would corrupt the framed responses of the other commands
+        if writes_to_stdout(&opts.payload) {
+            global_printer::synchronized_write_buf(
+                1,
+                &opts.nonce,
+                &error_to_json(ErrorWithBacktrace::from(
+                    "Writing the output to stdout (\"-\") is not supported in the long running process",
+                ))?
+                .as_bytes(),
+            )?;
+            continue;
+        }
+
+        let mut current_maximum_cache_size = maximum_frame_cache_size_in_bytes;
+
+        pool.install(move || {
+            match execute_command(opts.payload, Some(current_maximum_cache_size)) {
+                Ok(res) => global_printer::synchronized_write_buf(0, &opts.nonce, &res).unwrap(),
+                Err(err) => global_printer::synchronized_write_buf(
+                    1,
+                    &opts.nonce,
+                    &error_to_json(err).unwrap().as_bytes(),
+                )
+                .unwrap(),
+            };
+            if is_about_to_run_out_of_memory() {
+                ffmpeg::emergency_memory_free_up().unwrap();
+                AssetCache::get_instance().clear().unwrap();
+                current_maximum_cache_size = current_maximum_cache_size / 2;
+            }
+
+            ffmpeg::keep_only_latest_frames_and_close_videos(current_maximum_cache_size).unwrap();
+        });
+    }
+
+    Ok(())
+}
+
+// The binary only calls this, the commands are parsed from the arguments and stdin
+pub fn run() {
+    match mainfn() {
+        Ok(_) => (),
+        Err(err) => errors::handle_global_error(err),
+    }
+}
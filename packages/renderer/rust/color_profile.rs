@@ -0,0 +1,145 @@
+use std::{fs, io::Write};
+
+use flate2::{write::ZlibEncoder, Compression};
+
+use crate::{
+    errors::{ErrorCode, ErrorWithBacktrace},
+    payloads::payloads::ColorProfile,
+};
+
+// The profile that gets embedded into the output
+pub enum EmbeddedProfile {
+    Srgb,
+    // Verbatim contents of an .icc file
+    Icc(Vec<u8>),
+}
+
+impl EmbeddedProfile {
+    pub fn icc_bytes(&self) -> Vec<u8> {
+        match self {
+            EmbeddedProfile::Srgb => srgb_icc_profile(),
+            EmbeddedProfile::Icc(bytes) => bytes.clone(),
+        }
+    }
+}
+
+pub fn load_color_profile(profile: &ColorProfile) -> Result<EmbeddedProfile, ErrorWithBacktrace> {
+    let path = match profile {
+        ColorProfile::Srgb => return Ok(EmbeddedProfile::Srgb),
+        ColorProfile::Icc(path) => path,
+    };
+
+    let bytes = fs::read(path).map_err(|err| {
+        let code = match err.kind() {
+            std::io::ErrorKind::NotFound => ErrorCode::MissingInput,
+            _ => ErrorCode::Other,
+        };
+        ErrorWithBacktrace::with_code(
+            code,
+            format!("Could not read ICC profile {}: {}", path, err),
+        )
+    })?;
+
+    // Every ICC profile starts with a 128 byte header, with the signature at byte 36
+    if bytes.len() < 128 || &bytes[36..40] != b"acsp" {
+        return Err(ErrorWithBacktrace::from(format!(
+            "{} is not an ICC profile",
+            path
+        )));
+    }
+
+    Ok(EmbeddedProfile::Icc(bytes))
+}
+
+// The contents of a PNG iCCP chunk: a name, then the zlib compressed profile
+pub fn iccp_chunk(icc: &[u8]) -> Result<Vec<u8>, ErrorWithBacktrace> {
+    let mut encoder = ZlibEncoder::new(b"ICC profile\0\0".to_vec(), Compression::default());
+    encoder.write_all(icc)?;
+    Ok(encoder.finish()?)
+}
+
+fn s15_fixed16(value: f64) -> [u8; 4] {
+    ((value * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn xyz_tag(x: f64, y: f64, z: f64) -> Vec<u8> {
+    [
+        b"XYZ \0\0\0\0".as_slice(),
+        &s15_fixed16(x),
+        &s15_fixed16(y),
+        &s15_fixed16(z),
+    ]
+    .concat()
+}
+
+fn text_description_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"desc\0\0\0\0".to_vec();
+    tag.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    // No Unicode and no ScriptCode description
+    tag.extend_from_slice(&[0; 8]);
+    tag.extend_from_slice(&[0; 70]);
+    tag
+}
+
+// The sRGB transfer function, sampled at 1024 points
+fn srgb_curve_tag() -> Vec<u8> {
+    let points: u32 = 1024;
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    tag.extend_from_slice(&points.to_be_bytes());
+    for i in 0..points {
+        let value = i as f64 / (points - 1) as f64;
+        let linear = match value <= 0.04045 {
+            true => value / 12.92,
+            false => ((value + 0.055) / 1.055).powf(2.4),
+        };
+        tag.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+    }
+    tag
+}
+
+// A version 2 sRGB profile, built instead of bundled so that no binary blob
+// has to be checked in. The primaries are adapted to D50, like in the
+// profiles that ship with operating systems.
+pub fn srgb_icc_profile() -> Vec<u8> {
+    let curve = srgb_curve_tag();
+    let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"desc", text_description_tag("sRGB")),
+        (b"cprt", b"text\0\0\0\0No copyright, use freely\0".to_vec()),
+        (b"wtpt", xyz_tag(0.9642, 1.0, 0.8249)),
+        (b"rXYZ", xyz_tag(0.436066, 0.222488, 0.013916)),
+        (b"gXYZ", xyz_tag(0.385147, 0.716873, 0.097076)),
+        (b"bXYZ", xyz_tag(0.143066, 0.060608, 0.714096)),
+        (b"rTRC", curve.clone()),
+        (b"gTRC", curve.clone()),
+        (b"bTRC", curve),
+    ];
+
+    let mut table: Vec<u8> = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data: Vec<u8> = vec![];
+    let data_start = 128 + 4 + tags.len() * 12;
+    for (signature, tag) in &tags {
+        table.extend_from_slice(*signature);
+        table.extend_from_slice(&((data_start + data.len()) as u32).to_be_bytes());
+        table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        data.extend_from_slice(tag);
+        // Tags start at multiples of 4 bytes
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+    }
+
+    let size = data_start + data.len();
+    let mut header = vec![0; 128];
+    header[0..4].copy_from_slice(&(size as u32).to_be_bytes());
+    header[8..12].copy_from_slice(&[2, 0x10, 0, 0]);
+    header[12..16].copy_from_slice(b"mntr");
+    header[16..20].copy_from_slice(b"RGB ");
+    header[20..24].copy_from_slice(b"XYZ ");
+    header[36..40].copy_from_slice(b"acsp");
+    // D50 illuminant of the profile connection space
+    header[68..80].copy_from_slice(&xyz_tag(0.9642, 1.0, 0.8249)[8..]);
+
+    [header, table, data].concat()
+}
@@ -0,0 +1,434 @@
+use std::{sync::Mutex, time::Duration};
+
+use crate::{
+    anchor::resolve_anchors,
+    assets::DecodedAssets,
+    color_profile::load_color_profile,
+    compositor::{compose, get_clipping_warnings, ComposeOptions},
+    errors::{error_code, error_to_string, ErrorCode, ErrorWithBacktrace},
+    image::{
+        crop_image, encode_avif, encode_gif, encode_jpeg, encode_png, encode_webp, flatten,
+        validate_avif_speed, validate_bit_depth, validate_dpi, validate_quality, write_output,
+        RgbaImage, STDOUT_OUTPUT,
+    },
+    payloads::payloads::{
+        CliGenerateImageCommand, ComposeBatchCommand, ExportCrop, ImageFormat, Layer,
+        SuccessPayload,
+    },
+    scaling::scale_bilinear,
+    source::{describe_source, Downloads},
+};
+
+// The layers of every frame. Only GIFs can have more than one frame.
+fn get_frames(
+    command: &mut CliGenerateImageCommand,
+) -> Result<Vec<Vec<Layer>>, ErrorWithBacktrace> {
+    let frames = match command.frames.take() {
+        Some(frames) => frames,
+        None => return Ok(vec![std::mem::take(&mut command.layers)]),
+    };
+
+    if !command.layers.is_empty() {
+        return Err(ErrorWithBacktrace::from(
+            "Pass either `layers` or `frames`, but not both",
+        ));
+    }
+    if frames.is_empty() {
+        return Err(ErrorWithBacktrace::from(
+            "`frames` must contain at least one frame",
+        ));
+    }
+    match command.output_format {
+        ImageFormat::Gif => Ok(frames),
+        _ => Err(ErrorWithBacktrace::from(format!(
+            "`frames` is only supported for the Gif output format, but got {:?}",
+            command.output_format
+        ))),
+    }
+}
+
+fn get_warnings(width: u32, height: u32, frames: &[Vec<Layer>]) -> Vec<String> {
+    match frames {
+        [layers] => get_clipping_warnings(width, height, layers),
+        _ => frames
+            .iter()
+            .enumerate()
+            .flat_map(|(index, layers)| {
+                get_clipping_warnings(width, height, layers)
+                    .into_iter()
+                    .map(move |warning| format!("Frame {}: {}", index, warning))
+            })
+            .collect(),
+    }
+}
+
+// Without a base image, width and height are required. With one, its size is used
+// for what is omitted, keeping the aspect ratio if only one of them is given.
+fn get_canvas_size(
+    width: Option<u32>,
+    height: Option<u32>,
+    base_image_size: Option<(u32, u32)>,
+) -> Result<(u32, u32), ErrorWithBacktrace> {
+    let scale = |size: u32, from: u32, to: u32| {
+        ((size as u64 * to as u64 + from as u64 / 2) / (from as u64).max(1)) as u32
+    };
+
+    match (width, height, base_image_size) {
+        (Some(width), Some(height), _) => Ok((width, height)),
+        (None, None, Some(size)) => Ok(size),
+        (Some(width), None, Some((image_width, image_height))) => {
+            Ok((width, scale(image_height, image_width, width)))
+        }
+        (None, Some(height), Some((image_width, image_height))) => {
+            Ok((scale(image_width, image_height, height), height))
+        }
+        (_, _, None) => Err(ErrorWithBacktrace::from(
+            "width and height are required when there is no base_image",
+        )),
+    }
+}
+
+fn base_image_error(src: &str, err: ErrorWithBacktrace) -> ErrorWithBacktrace {
+    ErrorWithBacktrace::with_code(
+        error_code(&err),
+        format!(
+            "Could not load base_image {}: {}",
+            describe_source(src),
+            error_to_string(&err)
+        ),
+    )
+}
+
+fn get_limit<T: std::str::FromStr>(
+    value: Option<T>,
+    env_var: &str,
+) -> Result<Option<T>, ErrorWithBacktrace> {
+    if value.is_some() {
+        return Ok(value);
+    }
+    match std::env::var(env_var) {
+        Ok(env_value) => match env_value.trim().parse::<T>() {
+            Ok(limit) => Ok(Some(limit)),
+            Err(_) => Err(ErrorWithBacktrace::from(format!(
+                "{} must be a positive integer, but got {:?}",
+                env_var, env_value
+            ))),
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+// Large enough for photos from any camera, while an RGBA copy of such an
+// image still fits into memory
+const DEFAULT_MAX_SOURCE_PIXELS: u64 = 100_000_000;
+
+fn get_max_source_pixels(value: Option<u64>) -> Result<u64, ErrorWithBacktrace> {
+    Ok(get_limit(value, "REMOTION_COMPOSITOR_MAX_SOURCE_PIXELS")?
+        .unwrap_or(DEFAULT_MAX_SOURCE_PIXELS))
+}
+
+// Long videos in high quality still fit, while a wrong URL can not fill up the disk
+const DEFAULT_MAX_DOWNLOAD_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+
+fn get_max_download_bytes(value: Option<u64>) -> Result<u64, ErrorWithBacktrace> {
+    Ok(get_limit(value, "REMOTION_COMPOSITOR_MAX_DOWNLOAD_BYTES")?
+        .unwrap_or(DEFAULT_MAX_DOWNLOAD_BYTES))
+}
+
+fn validate_export_crop(
+    crop: &ExportCrop,
+    width: u32,
+    height: u32,
+) -> Result<(), ErrorWithBacktrace> {
+    if crop.width == 0 || crop.height == 0 {
+        return Err(ErrorWithBacktrace::from(format!(
+            "export_crop must have a positive width and height, but got {}x{}",
+            crop.width, crop.height
+        )));
+    }
+    if crop.x as u64 + crop.width as u64 > width as u64
+        || crop.y as u64 + crop.height as u64 > height as u64
+    {
+        return Err(ErrorWithBacktrace::from(format!(
+            "export_crop (x = {}, y = {}, width = {}, height = {}) does not fit into the canvas of {}x{}",
+            crop.x, crop.y, crop.width, crop.height, width, height
+        )));
+    }
+    Ok(())
+}
+
+// Also used for the sprite sheets of ExtractSpriteSheet
+pub fn check_max_pixels(
+    max_pixels: Option<u64>,
+    width: u32,
+    height: u32,
+) -> Result<(), ErrorWithBacktrace> {
+    let max_pixels = get_limit(max_pixels, "REMOTION_COMPOSITOR_MAX_PIXELS")?;
+    let pixels = width as u64 * height as u64;
+    match max_pixels.filter(|max_pixels| pixels > *max_pixels) {
+        Some(max_pixels) => Err(ErrorWithBacktrace::with_code(
+            ErrorCode::LimitExceeded,
+            format!(
+                "The canvas has {} pixels ({}x{}), which exceeds max_pixels of {}",
+                pixels, width, height, max_pixels
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+// Guards shared workers against payloads that would use up all memory.
+// Called before the canvas is allocated.
+fn check_limits(
+    command: &CliGenerateImageCommand,
+    width: u32,
+    height: u32,
+    frames: &[Vec<Layer>],
+) -> Result<(), ErrorWithBacktrace> {
+    check_max_pixels(command.max_pixels, width, height)?;
+    let max_layers = get_limit(command.max_layers, "REMOTION_COMPOSITOR_MAX_LAYERS")?;
+
+    let layers = frames.iter().map(|layers| layers.len()).max().unwrap_or(0);
+    if let Some(max_layers) = max_layers.filter(|max_layers| layers > *max_layers) {
+        return Err(ErrorWithBacktrace::with_code(
+            ErrorCode::LimitExceeded,
+            format!(
+                "The command has {} layers, which exceeds max_layers of {}",
+                layers, max_layers
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+// The encoded output of a Compose, before it is written anywhere
+pub struct EncodedImage {
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub warnings: Vec<String>,
+}
+
+// Everything a Compose does except for writing the output
+pub fn encode_compose(
+    mut command: CliGenerateImageCommand,
+    assets: &DecodedAssets,
+) -> Result<EncodedImage, ErrorWithBacktrace> {
+    let quality = match command.output_format {
+        ImageFormat::Png | ImageFormat::Gif => command.quality,
+        ImageFormat::Jpeg | ImageFormat::WebP | ImageFormat::Avif => {
+            validate_quality(command.quality)?
+        }
+    };
+
+    let dpi = validate_dpi(command.dpi)?;
+    let bit_depth = validate_bit_depth(command.bit_depth, &command.output_format)?;
+    let network_timeout = Duration::from_millis(command.network_timeout_ms);
+    let max_source_pixels = get_max_source_pixels(command.max_source_pixels)?;
+    let max_download_bytes = get_max_download_bytes(command.max_download_bytes)?;
+    let profile = match &command.color_profile {
+        Some(profile) => Some(load_color_profile(profile)?),
+        None => None,
+    };
+
+    // Only the header is read, the base image is decoded after the limits are checked
+    let base_image = match command.base_image.clone() {
+        Some(src) => {
+            let pending = assets
+                .read(&src, network_timeout, max_source_pixels)
+                .map_err(|err| base_image_error(&src, err))?;
+            Some((src, pending))
+        }
+        None => None,
+    };
+    let (width, height) = get_canvas_size(
+        command.width,
+        command.height,
+        base_image.as_ref().map(|(_, pending)| pending.size()),
+    )?;
+    let mut frames = get_frames(&mut command)?;
+    for layers in &mut frames {
+        resolve_anchors(width, height, layers)?;
+    }
+    check_limits(&command, width, height, &frames)?;
+    if let Some(crop) = &command.export_crop {
+        validate_export_crop(crop, width, height)?;
+    }
+
+    // Scaled to the canvas, so it can be drawn pixel by pixel
+    let base_image = match base_image {
+        Some((src, pending)) => {
+            let loaded = pending
+                .decode(&src, max_source_pixels)
+                .map_err(|err| base_image_error(&src, err))?;
+            let image = &loaded.image;
+            Some(match (image.width, image.height) == (width, height) {
+                true => image.data.clone(),
+                false => scale_bilinear(&image.data, image.width, image.height, width, height),
+            })
+        }
+        None => None,
+    };
+
+    let mut warnings = get_warnings(width, height, &frames);
+    let has_density = matches!(command.output_format, ImageFormat::Png | ImageFormat::Jpeg);
+    if dpi.is_some() && !has_density {
+        warnings.push(format!(
+            "dpi is ignored, because {:?} output has no density metadata",
+            command.output_format
+        ));
+    }
+    if profile.is_some() && !has_density {
+        warnings.push(format!(
+            "color_profile is ignored, because it can only be embedded into Png and Jpeg output, not {:?}",
+            command.output_format
+        ));
+    }
+
+    let background = command.background.0;
+    let downloads = Downloads::default();
+    let options = ComposeOptions {
+        background,
+        base_image,
+        network_timeout,
+        assets,
+        max_source_pixels,
+        downloads: &downloads,
+        max_download_bytes,
+        warnings: Mutex::new(vec![]),
+    };
+    let mut images = frames
+        .into_iter()
+        .map(|layers| compose(width, height, layers, &options))
+        .collect::<Result<Vec<Vec<u8>>, ErrorWithBacktrace>>()?;
+
+    // The layers were prepared in parallel, and every frame of a GIF repeats them
+    let mut prepare_warnings = options
+        .warnings
+        .into_inner()
+        .unwrap_or_else(|err| err.into_inner());
+    prepare_warnings.sort();
+    prepare_warnings.dedup();
+    warnings.extend(prepare_warnings);
+
+    // Only the window is encoded, the canvas is composed in full
+    let (width, height) = match &command.export_crop {
+        Some(crop) => {
+            images = images
+                .into_iter()
+                .map(|data| {
+                    let canvas = RgbaImage {
+                        width,
+                        height,
+                        data,
+                    };
+                    Ok(crop_image(&canvas, crop.x, crop.y, crop.width, crop.height)?.data)
+                })
+                .collect::<Result<Vec<Vec<u8>>, ErrorWithBacktrace>>()?;
+            (crop.width, crop.height)
+        }
+        None => (width, height),
+    };
+
+    let data = match command.output_format {
+        ImageFormat::Jpeg => {
+            let mut data = images.remove(0);
+            flatten(&mut data, [background[0], background[1], background[2]]);
+            encode_jpeg(
+                width,
+                height,
+                data,
+                quality,
+                dpi,
+                profile.as_ref(),
+                command.chroma_subsampling,
+            )?
+        }
+        ImageFormat::Png => encode_png(
+            width,
+            height,
+            images.remove(0),
+            dpi,
+            bit_depth,
+            profile.as_ref(),
+        )?,
+        ImageFormat::WebP => encode_webp(width, height, images.remove(0), quality)?,
+        ImageFormat::Avif => encode_avif(
+            width,
+            height,
+            images.remove(0),
+            quality,
+            validate_avif_speed(command.speed)?,
+        )?,
+        ImageFormat::Gif => encode_gif(width, height, images, command.delay_ms)?,
+    };
+
+    Ok(EncodedImage {
+        data,
+        width,
+        height,
+        warnings,
+    })
+}
+
+pub fn execute_compose(
+    command: CliGenerateImageCommand,
+    assets: &DecodedAssets,
+) -> Result<SuccessPayload, ErrorWithBacktrace> {
+    let output = command.output.clone();
+    let format = format!("{:?}", command.output_format);
+
+    // Encoded into memory first, so that no partial file is written if encoding fails
+    let image = encode_compose(command, assets)?;
+    let bytes_written = write_output(&output, &image.data)?;
+
+    Ok(SuccessPayload {
+        output,
+        width: image.width,
+        height: image.height,
+        format,
+        bytes_written,
+        frame_timestamp: None,
+        frame: None,
+        warnings: image.warnings,
+    })
+}
+
+// The assets are decoded once up front, then the outputs are composed one
+// after another, each of them in parallel like a single Compose
+pub fn execute_compose_batch(
+    command: ComposeBatchCommand,
+) -> Result<Vec<SuccessPayload>, ErrorWithBacktrace> {
+    if let Some(index) = command
+        .outputs
+        .iter()
+        .position(|output| output.output == STDOUT_OUTPUT)
+    {
+        return Err(ErrorWithBacktrace::from(format!(
+            "Output {} of the ComposeBatch writes to stdout, which is only supported by Compose",
+            index
+        )));
+    }
+
+    let network_timeout = Duration::from_millis(command.network_timeout_ms);
+    let assets = DecodedAssets::decode(
+        &command.assets,
+        network_timeout,
+        get_max_source_pixels(command.max_source_pixels)?,
+    )?;
+
+    command
+        .outputs
+        .into_iter()
+        .enumerate()
+        .map(|(index, output)| {
+            execute_compose(output, &assets).map_err(|err| {
+                ErrorWithBacktrace::with_code(
+                    error_code(&err),
+                    format!("Output {}: {}", index, error_to_string(&err)),
+                )
+            })
+        })
+        .collect()
+}
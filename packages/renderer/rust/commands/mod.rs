@@ -1,33 +1,105 @@
-use crate::compositor::draw_layer;
+mod compose;
+
+pub use compose::{check_max_pixels, encode_compose, EncodedImage};
+
+use crate::asset_cache::AssetCache;
+use crate::assets::DecodedAssets;
 use crate::copy_clipboard::copy_to_clipboard;
 use crate::errors::ErrorWithBacktrace;
-use crate::image::{save_as_jpeg, save_as_png};
+use crate::ffmpeg::ExtractedFrame;
+use crate::image::{get_frame_info, write_output, STDOUT_OUTPUT};
 use crate::opened_video_manager::OpenedVideoManager;
-use crate::payloads::payloads::CliInputCommandPayload;
-use crate::{ffmpeg, get_silent_parts};
+use crate::payloads::payloads::{CliInputCommandPayload, ExtractFrameCommand, SuccessPayload};
+use crate::{audio_waveform, ffmpeg, get_silent_parts, sprite_sheet};
+use compose::{execute_compose, execute_compose_batch};
 use std::io::ErrorKind;
 
+// Commands that write their result to stdout must not be followed by a response,
+// so that only the encoded bytes end up on stdout
+pub fn writes_to_stdout(opts: &CliInputCommandPayload) -> bool {
+    match opts {
+        CliInputCommandPayload::Compose(command) => command.output == STDOUT_OUTPUT,
+        CliInputCommandPayload::ExtractFrame(command) => {
+            command.output.as_deref() == Some(STDOUT_OUTPUT)
+        }
+        _ => false,
+    }
+}
+
+// Resolves `frame` and `fps` to a time, then decodes the frame at it
+pub fn extract_frame(
+    command: &ExtractFrameCommand,
+    maximum_frame_cache_size_in_bytes: Option<u128>,
+) -> Result<ExtractedFrame, ErrorWithBacktrace> {
+    let time = match (command.time, command.frame, command.fps) {
+        (None, Some(frame), Some(fps)) => ffmpeg::get_time_of_frame(&command.src, frame, fps)?,
+        (Some(time), None, None) => time,
+        _ => Err(ErrorWithBacktrace::from(
+            "ExtractFrame needs either `time`, or `frame` together with `fps`",
+        ))?,
+    };
+
+    ffmpeg::extract_frame_with_timestamp(
+        command.src.clone(),
+        command.original_src.clone(),
+        time,
+        command.transparent,
+        command.tone_mapped,
+        maximum_frame_cache_size_in_bytes,
+    )
+}
+
 pub fn execute_command(
     opts: CliInputCommandPayload,
     maximum_frame_cache_size_in_bytes: Option<u128>,
 ) -> Result<Vec<u8>, ErrorWithBacktrace> {
     match opts {
         CliInputCommandPayload::ExtractFrame(command) => {
-            let res = ffmpeg::extract_frame(
+            let res = extract_frame(&command, maximum_frame_cache_size_in_bytes)?;
+            match command.output {
+                Some(output) => {
+                    let (width, height, format) = get_frame_info(&res.data)?;
+                    let bytes_written = write_output(&output, &res.data)?;
+                    let str = serde_json::to_string(&SuccessPayload {
+                        output,
+                        width,
+                        height,
+                        format: format.to_string(),
+                        bytes_written,
+                        frame_timestamp: Some(res.timestamp),
+                        frame: command.frame,
+                        warnings: vec![],
+                    })?;
+                    Ok(str.as_bytes().to_vec())
+                }
+                // Without an output, the frame itself is the response
+                None => Ok(res.data),
+            }
+        }
+        CliInputCommandPayload::ExtractFrames(command) => {
+            let outputs = ffmpeg::extract_frames(
                 command.src,
                 command.original_src,
-                command.time,
+                command.times,
+                command.output_pattern,
                 command.transparent,
                 command.tone_mapped,
                 maximum_frame_cache_size_in_bytes,
+                command.progress,
             )?;
-            Ok(res)
+            let str = serde_json::to_string(&outputs)?;
+            Ok(str.as_bytes().to_vec())
         }
         CliInputCommandPayload::GetOpenVideoStats(_) => {
             let res = ffmpeg::get_open_video_stats()?;
             let str = serde_json::to_string(&res)?;
             Ok(str.as_bytes().to_vec())
         }
+        CliInputCommandPayload::GetAssetCacheStats(_) => {
+            let res = AssetCache::get_instance().get_stats()?;
+            let str = serde_json::to_string(&res)?;
+            Ok(str.as_bytes().to_vec())
+        }
         CliInputCommandPayload::DeliberatePanic(_) => {
             // For testing purposes
             let hi: Option<usize> = None;
@@ -66,38 +138,41 @@ pub fn execute_command(
             Ok(str.as_bytes().to_vec())
         }
         CliInputCommandPayload::Compose(compose_command) => {
-            let len: usize = (compose_command.width * compose_command.height).try_into()?;
-            let mut data: Vec<u8> = vec![0; len * 4];
-
-            for layer in compose_command.layers {
-                draw_layer(&mut data, compose_command.width, layer)?;
-            }
-
-            if matches!(
-                compose_command.output_format,
-                crate::payloads::payloads::ImageFormat::Jpeg
-            ) {
-                save_as_jpeg(
-                    compose_command.width,
-                    compose_command.height,
-                    data,
-                    compose_command.output,
-                )?;
-            } else {
-                save_as_png(
-                    compose_command.width,
-                    compose_command.height,
-                    data,
-                    compose_command.output,
-                )?;
-            }
-
-            Ok("".as_bytes().to_vec())
+            let str = serde_json::to_string(&execute_compose(
+                compose_command,
+                &DecodedAssets::default(),
+            )?)?;
+            Ok(str.as_bytes().to_vec())
+        }
+        CliInputCommandPayload::ComposeBatch(command) => {
+            let str = serde_json::to_string(&execute_compose_batch(command)?)?;
+            Ok(str.as_bytes().to_vec())
         }
         CliInputCommandPayload::CopyImageToClipboard(command) => copy_to_clipboard(command.src),
+        CliInputCommandPayload::Probe(command) => {
+            let res = ffmpeg::probe(&command.input)?;
+            let str = serde_json::to_string(&res)?;
+            Ok(str.as_bytes().to_vec())
+        }
         CliInputCommandPayload::ExtractAudio(_command) => {
             ffmpeg::extract_audio(&_command.input_path, &_command.output_path)?;
             Ok(vec![])
         }
+        CliInputCommandPayload::ExtractSpriteSheet(command) => {
+            let res = sprite_sheet::extract_sprite_sheet(command)?;
+            let str = serde_json::to_string(&res)?;
+            Ok(str.as_bytes().to_vec())
+        }
+        CliInputCommandPayload::GetAudioWaveform(command) => {
+            let res = audio_waveform::get_audio_waveform(
+                &command.input,
+                command.start,
+                command.duration,
+                command.sample_rate,
+                command.envelope_buckets,
+            )?;
+            let str = serde_json::to_string(&res)?;
+            Ok(str.as_bytes().to_vec())
+        }
     }
 }
@@ -1,8 +1,32 @@
-use std::{error::Error, fmt, fs::File};
+use std::{borrow::Cow, error::Error, fmt, sync::Mutex, time::Duration};
+
+use rayon::prelude::*;
 
 use crate::{
-    errors::ErrorWithBacktrace,
-    payloads::payloads::{ImageLayer, Layer, SolidLayer},
+    assets::DecodedAssets,
+    blur::draw_blur_layer,
+    border::{prepare_border, BorderShape},
+    canvas::Band,
+    errors::{error_code, error_to_string, ErrorCode, ErrorWithBacktrace},
+    ffmpeg,
+    fit::fit_image,
+    gradient::draw_gradient_layer,
+    image::{
+        adjust_image, bmp_to_rgba, crop_image, flip_image, format_name, is_identity_adjustment,
+        is_identity_tint, tint_image, RgbaImage,
+    },
+    layer_rotation::{draw_rotated_layer, draw_translated_layer, is_fractional, needs_rotation},
+    mask::{apply_mask, prepare_mask, Mask},
+    nine_slice::nine_slice_image,
+    payloads::payloads::{
+        BlendMode, BlurLayer, EllipseLayer, FitMode, GradientLayer, ImageLayer, Layer, MaskLayer,
+        SolidLayer, VideoLayer,
+    },
+    scaling::scale_bilinear,
+    shadow::prepare_shadow,
+    shapes::{ellipse_coverage, rounded_rect_coverage},
+    source::{describe_source, Downloads},
+    text::{rasterize_text_layer, TextPixel},
 };
 
 #[derive(Debug)]
@@ -16,34 +40,209 @@ impl fmt::Display for NoMetadataError {
 
 impl Error for NoMetadataError {}
 
-fn draw_solid_layer(img: &mut Vec<u8>, canvas_width: u32, layer: SolidLayer) {
-    for y in layer.y..(layer.height + layer.y) {
-        for x in layer.x..(layer.width + layer.x) {
-            let r_index = ((y * canvas_width + x) * 4) as usize;
-            let g_index = ((y * canvas_width + x) * 4 + 1) as usize;
-            let b_index = ((y * canvas_width + x) * 4 + 2) as usize;
-            let a_index = ((y * canvas_width + x) * 4 + 3) as usize;
-
-            let new_pixel = alpha_compositing(
-                img[r_index],
-                img[g_index],
-                img[b_index],
-                img[a_index],
-                layer.fill[0],
-                layer.fill[1],
-                layer.fill[2],
-                layer.fill[3],
+// An RGBA bitmap in the size of the layer that it is drawn at
+pub struct Bitmap {
+    // Fractional positions are drawn with subpixel sampling
+    pub x: f32,
+    pub y: f32,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+    pub opacity: f32,
+    pub rotation: f32,
+    pub blend_mode: BlendMode,
+}
+
+// A layer whose sources are decoded, so it can be drawn band by band
+pub enum PreparedLayer {
+    Solid(SolidLayer),
+    Ellipse(EllipseLayer),
+    Gradient(GradientLayer),
+    Bitmap(Bitmap),
+    // Sorted by row
+    Pixels(Vec<TextPixel>),
+    // Reads neighbouring pixels, so it can not be drawn band by band
+    Blur(BlurLayer),
+    // The shadow is drawn first, then the layer on top of it
+    Shadowed {
+        shadow: Bitmap,
+        layer: Box<PreparedLayer>,
+    },
+    Masked {
+        mask: Mask,
+        layer: Box<PreparedLayer>,
+    },
+    // The layer is drawn first, then the border on top of it
+    Bordered {
+        layer: Box<PreparedLayer>,
+        border: Bitmap,
+    },
+}
+
+fn with_border(border: Option<Bitmap>, layer: PreparedLayer) -> PreparedLayer {
+    match border {
+        Some(border) => PreparedLayer::Bordered {
+            layer: Box::new(layer),
+            border,
+        },
+        None => layer,
+    }
+}
+
+fn with_shadow(shadow: Option<Bitmap>, layer: PreparedLayer) -> PreparedLayer {
+    match shadow {
+        Some(shadow) => PreparedLayer::Shadowed {
+            shadow,
+            layer: Box::new(layer),
+        },
+        None => layer,
+    }
+}
+
+pub struct ComposeOptions<'a> {
+    // Fills the canvas before the first layer is drawn
+    pub background: [u8; 4],
+    // RGBA pixels in the size of the canvas, drawn over the background
+    pub base_image: Option<Vec<u8>>,
+    // Deadline for downloading each http(s) source
+    pub network_timeout: Duration,
+    // Decoded images shared between the outputs of a ComposeBatch
+    pub assets: &'a DecodedAssets,
+    // Sources whose header declares more pixels are not decoded
+    pub max_source_pixels: u64,
+    // Video URLs downloaded by this command, deleted once it is done
+    pub downloads: &'a Downloads,
+    pub max_download_bytes: u64,
+    // Collected while the layers are prepared in parallel
+    pub warnings: Mutex<Vec<String>>,
+}
+
+// Opacity values outside of 0-1 are clamped instead of rejected
+fn clamp_opacity(opacity: f32) -> f32 {
+    match opacity.is_nan() {
+        true => 1.0,
+        false => opacity.clamp(0.0, 1.0),
+    }
+}
+
+fn apply_opacity(alpha: u8, opacity: f32) -> u8 {
+    if opacity >= 1.0 {
+        return alpha;
+    }
+    (alpha as f32 * opacity).round() as u8
+}
+
+// Pixels outside of the band are skipped
+fn draw_solid_layer(band: &mut Band, layer: &SolidLayer) {
+    let opacity = clamp_opacity(layer.opacity);
+    if opacity == 0.0 {
+        return;
+    }
+
+    let rotated = needs_rotation(layer.rotation);
+    if rotated || is_fractional(layer.x, layer.y) {
+        let fill = layer.fill.0;
+        let width = layer.width as i64;
+        let height = layer.height as i64;
+        let corner_radius = layer.corner_radius;
+        let sample = |x: i64, y: i64| match x >= 0 && y >= 0 && x < width && y < height {
+            true if corner_radius > 0 => {
+                let coverage =
+                    rounded_rect_coverage(x, y, width as u32, height as u32, corner_radius);
+                [fill[0], fill[1], fill[2], apply_opacity(fill[3], coverage)]
+            }
+            true => fill,
+            false => [0, 0, 0, 0],
+        };
+
+        match rotated {
+            true => draw_rotated_layer(
+                band,
+                layer.x,
+                layer.y,
+                layer.width,
+                layer.height,
+                layer.rotation,
+                opacity,
+                layer.blend_mode,
+                sample,
+            ),
+            false => draw_translated_layer(
+                band,
+                layer.x,
+                layer.y,
+                layer.width,
+                layer.height,
+                opacity,
+                layer.blend_mode,
+                sample,
+            ),
+        }
+        return;
+    }
+
+    let fill_alpha = apply_opacity(layer.fill.0[3], opacity);
+    let x = layer.x as i64;
+    let y = layer.y as i64;
+
+    for canvas_y in band.rows(y, y + layer.height as i64) {
+        for canvas_x in band.columns(x, x + layer.width as i64) {
+            let alpha = match layer.corner_radius > 0 {
+                true => {
+                    let coverage = rounded_rect_coverage(
+                        canvas_x - x,
+                        canvas_y - y,
+                        layer.width,
+                        layer.height,
+                        layer.corner_radius,
+                    );
+                    apply_opacity(layer.fill.0[3], coverage * opacity)
+                }
+                false => fill_alpha,
+            };
+
+            band.blend_with_mode(
+                canvas_x,
+                canvas_y,
+                [layer.fill.0[0], layer.fill.0[1], layer.fill.0[2], alpha],
+                layer.blend_mode,
             );
+        }
+    }
+}
+
+// Pixels outside of the band are skipped
+fn draw_ellipse_layer(band: &mut Band, layer: &EllipseLayer) {
+    let opacity = clamp_opacity(layer.opacity);
+    if opacity == 0.0 {
+        return;
+    }
 
-            img[r_index] = new_pixel.0;
-            img[g_index] = new_pixel.1;
-            img[b_index] = new_pixel.2;
-            img[a_index] = new_pixel.3;
+    let x = layer.x as i64;
+    let y = layer.y as i64;
+
+    for canvas_y in band.rows(y, y + layer.height as i64) {
+        for canvas_x in band.columns(x, x + layer.width as i64) {
+            let coverage = ellipse_coverage(canvas_x - x, canvas_y - y, layer.width, layer.height);
+            if coverage == 0.0 {
+                continue;
+            }
+
+            band.blend(
+                canvas_x,
+                canvas_y,
+                [
+                    layer.fill.0[0],
+                    layer.fill.0[1],
+                    layer.fill.0[2],
+                    apply_opacity(layer.fill.0[3], coverage * opacity),
+                ],
+            );
         }
     }
 }
 
-fn alpha_compositing(
+pub fn alpha_compositing(
     prev_r: u8,
     prev_g: u8,
     prev_b: u8,
@@ -76,103 +275,660 @@ fn alpha_compositing(
     return (r, g, b, a);
 }
 
-fn draw_png_image_layer(
-    img: &mut Vec<u8>,
-    canvas_width: u32,
+// Applies the crop rectangle of the layer. Omitted values fall back to the
+// whole image, and the rectangle must be inside of the source image.
+fn crop_image_layer<'a>(
+    image: &'a RgbaImage,
+    layer: &ImageLayer,
+) -> Result<Cow<'a, RgbaImage>, ErrorWithBacktrace> {
+    if layer.crop_x.is_none()
+        && layer.crop_y.is_none()
+        && layer.crop_width.is_none()
+        && layer.crop_height.is_none()
+    {
+        return Ok(Cow::Borrowed(image));
+    }
+
+    let crop_x = layer.crop_x.unwrap_or(0);
+    let crop_y = layer.crop_y.unwrap_or(0);
+    let crop_width = layer
+        .crop_width
+        .unwrap_or(image.width.saturating_sub(crop_x));
+    let crop_height = layer
+        .crop_height
+        .unwrap_or(image.height.saturating_sub(crop_y));
+
+    crop_image(image, crop_x, crop_y, crop_width, crop_height)
+        .map(Cow::Owned)
+        .map_err(|err| {
+            ErrorWithBacktrace::from(format!(
+                "Invalid crop for {}: {}",
+                describe_source(&layer.src),
+                error_to_string(&err)
+            ))
+        })
+}
+
+// Mislabeled files are common, so the format is detected from the data and
+// `declared_type` only decides whether a warning is emitted
+fn prepare_image_layer(
     layer: ImageLayer,
-) -> Result<(), ErrorWithBacktrace> {
-    let file = File::open(layer.src)?;
-
-    let decoder = png::Decoder::new(file);
-    let mut reader = decoder.read_info()?;
-
-    let size = reader.output_buffer_size();
-    let mut buf = vec![0; size];
-    let info = reader.next_frame(&mut buf)?;
-
-    let bytes = &buf[..info.buffer_size()];
-    for y in 0..(layer.height) {
-        for x in 0..(layer.width) {
-            let r = bytes[((y * layer.width + x) * 4) as usize];
-            let g = bytes[((y * layer.width + x) * 4 + 1) as usize];
-            let b = bytes[((y * layer.width + x) * 4 + 2) as usize];
-            let a = bytes[((y * layer.width + x) * 4 + 3) as usize];
-
-            let r_index = (((y + layer.y) * canvas_width + (x + layer.x)) * 4) as usize;
-            let g_index = (((y + layer.y) * canvas_width + (x + layer.x)) * 4 + 1) as usize;
-            let b_index = (((y + layer.y) * canvas_width + (x + layer.x)) * 4 + 2) as usize;
-            let a_index = (((y + layer.y) * canvas_width + (x + layer.x)) * 4 + 3) as usize;
-
-            let new_pixel = alpha_compositing(
-                img[r_index],
-                img[g_index],
-                img[b_index],
-                img[a_index],
-                r,
-                g,
-                b,
-                a,
-            );
+    declared_type: &str,
+    options: &ComposeOptions,
+) -> Result<Option<PreparedLayer>, ErrorWithBacktrace> {
+    let opacity = clamp_opacity(layer.opacity);
+    if opacity == 0.0 {
+        return Ok(None);
+    }
+
+    let loaded = options.assets.get_or_decode(
+        &layer.src,
+        options.network_timeout,
+        options.max_source_pixels,
+    )?;
+    if let Some(actual) = loaded.mime_type.filter(|actual| *actual != declared_type) {
+        options.warnings.lock()?.push(format!(
+            "{} is declared as {}, but was decoded as {}, which is its actual format",
+            describe_source(&layer.src),
+            format_name(declared_type),
+            format_name(actual)
+        ));
+    }
+    // The decoded source is shared, it is only copied if it has to be changed
+    let mut source = crop_image_layer(&loaded.image, &layer)?;
+    if layer.flip_h || layer.flip_v {
+        flip_image(source.to_mut(), layer.flip_h, layer.flip_v);
+    }
+    // Before the tint, so that a grayscale source can be tinted into one color
+    if !is_identity_adjustment(layer.grayscale, layer.brightness, layer.contrast) {
+        adjust_image(
+            source.to_mut(),
+            layer.grayscale,
+            layer.brightness,
+            layer.contrast,
+        );
+    }
+    if let Some(tint) = layer.tint.filter(|tint| !is_identity_tint(tint.0)) {
+        tint_image(source.to_mut(), tint.0);
+    }
 
-            img[r_index] = new_pixel.0;
-            img[g_index] = new_pixel.1;
-            img[b_index] = new_pixel.2;
-            img[a_index] = new_pixel.3;
+    // The source is scaled to the size of the layer
+    let scaled = match (&layer.nine_slice, layer.fit) {
+        (None, fit) => fit_image(
+            &source,
+            fit,
+            &layer.scaling,
+            layer.pad_color.map_or([0, 0, 0, 0], |color| color.0),
+            layer.width,
+            layer.height,
+        )?,
+        (Some(slice), FitMode::Stretch) => {
+            nine_slice_image(&source, slice, &layer.scaling, layer.width, layer.height)?
         }
+        (Some(_), fit) => Err(format!(
+            "nine_slice can only be used with fit = Stretch, but got {:?}",
+            fit
+        ))?,
+    };
+
+    let shadow = layer.shadow.as_ref().map(|shadow| {
+        prepare_shadow(
+            shadow,
+            layer.x,
+            layer.y,
+            layer.width,
+            layer.height,
+            layer.rotation,
+            |x, y| scaled[((y * layer.width + x) * 4 + 3) as usize] as f32 * opacity,
+        )
+    });
+
+    let border = layer.border.as_ref().and_then(|border| {
+        prepare_border(
+            border,
+            &BorderShape::RoundedRect(0),
+            layer.x,
+            layer.y,
+            layer.width,
+            layer.height,
+            opacity,
+            layer.rotation,
+            layer.blend_mode,
+        )
+    });
+
+    let bitmap = PreparedLayer::Bitmap(Bitmap {
+        x: layer.x,
+        y: layer.y,
+        width: layer.width,
+        height: layer.height,
+        data: scaled,
+        opacity,
+        rotation: layer.rotation,
+        blend_mode: layer.blend_mode,
+    });
+
+    Ok(Some(with_shadow(shadow, with_border(border, bitmap))))
+}
+
+// The shadow follows the rounded corners and the alpha of the fill
+fn prepare_solid_layer(layer: SolidLayer) -> PreparedLayer {
+    let opacity = clamp_opacity(layer.opacity);
+    let shadow = layer.shadow.as_ref().map(|shadow| {
+        prepare_shadow(
+            shadow,
+            layer.x,
+            layer.y,
+            layer.width,
+            layer.height,
+            layer.rotation,
+            |x, y| {
+                let coverage = match layer.corner_radius > 0 {
+                    true => rounded_rect_coverage(
+                        x as i64,
+                        y as i64,
+                        layer.width,
+                        layer.height,
+                        layer.corner_radius,
+                    ),
+                    false => 1.0,
+                };
+                layer.fill.0[3] as f32 * coverage * opacity
+            },
+        )
+    });
+
+    let border = layer.border.as_ref().and_then(|border| {
+        prepare_border(
+            border,
+            &BorderShape::RoundedRect(layer.corner_radius),
+            layer.x,
+            layer.y,
+            layer.width,
+            layer.height,
+            opacity,
+            layer.rotation,
+            layer.blend_mode,
+        )
+    });
+
+    with_shadow(shadow, with_border(border, PreparedLayer::Solid(layer)))
+}
+
+fn prepare_ellipse_layer(layer: EllipseLayer) -> PreparedLayer {
+    let border = layer.border.as_ref().and_then(|border| {
+        prepare_border(
+            border,
+            &BorderShape::Ellipse,
+            layer.x as f32,
+            layer.y as f32,
+            layer.width,
+            layer.height,
+            clamp_opacity(layer.opacity),
+            0.0,
+            BlendMode::Normal,
+        )
+    });
+
+    with_border(border, PreparedLayer::Ellipse(layer))
+}
+
+fn prepare_video_layer(
+    layer: VideoLayer,
+    options: &ComposeOptions,
+) -> Result<Option<PreparedLayer>, ErrorWithBacktrace> {
+    let opacity = clamp_opacity(layer.opacity);
+    if opacity == 0.0 {
+        return Ok(None);
+    }
+
+    let src = options.downloads.get_local_path(
+        &layer.src,
+        options.network_timeout,
+        options.max_download_bytes,
+    )?;
+
+    // Times after the end of the video resolve to the last frame
+    let frame = ffmpeg::extract_frame(
+        src,
+        layer.src.clone(),
+        layer.time.max(0.0),
+        false,
+        true,
+        None,
+    )
+    .map_err(|err| {
+        format!(
+            "Could not extract frame at time {} from video layer {}: {}",
+            layer.time,
+            layer.src,
+            error_to_string(&err)
+        )
+    })?;
+
+    let (frame_width, frame_height, rgba) = bmp_to_rgba(&frame)?;
+    let scaled = scale_bilinear(&rgba, frame_width, frame_height, layer.width, layer.height);
+
+    Ok(Some(PreparedLayer::Bitmap(Bitmap {
+        x: layer.x as f32,
+        y: layer.y as f32,
+        width: layer.width,
+        height: layer.height,
+        data: scaled,
+        opacity,
+        rotation: layer.rotation,
+        blend_mode: layer.blend_mode,
+    })))
+}
+
+// Decodes the sources of the layer. Nothing is drawn yet.
+fn prepare_layer(
+    layer: Layer,
+    options: &ComposeOptions,
+) -> Result<Option<PreparedLayer>, ErrorWithBacktrace> {
+    match layer {
+        Layer::PngImage(layer) => prepare_image_layer(layer, "image/png", options),
+        Layer::JpgImage(layer) => prepare_image_layer(layer, "image/jpeg", options),
+        Layer::Solid(layer) => Ok(Some(prepare_solid_layer(layer))),
+        Layer::Text(layer) => Ok(Some(PreparedLayer::Pixels(rasterize_text_layer(layer)?))),
+        Layer::Gradient(layer) => Ok(Some(PreparedLayer::Gradient(layer))),
+        Layer::Video(layer) => prepare_video_layer(layer, options),
+        Layer::Blur(layer) => Ok(Some(PreparedLayer::Blur(layer))),
+        Layer::Ellipse(layer) => Ok(Some(prepare_ellipse_layer(layer))),
+        // Attached to the next layer by attach_masks()
+        Layer::Mask(_) => Ok(None),
     }
-    Ok(())
 }
 
-fn draw_jpg_image_layer(
-    img: &mut Vec<u8>,
-    canvas_width: u32,
-    layer: ImageLayer,
-) -> Result<(), ErrorWithBacktrace> {
-    let file = File::open(layer.src)?;
+// A mask, together with the rectangle and rotation of the layer that it applies to
+type PendingMask = (MaskLayer, (f32, f32, u32, u32, f32));
+
+fn get_z_index(layer: &Layer) -> Option<i32> {
+    match layer {
+        Layer::PngImage(layer) | Layer::JpgImage(layer) => layer.z_index,
+        Layer::Solid(layer) => layer.z_index,
+        Layer::Text(layer) => layer.z_index,
+        Layer::Gradient(layer) => layer.z_index,
+        Layer::Video(layer) => layer.z_index,
+        Layer::Blur(layer) => layer.z_index,
+        Layer::Ellipse(layer) => layer.z_index,
+        Layer::Mask(layer) => layer.z_index,
+    }
+}
+
+// Layers are painted from the lowest to the highest z_index, layers without
+// one count as 0. Layers with the same z_index keep their order in the array,
+// and if no layer has a z_index, the array order is used as is.
+// A Mask applies to the layer that is painted after it in this order.
+// The layers keep their index in the array, so that errors can point to them.
+fn sort_by_z_index(layers: Vec<Layer>) -> Vec<(usize, Layer)> {
+    let mut indexed: Vec<(usize, Layer)> = layers.into_iter().enumerate().collect();
+    if indexed
+        .iter()
+        .any(|(_, layer)| get_z_index(layer).is_some())
+    {
+        // Stable, so equal z_index values stay in array order
+        indexed.sort_by_key(|(_, layer)| get_z_index(layer).unwrap_or(0));
+    }
+    indexed
+}
+
+// Only these layers can be rotated
+fn get_layer_rotation(layer: &Layer) -> f32 {
+    match layer {
+        Layer::PngImage(layer) | Layer::JpgImage(layer) => layer.rotation,
+        Layer::Solid(layer) => layer.rotation,
+        Layer::Video(layer) => layer.rotation,
+        _ => 0.0,
+    }
+}
+
+// Every Mask is attached to the layer after it
+fn attach_masks(
+    layers: Vec<(usize, Layer)>,
+) -> Result<Vec<(usize, Option<PendingMask>, Layer)>, ErrorWithBacktrace> {
+    let mut attached = vec![];
+    let mut pending: Option<MaskLayer> = None;
+
+    for (index, layer) in layers {
+        let (mask, layer) = match (pending.take(), layer) {
+            (None, Layer::Mask(mask)) => {
+                pending = Some(mask);
+                continue;
+            }
+            (Some(_), Layer::Mask(_)) => Err(format!(
+                "Layer {} is a Mask, but the layer before it is already a Mask",
+                index
+            ))?,
+            (mask, layer) => (mask, layer),
+        };
+
+        let mask = match mask {
+            Some(mask) => match (&layer, get_layer_rect(&layer)) {
+                (Layer::Blur(_), _) | (_, None) => Err(format!(
+                    "Layer {} can not be masked, only layers with a size except Blur can",
+                    index
+                ))?,
+                (_, Some((_, x, y, width, height))) => {
+                    let rotation = get_layer_rotation(&layer);
+                    Some((mask, (x as f32, y as f32, width, height, rotation)))
+                }
+            },
+            None => None,
+        };
+        attached.push((index, mask, layer));
+    }
+
+    if pending.is_some() {
+        return Err(ErrorWithBacktrace::from(
+            "The last layer is a Mask, but there is no layer after it to apply it to",
+        ));
+    }
 
-    let mut decoder = jpeg_decoder::Decoder::new(file);
+    Ok(attached)
+}
 
-    let pixels = decoder.decode()?;
+fn prepare_masked_layer(
+    mask: Option<PendingMask>,
+    layer: Layer,
+    options: &ComposeOptions,
+) -> Result<Option<PreparedLayer>, ErrorWithBacktrace> {
+    let (mask, (x, y, width, height, rotation)) = match mask {
+        Some(mask) => mask,
+        None => return prepare_layer(layer, options),
+    };
 
-    for y in 0..(layer.height) {
-        for x in 0..(layer.width) {
-            let r_index = (((y + layer.y) * canvas_width + (x + layer.x)) * 4) as usize;
-            let g_index = (((y + layer.y) * canvas_width + (x + layer.x)) * 4 + 1) as usize;
-            let b_index = (((y + layer.y) * canvas_width + (x + layer.x)) * 4 + 2) as usize;
-            let a_index = (((y + layer.y) * canvas_width + (x + layer.x)) * 4 + 3) as usize;
+    let mask = prepare_mask(&mask, x, y, width, height, rotation, options)?;
+    Ok(
+        prepare_layer(layer, options)?.map(|layer| PreparedLayer::Masked {
+            mask,
+            layer: Box::new(layer),
+        }),
+    )
+}
 
-            let prev_r = img[r_index];
-            let prev_g = img[g_index];
-            let prev_b = img[b_index];
-            let prev_a = img[a_index];
+fn draw_bitmap(band: &mut Band, bitmap: &Bitmap) {
+    let rotated = needs_rotation(bitmap.rotation);
+    if rotated || is_fractional(bitmap.x, bitmap.y) {
+        let width = bitmap.width as i64;
+        let height = bitmap.height as i64;
+        let sample = |x: i64, y: i64| {
+            if x < 0 || y < 0 || x >= width || y >= height {
+                return [0, 0, 0, 0];
+            }
+            let index = ((y * width + x) * 4) as usize;
+            [
+                bitmap.data[index],
+                bitmap.data[index + 1],
+                bitmap.data[index + 2],
+                bitmap.data[index + 3],
+            ]
+        };
 
-            let layer_r_index = ((y * layer.width + x) * 3) as usize;
-            let layer_g_index = ((y * layer.width + x) * 3 + 1) as usize;
-            let layer_b_index = ((y * layer.width + x) * 3 + 2) as usize;
+        match rotated {
+            true => draw_rotated_layer(
+                band,
+                bitmap.x,
+                bitmap.y,
+                bitmap.width,
+                bitmap.height,
+                bitmap.rotation,
+                bitmap.opacity,
+                bitmap.blend_mode,
+                sample,
+            ),
+            false => draw_translated_layer(
+                band,
+                bitmap.x,
+                bitmap.y,
+                bitmap.width,
+                bitmap.height,
+                bitmap.opacity,
+                bitmap.blend_mode,
+                sample,
+            ),
+        }
+        return;
+    }
 
-            let r = pixels[layer_r_index];
-            let g = pixels[layer_g_index];
-            let b = pixels[layer_b_index];
+    let x = bitmap.x as i64;
+    let y = bitmap.y as i64;
 
-            let new_pixel = alpha_compositing(prev_r, prev_g, prev_b, prev_a, r, g, b, 255);
+    for canvas_y in band.rows(y, y + bitmap.height as i64) {
+        for canvas_x in band.columns(x, x + bitmap.width as i64) {
+            let index = (((canvas_y - y) * bitmap.width as i64 + (canvas_x - x)) * 4) as usize;
 
-            img[r_index] = new_pixel.0;
-            img[g_index] = new_pixel.1;
-            img[b_index] = new_pixel.2;
-            img[a_index] = new_pixel.3;
+            band.blend_with_mode(
+                canvas_x,
+                canvas_y,
+                [
+                    bitmap.data[index],
+                    bitmap.data[index + 1],
+                    bitmap.data[index + 2],
+                    apply_opacity(bitmap.data[index + 3], bitmap.opacity),
+                ],
+                bitmap.blend_mode,
+            );
         }
     }
-    Ok(())
 }
 
-pub fn draw_layer(
-    img: &mut Vec<u8>,
-    canvas_width: u32,
-    layer: Layer,
-) -> Result<(), ErrorWithBacktrace> {
+fn draw_pixels(band: &mut Band, pixels: &[TextPixel]) {
+    let start = pixels.partition_point(|pixel| pixel.y < band.y as i64);
+    let end = pixels.partition_point(|pixel| pixel.y < band.y as i64 + band.height as i64);
+
+    for pixel in &pixels[start..end] {
+        if band.contains(pixel.x, pixel.y) {
+            band.blend(pixel.x, pixel.y, pixel.color);
+        }
+    }
+}
+
+fn draw_prepared_layer(band: &mut Band, layer: &PreparedLayer) {
+    match layer {
+        PreparedLayer::Solid(layer) => draw_solid_layer(band, layer),
+        PreparedLayer::Ellipse(layer) => draw_ellipse_layer(band, layer),
+        PreparedLayer::Gradient(layer) => draw_gradient_layer(band, layer),
+        PreparedLayer::Bitmap(bitmap) => draw_bitmap(band, bitmap),
+        PreparedLayer::Pixels(pixels) => draw_pixels(band, pixels),
+        // Applied to the whole canvas in compose()
+        PreparedLayer::Blur(_) => {}
+        PreparedLayer::Shadowed { shadow, layer } => {
+            draw_bitmap(band, shadow);
+            draw_prepared_layer(band, layer);
+        }
+        PreparedLayer::Masked { mask, layer } => {
+            let before = band.data.to_vec();
+            draw_prepared_layer(band, layer);
+            apply_mask(band, &before, mask);
+        }
+        PreparedLayer::Bordered { layer, border } => {
+            draw_prepared_layer(band, layer);
+            draw_bitmap(band, border);
+        }
+    }
+}
+
+// Enough bands for every thread to get a few, so that an uneven
+// distribution of the layers still keeps all threads busy
+fn get_band_height(height: u32) -> u32 {
+    (height / (rayon::current_num_threads() as u32 * 4)).max(1)
+}
+
+fn draw_in_bands(data: &mut [u8], width: u32, height: u32, layers: &[Option<PreparedLayer>]) {
+    let band_height = get_band_height(height);
+    let row_size = (width * 4) as usize;
+
+    data.par_chunks_mut(band_height as usize * row_size)
+        .enumerate()
+        .for_each(|(index, chunk)| {
+            let mut band = Band {
+                canvas_width: width,
+                y: index as u32 * band_height,
+                height: (chunk.len() / row_size) as u32,
+                data: chunk,
+            };
+
+            for layer in layers.iter().flatten() {
+                draw_prepared_layer(&mut band, layer);
+            }
+        });
+}
+
+fn get_layer_rect(layer: &Layer) -> Option<(&'static str, f64, f64, u32, u32)> {
     match layer {
-        Layer::PngImage(layer) => draw_png_image_layer(img, canvas_width, layer),
-        Layer::JpgImage(layer) => draw_jpg_image_layer(img, canvas_width, layer),
-        Layer::Solid(layer) => Ok(draw_solid_layer(img, canvas_width, layer)),
+        Layer::PngImage(layer) => Some((
+            "PngImage",
+            layer.x as f64,
+            layer.y as f64,
+            layer.width,
+            layer.height,
+        )),
+        Layer::JpgImage(layer) => Some((
+            "JpgImage",
+            layer.x as f64,
+            layer.y as f64,
+            layer.width,
+            layer.height,
+        )),
+        Layer::Solid(layer) => Some((
+            "Solid",
+            layer.x as f64,
+            layer.y as f64,
+            layer.width,
+            layer.height,
+        )),
+        Layer::Gradient(layer) => Some((
+            "Gradient",
+            layer.x as f64,
+            layer.y as f64,
+            layer.width,
+            layer.height,
+        )),
+        Layer::Video(layer) => Some((
+            "Video",
+            layer.x as f64,
+            layer.y as f64,
+            layer.width,
+            layer.height,
+        )),
+        Layer::Blur(layer) => Some((
+            "Blur",
+            layer.x as f64,
+            layer.y as f64,
+            layer.width,
+            layer.height,
+        )),
+        Layer::Ellipse(layer) => Some((
+            "Ellipse",
+            layer.x as f64,
+            layer.y as f64,
+            layer.width,
+            layer.height,
+        )),
+        // The size of text is only known after laying it out
+        Layer::Text(_) => None,
+        // Takes the size of the layer that it masks
+        Layer::Mask(_) => None,
     }
 }
+
+// Layers that extend beyond the canvas are still drawn, but clipped.
+// Rotation is not taken into account.
+pub fn get_clipping_warnings(width: u32, height: u32, layers: &[Layer]) -> Vec<String> {
+    layers
+        .iter()
+        .enumerate()
+        .filter_map(|(index, layer)| {
+            let (layer_type, x, y, layer_width, layer_height) = get_layer_rect(layer)?;
+            let fits = x >= 0.0
+                && y >= 0.0
+                && x + layer_width as f64 <= width as f64
+                && y + layer_height as f64 <= height as f64;
+            match fits {
+                true => None,
+                false => Some(format!(
+                    "Layer {} ({}) is clipped: x = {}, y = {}, width = {}, height = {} does not fit into the canvas of {}x{}",
+                    index, layer_type, x, y, layer_width, layer_height, width, height
+                )),
+            }
+        })
+        .collect()
+}
+
+// Sources are decoded in parallel first. Then the canvas is split into horizontal
+// bands that are drawn in parallel, and within each band the layers are applied
+// back-to-front. Every pixel only depends on the layers above it, so the result
+// is the same as drawing the layers one after another.
+// Blur layers depend on the neighbouring pixels, so everything below a blur
+// is drawn before the blur is applied to the whole canvas.
+//
+// The output is deterministic: the same payload gives the same bytes, regardless
+// of the amount of threads. Nothing that is drawn may depend on the band size or
+// on the order in which threads finish, rounding is done per pixel with fixed
+// rules, and parallel results are always collected in layer order.
+// compose-determinism.test.ts locks this with a hash of the encoded output.
+// The amount of pixels of a canvas. Sizes whose RGBA buffer would not be
+// addressable are rejected instead of wrapping around.
+pub fn get_pixel_count(width: u32, height: u32) -> Result<usize, ErrorWithBacktrace> {
+    (width as u64)
+        .checked_mul(height as u64)
+        .filter(|pixels| {
+            pixels
+                .checked_mul(4)
+                .is_some_and(|bytes| usize::try_from(bytes).is_ok())
+        })
+        .map(|pixels| pixels as usize)
+        .ok_or_else(|| {
+            ErrorWithBacktrace::with_code(
+                ErrorCode::LimitExceeded,
+                format!("A canvas of {}x{} pixels is too large", width, height),
+            )
+        })
+}
+
+pub fn compose(
+    width: u32,
+    height: u32,
+    layers: Vec<Layer>,
+    options: &ComposeOptions,
+) -> Result<Vec<u8>, ErrorWithBacktrace> {
+    let len = get_pixel_count(width, height)?;
+    let mut data: Vec<u8> = options.background.repeat(len);
+    if let Some(base_image) = &options.base_image {
+        for (pixel, base) in data.chunks_exact_mut(4).zip(base_image.chunks_exact(4)) {
+            let blended = alpha_compositing(
+                pixel[0], pixel[1], pixel[2], pixel[3], base[0], base[1], base[2], base[3],
+            );
+            pixel.copy_from_slice(&[blended.0, blended.1, blended.2, blended.3]);
+        }
+    }
+
+    let prepared = attach_masks(sort_by_z_index(layers))?
+        .into_par_iter()
+        .map(|(index, mask, layer)| {
+            prepare_masked_layer(mask, layer, options).map_err(|err| {
+                ErrorWithBacktrace::with_code(
+                    error_code(&err),
+                    format!("Layer {}: {}", index, error_to_string(&err)),
+                )
+            })
+        })
+        .collect::<Result<Vec<Option<PreparedLayer>>, ErrorWithBacktrace>>()?;
+
+    if data.is_empty() {
+        return Ok(data);
+    }
+
+    let mut start = 0;
+    for (index, layer) in prepared.iter().enumerate() {
+        if let Some(PreparedLayer::Blur(blur)) = layer {
+            draw_in_bands(&mut data, width, height, &prepared[start..index]);
+            draw_blur_layer(&mut data, width, height, blur);
+            start = index + 1;
+        }
+    }
+    draw_in_bands(&mut data, width, height, &prepared[start..]);
+
+    Ok(data)
+}
@@ -1,3 +1,4 @@
+use crate::asset_cache::AssetCacheState;
 use crate::frame_cache::FrameCache;
 use crate::opened_stream::OpenedStream;
 use crate::opened_video::OpenedVideo;
@@ -8,12 +9,72 @@ use png::EncodingError;
 use std::any::Any;
 use std::backtrace::Backtrace;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{
     Arc, Mutex, MutexGuard, PoisonError, RwLockReadGuard, RwLockWriteGuard, TryLockError,
 };
 
+static BACKTRACES: AtomicBool = AtomicBool::new(false);
+
+// Backtraces are large and contain the paths of the build machine, so they
+// are only captured with verbose logging or REMOTION_COMPOSITOR_BACKTRACE=1.
+// Not a Mutex like the verbose flag of the logger, because errors can be
+// created while the logger is locked.
+pub fn set_backtraces(enabled: bool) {
+    BACKTRACES.store(enabled, Ordering::Relaxed);
+}
+
+fn backtraces_enabled() -> bool {
+    BACKTRACES.load(Ordering::Relaxed)
+        || std::env::var("REMOTION_COMPOSITOR_BACKTRACE").is_ok_and(|value| value == "1")
+}
+
+fn capture_backtrace() -> String {
+    match backtraces_enabled() {
+        true => Backtrace::force_capture().to_string(),
+        false => String::new(),
+    }
+}
+
+// Exit codes of the binary. The same code is sent as `code` in the ErrorPayload,
+// so callers can tell bad input apart from failures that are worth retrying.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorCode {
+    // Everything that does not fall into one of the categories below
+    Other = 1,
+    // The command is not valid JSON or does not match the shape of its payload
+    Parse = 2,
+    // A file that should be read does not exist
+    MissingInput = 3,
+    // The output image could not be encoded
+    Encode = 4,
+    // An input image could not be decoded
+    Decode = 5,
+    // A network request failed or timed out, retrying may succeed
+    Network = 6,
+    // The command exceeds max_pixels, max_layers or max_source_pixels
+    LimitExceeded = 7,
+}
+
+pub fn error_code(err: &ErrorWithBacktrace) -> ErrorCode {
+    match &err.error {
+        PossibleErrors::Categorized(code, _) => *code,
+        PossibleErrors::SerdeError(_) => ErrorCode::Parse,
+        PossibleErrors::IoError(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            ErrorCode::MissingInput
+        }
+        PossibleErrors::FfmpegError(remotionffmpeg::Error::Other {
+            errno: remotionffmpeg::error::ENOENT,
+        }) => ErrorCode::MissingInput,
+        PossibleErrors::EncodingError(_) => ErrorCode::Encode,
+        PossibleErrors::DecodingError(_) | PossibleErrors::JpegDecoderError(_) => ErrorCode::Decode,
+        _ => ErrorCode::Other,
+    }
+}
+
 pub fn error_to_string(err: &ErrorWithBacktrace) -> String {
     match &err.error {
+        PossibleErrors::Categorized(_, message) => message.clone(),
         PossibleErrors::IoError(err) => err.to_string(),
         PossibleErrors::FfmpegError(err) => err.to_string(),
         PossibleErrors::TryFromIntError(err) => err.to_string(),
@@ -29,6 +90,7 @@ pub fn error_to_string(err: &ErrorWithBacktrace) -> String {
 pub fn error_to_json(err: ErrorWithBacktrace) -> Result<String, ErrorWithBacktrace> {
     let json = ErrorPayload {
         error: error_to_string(&err),
+        code: error_code(&err) as u32,
         backtrace: err.backtrace,
     };
     Ok(serde_json::to_string(&json)?)
@@ -36,8 +98,40 @@ pub fn error_to_json(err: ErrorWithBacktrace) -> Result<String, ErrorWithBacktra
 
 pub fn handle_global_error(err: ErrorWithBacktrace) -> ! {
     // Only log printing to stderr
+    let code = error_code(&err);
     eprint!("{}", error_to_json(err).unwrap());
-    std::process::exit(1);
+    std::process::exit(code as i32);
+}
+
+pub enum ParseError {
+    // Not valid JSON, includes the line and column of the syntax error
+    MalformedJson(serde_json::Error),
+    // Valid JSON, but the `type` of the payload is not a known command
+    UnknownCommand(String, serde_json::Error),
+    // Known command, but the payload does not match its shape
+    InvalidPayload(serde_json::Error),
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::MalformedJson(err) => write!(f, "Malformed JSON: {}", err),
+            ParseError::UnknownCommand(command, _) => write!(f, "Unknown command: {}", command),
+            ParseError::InvalidPayload(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::fmt::Debug for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::MalformedJson(err) => write!(f, "MalformedJson: {:?}", err),
+            ParseError::UnknownCommand(command, err) => {
+                write!(f, "UnknownCommand({}): {:?}", command, err)
+            }
+            ParseError::InvalidPayload(err) => write!(f, "InvalidPayload: {:?}", err),
+        }
+    }
 }
 
 enum PossibleErrors {
@@ -50,18 +144,30 @@ enum PossibleErrors {
     WorkerError(Box<dyn Any + Send>),
     EncodingError(EncodingError),
     ThreadPoolBuilderError(rayon::ThreadPoolBuildError),
+    // An error that was created with an explicit code
+    Categorized(ErrorCode, String),
 }
 
 pub struct ErrorWithBacktrace {
     error: PossibleErrors,
+    // Empty unless backtraces are enabled, see set_backtraces()
     pub backtrace: String,
 }
 
+impl ErrorWithBacktrace {
+    pub fn with_code(code: ErrorCode, message: impl Into<String>) -> ErrorWithBacktrace {
+        ErrorWithBacktrace {
+            error: PossibleErrors::Categorized(code, message.into()),
+            backtrace: capture_backtrace(),
+        }
+    }
+}
+
 impl From<Box<dyn Any + Send>> for ErrorWithBacktrace {
     fn from(err: Box<dyn Any + Send>) -> ErrorWithBacktrace {
         ErrorWithBacktrace {
             error: PossibleErrors::WorkerError(err),
-            backtrace: Backtrace::force_capture().to_string(),
+            backtrace: capture_backtrace(),
         }
     }
 }
@@ -70,7 +176,7 @@ impl From<remotionffmpeg::Error> for ErrorWithBacktrace {
     fn from(err: remotionffmpeg::Error) -> ErrorWithBacktrace {
         ErrorWithBacktrace {
             error: PossibleErrors::FfmpegError(err),
-            backtrace: Backtrace::force_capture().to_string(),
+            backtrace: capture_backtrace(),
         }
     }
 }
@@ -79,7 +185,7 @@ impl From<std::io::Error> for ErrorWithBacktrace {
     fn from(err: std::io::Error) -> ErrorWithBacktrace {
         ErrorWithBacktrace {
             error: PossibleErrors::IoError(err),
-            backtrace: Backtrace::force_capture().to_string(),
+            backtrace: capture_backtrace(),
         }
     }
 }
@@ -88,7 +194,7 @@ impl From<std::num::TryFromIntError> for ErrorWithBacktrace {
     fn from(err: std::num::TryFromIntError) -> ErrorWithBacktrace {
         ErrorWithBacktrace {
             error: PossibleErrors::TryFromIntError(err),
-            backtrace: Backtrace::force_capture().to_string(),
+            backtrace: capture_backtrace(),
         }
     }
 }
@@ -97,7 +203,17 @@ impl From<serde_json::Error> for ErrorWithBacktrace {
     fn from(err: serde_json::Error) -> ErrorWithBacktrace {
         ErrorWithBacktrace {
             error: PossibleErrors::SerdeError(err),
-            backtrace: Backtrace::force_capture().to_string(),
+            backtrace: capture_backtrace(),
+        }
+    }
+}
+
+impl From<ParseError> for ErrorWithBacktrace {
+    fn from(err: ParseError) -> ErrorWithBacktrace {
+        match err {
+            ParseError::MalformedJson(err) => ErrorWithBacktrace::from(err),
+            ParseError::UnknownCommand(_, err) => ErrorWithBacktrace::from(err),
+            ParseError::InvalidPayload(err) => ErrorWithBacktrace::from(err),
         }
     }
 }
@@ -106,7 +222,7 @@ impl From<png::DecodingError> for ErrorWithBacktrace {
     fn from(err: png::DecodingError) -> ErrorWithBacktrace {
         ErrorWithBacktrace {
             error: PossibleErrors::DecodingError(err),
-            backtrace: Backtrace::force_capture().to_string(),
+            backtrace: capture_backtrace(),
         }
     }
 }
@@ -115,7 +231,7 @@ impl From<jpeg_decoder::Error> for ErrorWithBacktrace {
     fn from(err: jpeg_decoder::Error) -> ErrorWithBacktrace {
         ErrorWithBacktrace {
             error: PossibleErrors::JpegDecoderError(err),
-            backtrace: Backtrace::force_capture().to_string(),
+            backtrace: capture_backtrace(),
         }
     }
 }
@@ -124,7 +240,7 @@ impl From<EncodingError> for ErrorWithBacktrace {
     fn from(err: EncodingError) -> ErrorWithBacktrace {
         ErrorWithBacktrace {
             error: PossibleErrors::EncodingError(err),
-            backtrace: Backtrace::force_capture().to_string(),
+            backtrace: capture_backtrace(),
         }
     }
 }
@@ -133,7 +249,7 @@ impl From<rayon::ThreadPoolBuildError> for ErrorWithBacktrace {
     fn from(err: rayon::ThreadPoolBuildError) -> ErrorWithBacktrace {
         ErrorWithBacktrace {
             error: PossibleErrors::ThreadPoolBuilderError(err),
-            backtrace: Backtrace::force_capture().to_string(),
+            backtrace: capture_backtrace(),
         }
     }
 }
@@ -145,7 +261,7 @@ impl From<&str> for ErrorWithBacktrace {
                 std::io::ErrorKind::Other,
                 err.to_string(),
             )),
-            backtrace: Backtrace::force_capture().to_string(),
+            backtrace: capture_backtrace(),
         }
     }
 }
@@ -154,7 +270,7 @@ impl From<std::string::String> for ErrorWithBacktrace {
     fn from(err: std::string::String) -> ErrorWithBacktrace {
         ErrorWithBacktrace {
             error: PossibleErrors::IoError(std::io::Error::new(std::io::ErrorKind::Other, err)),
-            backtrace: Backtrace::force_capture().to_string(),
+            backtrace: capture_backtrace(),
         }
     }
 }
@@ -165,7 +281,7 @@ fn create_error_with_backtrace<T>(err: PoisonError<MutexGuard<'_, T>>) -> ErrorW
             std::io::ErrorKind::Other,
             err.to_string(),
         )),
-        backtrace: Backtrace::force_capture().to_string(),
+        backtrace: capture_backtrace(),
     }
 }
 
@@ -192,6 +308,18 @@ impl From<PoisonError<MutexGuard<'_, OpenedVideoManager>>> for ErrorWithBacktrac
     }
 }
 
+impl From<PoisonError<MutexGuard<'_, AssetCacheState>>> for ErrorWithBacktrace {
+    fn from(err: PoisonError<MutexGuard<'_, AssetCacheState>>) -> ErrorWithBacktrace {
+        create_error_with_backtrace(err)
+    }
+}
+
+impl From<PoisonError<MutexGuard<'_, Vec<String>>>> for ErrorWithBacktrace {
+    fn from(err: PoisonError<MutexGuard<'_, Vec<String>>>) -> ErrorWithBacktrace {
+        create_error_with_backtrace(err)
+    }
+}
+
 impl From<PoisonError<RwLockReadGuard<'_, HashMap<std::string::String, Arc<Mutex<OpenedVideo>>>>>>
     for ErrorWithBacktrace
 {
@@ -238,6 +366,15 @@ impl From<PoisonError<RwLockReadGuard<'_, HashMap<std::string::String, Arc<Mutex
     }
 }
 
+// So that embedders of the library can handle it like any other error
+impl std::fmt::Display for ErrorWithBacktrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", error_to_string(self))
+    }
+}
+
+impl std::error::Error for ErrorWithBacktrace {}
+
 impl std::fmt::Debug for ErrorWithBacktrace {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self.error {
@@ -252,6 +389,7 @@ impl std::fmt::Debug for ErrorWithBacktrace {
             PossibleErrors::ThreadPoolBuilderError(err) => {
                 write!(f, "ThreadPoolBuilderError: {:?}", err)
             }
+            PossibleErrors::Categorized(code, message) => write!(f, "{:?}: {}", code, message),
         }
     }
 }
@@ -1,10 +1,11 @@
-use crate::errors::ErrorWithBacktrace;
+use crate::errors::{error_to_string, ErrorWithBacktrace};
 use crate::frame_cache_manager::FrameCacheManager;
-use crate::global_printer::_print_verbose;
+use crate::global_printer::{_print_verbose, print_progress};
+use crate::image::frame_to_png;
 use crate::opened_stream::calc_position;
 use crate::opened_video_manager::OpenedVideoManager;
 use crate::payloads::payloads::{
-    KnownAudioCodecs, KnownCodecs, KnownColorSpaces, OpenVideoStats, VideoMetadata,
+    KnownAudioCodecs, KnownCodecs, KnownColorSpaces, OpenVideoStats, ProbeResult, VideoMetadata,
 };
 use std::fs::File;
 use std::io::{BufReader, ErrorKind};
@@ -45,6 +46,16 @@ pub fn keep_only_latest_frames_and_close_videos(
     Ok(())
 }
 
+// Closes the video and drops its frame cache, if it is open
+pub fn close_video(src: &str) -> Result<(), ErrorWithBacktrace> {
+    let opened_video_manager = OpenedVideoManager::get_instance();
+    if opened_video_manager.is_video_open(src)? {
+        opened_video_manager.remove_video(src)?;
+    }
+
+    Ok(())
+}
+
 pub fn emergency_memory_free_up() -> Result<(), ErrorWithBacktrace> {
     let manager = FrameCacheManager::get_instance();
 
@@ -54,6 +65,78 @@ pub fn emergency_memory_free_up() -> Result<(), ErrorWithBacktrace> {
     Ok(())
 }
 
+pub struct ExtractedFrame {
+    pub data: Vec<u8>,
+    // The timestamp of the decoded frame in seconds, which can differ from the requested time
+    pub timestamp: f64,
+}
+
+fn get_frame_timestamp(
+    src: &str,
+    original_src: &str,
+    transparent: bool,
+    tone_mapped: bool,
+    frame_id: usize,
+    time_base: Rational,
+) -> Result<f64, ErrorWithBacktrace> {
+    let resolved_pts = FrameCacheManager::get_instance()
+        .get_frame_cache(src, original_src, transparent, tone_mapped)
+        .lock()?
+        .get_item_resolved_pts(frame_id)
+        .ok_or(ErrorWithBacktrace::from("Frame evicted from cache"))?;
+
+    Ok(resolved_pts as f64 * time_base.0 as f64 / time_base.1 as f64)
+}
+
+// Only frames that start before the end of the video are counted
+pub fn get_frame_count(duration_in_seconds: f64, fps: f64) -> u64 {
+    (duration_in_seconds * fps).floor() as u64
+}
+
+pub fn probe(input: &str) -> Result<ProbeResult, ErrorWithBacktrace> {
+    let metadata = get_video_metadata(input).map_err(|err| {
+        ErrorWithBacktrace::from(format!(
+            "Could not probe {}: {}",
+            input,
+            error_to_string(&err)
+        ))
+    })?;
+
+    Ok(ProbeResult {
+        width: metadata.width,
+        height: metadata.height,
+        duration_seconds: metadata.durationInSeconds,
+        fps: metadata.fps as f64,
+        frame_count: get_frame_count(metadata.durationInSeconds, metadata.fps as f64),
+    })
+}
+
+// The timestamp of a frame number at the given frame rate.
+// Frames past the end of the video are an error.
+pub fn get_time_of_frame(src: &str, frame: u64, fps: f64) -> Result<f64, ErrorWithBacktrace> {
+    if !fps.is_finite() || fps <= 0.0 {
+        return Err(ErrorWithBacktrace::from(format!(
+            "fps must be a positive number, but got {}",
+            fps
+        )));
+    }
+
+    let metadata = get_video_metadata(src)?;
+    let total_frames = get_frame_count(metadata.durationInSeconds, fps);
+    if frame >= total_frames {
+        return Err(ErrorWithBacktrace::from(format!(
+            "Frame {} is out of range, {} has {} frames at {} fps (0-{})",
+            frame,
+            src,
+            total_frames,
+            fps,
+            total_frames.saturating_sub(1)
+        )));
+    }
+
+    Ok(frame as f64 / fps)
+}
+
 pub fn extract_frame(
     src: String,
     original_src: String,
@@ -62,6 +145,25 @@ pub fn extract_frame(
     tone_mapped: bool,
     maximum_frame_cache_size_in_bytes: Option<u128>,
 ) -> Result<Vec<u8>, ErrorWithBacktrace> {
+    Ok(extract_frame_with_timestamp(
+        src,
+        original_src,
+        time,
+        transparent,
+        tone_mapped,
+        maximum_frame_cache_size_in_bytes,
+    )?
+    .data)
+}
+
+pub fn extract_frame_with_timestamp(
+    src: String,
+    original_src: String,
+    time: f64,
+    transparent: bool,
+    tone_mapped: bool,
+    maximum_frame_cache_size_in_bytes: Option<u128>,
+) -> Result<ExtractedFrame, ErrorWithBacktrace> {
     let manager = OpenedVideoManager::get_instance();
     let video_locked = manager.get_video(&src, &original_src, transparent)?;
     let mut vid = video_locked.lock()?;
@@ -94,13 +196,22 @@ pub fn extract_frame(
 
     match cache_item {
         Ok(Some(item)) => {
-            return Ok(FrameCacheManager::get_instance().get_cache_item_from_id(
+            let data = FrameCacheManager::get_instance().get_cache_item_from_id(
                 &src,
                 &original_src,
                 transparent,
                 tone_mapped,
                 item,
-            )?);
+            )?;
+            let timestamp = get_frame_timestamp(
+                &src,
+                &original_src,
+                transparent,
+                tone_mapped,
+                item,
+                vid.time_base,
+            )?;
+            return Ok(ExtractedFrame { data, timestamp });
         }
         Ok(None) => {}
         Err(err) => {
@@ -165,14 +276,84 @@ pub fn extract_frame(
         .lock()?
         .get_item_from_id(frame_id);
 
-    match from_cache {
-        Ok(Some(data)) => Ok(data),
+    let data = match from_cache {
+        Ok(Some(data)) => data,
         Ok(None) => Err(std::io::Error::new(
             ErrorKind::Other,
             "Frame evicted from cache",
         ))?,
-        Err(err) => Err(err),
+        Err(err) => return Err(err),
+    };
+
+    let timestamp = get_frame_timestamp(
+        &src,
+        &original_src,
+        transparent,
+        tone_mapped,
+        frame_id,
+        time_base,
+    )?;
+
+    Ok(ExtractedFrame { data, timestamp })
+}
+
+pub fn get_frame_output_path(output_pattern: &str, index: usize, total: usize) -> String {
+    let digits = (total.max(1) - 1).to_string().len();
+    output_pattern.replace("{index}", &format!("{:0width$}", index, width = digits))
+}
+
+// Times are decoded in ascending order so the video only needs to be decoded forward once.
+// {index} is the position of the time in `times`, the outputs are returned in the same
+// order. A time that appears more than once is decoded once and written to every output.
+pub fn extract_frames(
+    src: String,
+    original_src: String,
+    times: Vec<f64>,
+    output_pattern: String,
+    transparent: bool,
+    tone_mapped: bool,
+    maximum_frame_cache_size_in_bytes: Option<u128>,
+    progress: bool,
+) -> Result<Vec<String>, ErrorWithBacktrace> {
+    if times.is_empty() {
+        return Err(ErrorWithBacktrace::from(
+            "times must contain at least one timestamp",
+        ));
+    }
+    if times.iter().any(|time| !time.is_finite()) {
+        return Err(ErrorWithBacktrace::from(
+            "times must only contain finite numbers",
+        ));
+    }
+
+    let mut order: Vec<usize> = (0..times.len()).collect();
+    order.sort_by(|a, b| times[*a].total_cmp(&times[*b]));
+
+    let outputs: Vec<String> = (0..times.len())
+        .map(|index| get_frame_output_path(&output_pattern, index, times.len()))
+        .collect();
+    let mut written = 0;
+    for same_time in order.chunk_by(|a, b| times[*a] == times[*b]) {
+        let frame = extract_frame(
+            src.clone(),
+            original_src.clone(),
+            times[same_time[0]],
+            transparent,
+            tone_mapped,
+            maximum_frame_cache_size_in_bytes,
+        )?;
+        let png = frame_to_png(frame)?;
+
+        for index in same_time {
+            std::fs::write(&outputs[*index], &png)?;
+            written += 1;
+            if progress {
+                print_progress(written, times.len())?;
+            }
+        }
     }
+
+    Ok(outputs)
 }
 
 // https://docs.rs/ffmpeg-next/6.0.0/src/metadata/metadata.rs.html#35
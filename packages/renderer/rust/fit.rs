@@ -0,0 +1,114 @@
+use std::cmp::Ordering;
+
+use crate::{
+    errors::ErrorWithBacktrace,
+    image::{crop_image, RgbaImage},
+    payloads::payloads::{FitMode, ScaleMode},
+    scaling::scale,
+};
+
+// The size closest to `size` that differs from `total` by an even amount,
+// so that the margins on both sides come out equal
+fn centered_size(size: f64, total: u32) -> u32 {
+    let margin = ((total as f64 - size) / 2.0).round().max(0.0) as u32;
+    total - margin.min(total.saturating_sub(1) / 2) * 2
+}
+
+// Scales the source to `width` x `height`. Contain keeps the full source and
+// fills the bars next to it with `pad`, Cover crops the same amount from both
+// sides of the source.
+pub fn fit_image(
+    source: &RgbaImage,
+    fit: FitMode,
+    scaling: &ScaleMode,
+    pad: [u8; 4],
+    width: u32,
+    height: u32,
+) -> Result<Vec<u8>, ErrorWithBacktrace> {
+    if width == 0 || height == 0 {
+        return Ok(vec![]);
+    }
+
+    // Compared without dividing, so that equal aspect ratios are never treated as different
+    let shape = (source.width as u64 * height as u64).cmp(&(source.height as u64 * width as u64));
+    let aspect = width as f64 / height as f64;
+    let source_aspect = source.width as f64 / source.height as f64;
+
+    match (fit, shape) {
+        // The source is wider than the layer
+        (FitMode::Cover, Ordering::Greater) => {
+            let crop_width = centered_size(source.height as f64 * aspect, source.width);
+            let cropped = crop_image(
+                source,
+                (source.width - crop_width) / 2,
+                0,
+                crop_width,
+                source.height,
+            )?;
+            Ok(scale(
+                scaling,
+                &cropped.data,
+                crop_width,
+                source.height,
+                width,
+                height,
+            ))
+        }
+        (FitMode::Cover, Ordering::Less) => {
+            let crop_height = centered_size(source.width as f64 / aspect, source.height);
+            let cropped = crop_image(
+                source,
+                0,
+                (source.height - crop_height) / 2,
+                source.width,
+                crop_height,
+            )?;
+            Ok(scale(
+                scaling,
+                &cropped.data,
+                source.width,
+                crop_height,
+                width,
+                height,
+            ))
+        }
+        (FitMode::Contain, Ordering::Greater | Ordering::Less) => {
+            let (fit_width, fit_height) = match shape {
+                Ordering::Greater => (width, centered_size(width as f64 / source_aspect, height)),
+                _ => (centered_size(height as f64 * source_aspect, width), height),
+            };
+            let scaled = scale(
+                scaling,
+                &source.data,
+                source.width,
+                source.height,
+                fit_width,
+                fit_height,
+            );
+
+            let mut data: Vec<u8> = pad
+                .iter()
+                .copied()
+                .cycle()
+                .take((width * height * 4) as usize)
+                .collect();
+            let x = (width - fit_width) / 2;
+            let y = (height - fit_height) / 2;
+            let row_size = (fit_width * 4) as usize;
+            for (row, scaled_row) in scaled.chunks_exact(row_size).enumerate() {
+                let start = (((y + row as u32) * width + x) * 4) as usize;
+                data[start..start + row_size].copy_from_slice(scaled_row);
+            }
+            Ok(data)
+        }
+        // Also when the aspect ratios are equal, then all modes are the same
+        _ => Ok(scale(
+            scaling,
+            &source.data,
+            source.width,
+            source.height,
+            width,
+            height,
+        )),
+    }
+}
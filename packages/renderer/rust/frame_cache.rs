@@ -109,6 +109,13 @@ impl FrameCache {
         }
     }
 
+    pub fn get_item_resolved_pts(&self, id: usize) -> Option<i64> {
+        self.items
+            .iter()
+            .find(|item| item.id == id)
+            .map(|item| item.resolved_pts)
+    }
+
     pub fn get_item_from_id(&mut self, id: usize) -> Result<Option<Vec<u8>>, ErrorWithBacktrace> {
         let mut data: Option<Vec<u8>> = None;
         for i in 0..self.items.len() {
@@ -34,6 +34,19 @@ pub fn synchronized_write_buf(
     Ok(())
 }
 
+// One JSON line per call, flushed right away so a progress bar can update live.
+// stderr is used so that the framed responses on stdout are not affected.
+pub fn print_progress(done: usize, total: usize) -> Result<(), ErrorWithBacktrace> {
+    let line = format!(
+        "{{\"progress\":{{\"done\":{},\"total\":{}}}}}\n",
+        done, total
+    );
+    let mut handle = io::stderr().lock();
+    handle.write_all(line.as_bytes())?;
+    handle.flush()?;
+    Ok(())
+}
+
 struct Logger {
     verbose: Arc<Mutex<bool>>,
 }
@@ -0,0 +1,102 @@
+use crate::{
+    canvas::Band,
+    payloads::payloads::{GradientDirection, GradientLayer},
+};
+
+// 4x4 ordered dithering matrix, used to avoid banding in subtle gradients
+const BAYER_MATRIX: [[f32; 4]; 4] = [
+    [0.0, 8.0, 2.0, 10.0],
+    [12.0, 4.0, 14.0, 6.0],
+    [3.0, 11.0, 1.0, 9.0],
+    [15.0, 7.0, 13.0, 5.0],
+];
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let c = value as f32 / 255.0;
+    match c <= 0.04045 {
+        true => c / 12.92,
+        false => ((c + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    let c = value.clamp(0.0, 1.0);
+    let srgb = match c <= 0.0031308 {
+        true => c * 12.92,
+        false => 1.055 * c.powf(1.0 / 2.4) - 0.055,
+    };
+    srgb * 255.0
+}
+
+fn quantize(value: f32, dither: f32) -> u8 {
+    (value + dither).round().clamp(0.0, 255.0) as u8
+}
+
+fn get_progress(direction: &GradientDirection, x: u32, y: u32, width: u32, height: u32) -> f32 {
+    let horizontal = match width > 1 {
+        true => x as f32 / (width - 1) as f32,
+        false => 0.0,
+    };
+    let vertical = match height > 1 {
+        true => y as f32 / (height - 1) as f32,
+        false => 0.0,
+    };
+
+    match direction {
+        GradientDirection::Horizontal => horizontal,
+        GradientDirection::Vertical => vertical,
+        GradientDirection::Diagonal => (horizontal + vertical) / 2.0,
+    }
+}
+
+// Colors are interpolated in linear light and converted back to sRGB.
+// Pixels outside of the band are skipped.
+pub fn draw_gradient_layer(band: &mut Band, layer: &GradientLayer) {
+    let start = [
+        srgb_to_linear(layer.start_color.0[0]),
+        srgb_to_linear(layer.start_color.0[1]),
+        srgb_to_linear(layer.start_color.0[2]),
+    ];
+    let end = [
+        srgb_to_linear(layer.end_color.0[0]),
+        srgb_to_linear(layer.end_color.0[1]),
+        srgb_to_linear(layer.end_color.0[2]),
+    ];
+
+    let rows = band.rows(layer.y as i64, layer.y as i64 + layer.height as i64);
+    let columns = band.columns(layer.x as i64, layer.x as i64 + layer.width as i64);
+
+    for y in rows {
+        for x in columns.clone() {
+            let local_x = (x - layer.x as i64) as u32;
+            let local_y = (y - layer.y as i64) as u32;
+            let t = get_progress(
+                &layer.direction,
+                local_x,
+                local_y,
+                layer.width,
+                layer.height,
+            );
+
+            let dither = (BAYER_MATRIX[(y % 4) as usize][(x % 4) as usize] + 0.5) / 16.0 - 0.5;
+
+            let r = linear_to_srgb(start[0] + (end[0] - start[0]) * t);
+            let g = linear_to_srgb(start[1] + (end[1] - start[1]) * t);
+            let b = linear_to_srgb(start[2] + (end[2] - start[2]) * t);
+            let a = layer.start_color.0[3] as f32
+                + (layer.end_color.0[3] as f32 - layer.start_color.0[3] as f32) * t;
+
+            band.blend_with_mode(
+                x,
+                y,
+                [
+                    quantize(r, dither),
+                    quantize(g, dither),
+                    quantize(b, dither),
+                    quantize(a, dither),
+                ],
+                layer.blend_mode,
+            );
+        }
+    }
+}
@@ -1,30 +1,114 @@
-use std::{fs::File, io::BufWriter, path::Path};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
 
-use jpeg_encoder::{ColorType, Encoder};
+use jpeg_encoder::{ColorType, Density, Encoder, SamplingFactor};
 
-use crate::errors::ErrorWithBacktrace;
+use crate::{
+    color_profile::{iccp_chunk, EmbeddedProfile},
+    compositor::alpha_compositing,
+    errors::{ErrorCode, ErrorWithBacktrace},
+    payloads::payloads::{ChromaSubsampling, ImageFormat},
+    source::{describe_source, get_mime_type_from_bytes},
+};
 
-pub fn save_as_jpeg(
+// Passing "-" as the output writes the encoded bytes to stdout instead of a file
+pub const STDOUT_OUTPUT: &str = "-";
+
+// Rust does not translate newlines on any platform, and the BufWriter makes sure
+// the data is passed on in big chunks rather than line by line.
+// Returns the amount of bytes written.
+pub fn write_output(output: &str, data: &[u8]) -> Result<usize, std::io::Error> {
+    let writer: Box<dyn Write> = match output == STDOUT_OUTPUT {
+        true => Box::new(io::stdout().lock()),
+        false => Box::new(File::create(output)?),
+    };
+
+    let mut writer = BufWriter::with_capacity(32 * 1024, writer);
+    writer.write_all(data)?;
+    writer.flush()?;
+    Ok(data.len())
+}
+
+// Quality is 1-100, bigger values are clamped to 100
+pub fn validate_quality(quality: u8) -> Result<u8, ErrorWithBacktrace> {
+    if quality == 0 {
+        return Err(ErrorWithBacktrace::from(
+            "quality must be between 1 and 100, but got 0",
+        ));
+    }
+
+    Ok(quality.min(100))
+}
+
+// JPEG has no alpha channel, so the image is composited onto an opaque color.
+// Otherwise transparent pixels would turn into whatever RGB values they carry.
+pub fn flatten(data: &mut [u8], color: [u8; 3]) {
+    for pixel in data.chunks_exact_mut(4) {
+        let flattened = alpha_compositing(
+            color[0], color[1], color[2], 255, pixel[0], pixel[1], pixel[2], pixel[3],
+        );
+        pixel.copy_from_slice(&[flattened.0, flattened.1, flattened.2, flattened.3]);
+    }
+}
+
+// Density is stored as dots per inch in JPEG and as dots per meter in PNG
+pub fn validate_dpi(dpi: Option<u32>) -> Result<Option<u32>, ErrorWithBacktrace> {
+    match dpi {
+        Some(dpi) if dpi == 0 || dpi > u16::MAX as u32 => Err(ErrorWithBacktrace::from(format!(
+            "dpi must be between 1 and {}, but got {}",
+            u16::MAX,
+            dpi
+        ))),
+        dpi => Ok(dpi),
+    }
+}
+
+pub fn encode_jpeg(
     width: u32,
     height: u32,
     data: Vec<u8>,
-    output: String,
-) -> Result<(), std::io::Error> {
-    let encoder = match Encoder::new_file(output, 100) {
-        Ok(content) => content,
-        Err(_) => {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "could not create JPEG encoder",
-            ))
+    quality: u8,
+    dpi: Option<u32>,
+    profile: Option<&EmbeddedProfile>,
+    chroma_subsampling: Option<ChromaSubsampling>,
+) -> Result<Vec<u8>, ErrorWithBacktrace> {
+    let mut encoded: Vec<u8> = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded, quality);
+
+    // Horizontal and vertical factors of the brightness relative to the color channels
+    if let Some(chroma_subsampling) = chroma_subsampling {
+        encoder.set_sampling_factor(match chroma_subsampling {
+            ChromaSubsampling::S444 => SamplingFactor::F_1_1,
+            ChromaSubsampling::S422 => SamplingFactor::F_2_1,
+            ChromaSubsampling::S420 => SamplingFactor::F_2_2,
+        });
+    }
+
+    // Written into the JFIF header, validate_dpi() makes sure it fits into u16
+    if let Some(dpi) = dpi {
+        encoder.set_density(Density::Inch {
+            x: dpi as u16,
+            y: dpi as u16,
+        });
+    }
+
+    // Split into APP2 segments by the encoder
+    if let Some(profile) = profile {
+        if let Err(err) = encoder.add_icc_profile(&profile.icc_bytes()) {
+            return Err(ErrorWithBacktrace::with_code(
+                ErrorCode::Encode,
+                format!("could not embed the ICC profile: {}", err),
+            ));
         }
-    };
+    }
 
     let width_u16: u16 = match width.try_into() {
         Ok(content) => content,
         Err(_) => {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
+            return Err(ErrorWithBacktrace::with_code(
+                ErrorCode::Encode,
                 "could not convert width to u16",
             ))
         }
@@ -32,8 +116,8 @@ pub fn save_as_jpeg(
     let height_u16: u16 = match height.try_into() {
         Ok(content) => content,
         Err(_) => {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
+            return Err(ErrorWithBacktrace::with_code(
+                ErrorCode::Encode,
                 "could not convert height to u16",
             ))
         }
@@ -42,14 +126,132 @@ pub fn save_as_jpeg(
     match encoder.encode(&data, width_u16, height_u16, ColorType::Rgba) {
         Ok(_) => (),
         Err(_) => {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
+            return Err(ErrorWithBacktrace::with_code(
+                ErrorCode::Encode,
                 "could not encode into JPEG",
             ))
         }
     };
 
-    Ok(())
+    Ok(encoded)
+}
+
+// Lossy WebP, the alpha channel is preserved
+pub fn encode_webp(
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+    quality: u8,
+) -> Result<Vec<u8>, ErrorWithBacktrace> {
+    let encoder = webp::Encoder::from_rgba(&data, width, height);
+
+    let encoded = match encoder.encode_simple(false, quality as f32) {
+        Ok(content) => content,
+        Err(err) => {
+            return Err(ErrorWithBacktrace::with_code(
+                ErrorCode::Encode,
+                format!("could not encode into WebP: {:?}", err),
+            ))
+        }
+    };
+
+    Ok(encoded.to_vec())
+}
+
+// Speed is 1-10, 10 is the fastest but compresses the least
+pub fn validate_avif_speed(speed: Option<u8>) -> Result<u8, ErrorWithBacktrace> {
+    match speed {
+        // 0 is accepted as the slowest speed
+        Some(speed) if speed <= 10 => Ok(speed.max(1)),
+        Some(speed) => Err(ErrorWithBacktrace::from(format!(
+            "speed must be between 0 and 10, but got {}",
+            speed
+        ))),
+        None => Ok(4),
+    }
+}
+
+// The alpha channel is preserved
+pub fn encode_avif(
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+    quality: u8,
+    speed: u8,
+) -> Result<Vec<u8>, ErrorWithBacktrace> {
+    let pixels: Vec<ravif::RGBA8> = data
+        .chunks_exact(4)
+        .map(|pixel| ravif::RGBA8::new(pixel[0], pixel[1], pixel[2], pixel[3]))
+        .collect();
+
+    let encoder = ravif::Encoder::new()
+        .with_quality(quality as f32)
+        .with_alpha_quality(quality as f32)
+        .with_speed(speed);
+
+    let encoded = match encoder.encode_rgba(ravif::Img::new(
+        &pixels[..],
+        width as usize,
+        height as usize,
+    )) {
+        Ok(content) => content,
+        Err(err) => {
+            return Err(ErrorWithBacktrace::with_code(
+                ErrorCode::Encode,
+                format!("could not encode into AVIF: {}", err),
+            ))
+        }
+    };
+
+    Ok(encoded.avif_file)
+}
+
+// Every frame is quantized to its own palette of at most 256 colors with NeuQuant.
+// Fully transparent pixels stay transparent, and since every frame is cleared
+// before the next one is drawn, frames don't show through each other.
+// A single frame results in a still image, multiple frames loop forever.
+pub fn encode_gif(
+    width: u32,
+    height: u32,
+    frames: Vec<Vec<u8>>,
+    delay_ms: u32,
+) -> Result<Vec<u8>, ErrorWithBacktrace> {
+    let (width, height): (u16, u16) = match (width.try_into(), height.try_into()) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => {
+            return Err(ErrorWithBacktrace::with_code(
+                ErrorCode::Encode,
+                format!(
+                    "GIF supports at most 65535x65535 pixels, but got {}x{}",
+                    width, height
+                ),
+            ))
+        }
+    };
+
+    let encode_error =
+        |err: gif::EncodingError| ErrorWithBacktrace::with_code(ErrorCode::Encode, err.to_string());
+
+    let mut encoded: Vec<u8> = Vec::new();
+    {
+        let mut encoder =
+            gif::Encoder::new(&mut encoded, width, height, &[]).map_err(encode_error)?;
+        if frames.len() > 1 {
+            encoder
+                .set_repeat(gif::Repeat::Infinite)
+                .map_err(encode_error)?;
+        }
+
+        for mut data in frames {
+            let mut frame = gif::Frame::from_rgba_speed(width, height, &mut data, 10);
+            // GIF delays are in hundredths of a second
+            frame.delay = ((delay_ms + 5) / 10).min(u16::MAX as u32) as u16;
+            frame.dispose = gif::DisposalMethod::Background;
+            encoder.write_frame(&frame).map_err(encode_error)?;
+        }
+    }
+
+    Ok(encoded)
 }
 
 pub fn get_png_data(
@@ -79,22 +281,57 @@ pub fn get_png_data(
     Ok(png_data.clone())
 }
 
-pub fn save_as_png(
+pub fn validate_bit_depth(bit_depth: u8, format: &ImageFormat) -> Result<u8, ErrorWithBacktrace> {
+    match (bit_depth, format) {
+        (8, _) | (16, ImageFormat::Png) => Ok(bit_depth),
+        (16, _) => Err(ErrorWithBacktrace::from(format!(
+            "bit_depth 16 is only supported for Png output, but the output format is {:?}",
+            format
+        ))),
+        _ => Err(ErrorWithBacktrace::from(format!(
+            "bit_depth must be 8 or 16, but got {}",
+            bit_depth
+        ))),
+    }
+}
+
+// The canvas has 8 bits per channel. Multiplying by 257 maps 0-255 exactly
+// onto 0-65535, so that 255 stays full white and converting back is lossless.
+fn to_16_bit(data: &[u8]) -> Vec<u8> {
+    data.iter()
+        .flat_map(|value| (*value as u16 * 257).to_be_bytes())
+        .collect()
+}
+
+// The pixels per unit on both axes, followed by the unit, 1 is the meter
+fn phys_chunk(dpi: u32) -> Vec<u8> {
+    let pixels_per_meter = (dpi as f64 / 0.0254).round() as u32;
+    let mut chunk = pixels_per_meter.to_be_bytes().to_vec();
+    chunk.extend_from_slice(&pixels_per_meter.to_be_bytes());
+    chunk.push(1);
+    chunk
+}
+
+pub fn encode_png(
     width: u32,
     height: u32,
     data: Vec<u8>,
-    output: String,
-) -> Result<(), std::io::Error> {
-    let path = Path::new(&output);
-    let file = match File::create(path) {
-        Ok(content) => content,
-        Err(err) => return Err(err),
+    dpi: Option<u32>,
+    bit_depth: u8,
+    profile: Option<&EmbeddedProfile>,
+) -> Result<Vec<u8>, ErrorWithBacktrace> {
+    let mut encoded: Vec<u8> = Vec::new();
+    let data = match bit_depth {
+        16 => to_16_bit(&data),
+        _ => data,
     };
-    let ref mut w = BufWriter::new(file);
 
-    let mut encoder = png::Encoder::new(w, width, height);
+    let mut encoder = png::Encoder::new(&mut encoded, width, height);
     encoder.set_color(png::ColorType::Rgba);
-    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_depth(match bit_depth {
+        16 => png::BitDepth::Sixteen,
+        _ => png::BitDepth::Eight,
+    });
     encoder.set_source_gamma(png::ScaledFloat::from_scaled(45455)); // 1.0 / 2.2, scaled by 100000
     encoder.set_source_gamma(png::ScaledFloat::new(1.0 / 2.2)); // 1.0 / 2.2, unscaled, but rounded
     let source_chromaticities = png::SourceChromaticities::new(
@@ -105,14 +342,518 @@ pub fn save_as_png(
         (0.15000, 0.06000),
     );
     encoder.set_source_chromaticities(source_chromaticities);
+    if let Some(EmbeddedProfile::Srgb) = profile {
+        encoder.set_srgb(png::SrgbRenderingIntent::Perceptual);
+    }
     let mut writer = match encoder.write_header() {
         Ok(content) => content,
-        Err(err) => return Err(err.into()),
+        Err(err) => {
+            return Err(ErrorWithBacktrace::with_code(
+                ErrorCode::Encode,
+                err.to_string(),
+            ))
+        }
     };
+    // pHYs chunk, in pixels per meter
+    if let Some(dpi) = dpi {
+        if let Err(err) = writer.write_chunk(png::chunk::pHYs, &phys_chunk(dpi)) {
+            return Err(ErrorWithBacktrace::with_code(
+                ErrorCode::Encode,
+                err.to_string(),
+            ));
+        }
+    }
+    // Must come before the image data
+    if let Some(EmbeddedProfile::Icc(icc)) = profile {
+        if let Err(err) = writer.write_chunk(png::chunk::iCCP, &iccp_chunk(icc)?) {
+            return Err(ErrorWithBacktrace::with_code(
+                ErrorCode::Encode,
+                err.to_string(),
+            ));
+        }
+    }
 
     match writer.write_image_data(&data) {
         Ok(_) => (),
-        Err(err) => return Err(err.into()),
+        Err(err) => {
+            return Err(ErrorWithBacktrace::with_code(
+                ErrorCode::Encode,
+                err.to_string(),
+            ))
+        }
     };
+    match writer.finish() {
+        Ok(_) => (),
+        Err(err) => {
+            return Err(ErrorWithBacktrace::with_code(
+                ErrorCode::Encode,
+                err.to_string(),
+            ))
+        }
+    };
+
+    Ok(encoded)
+}
+
+// Decodes the 24-bit bottom-up BMP that ExtractFrame returns for opaque frames.
+// Returns the width, height and RGBA data.
+pub fn bmp_to_rgba(frame: &[u8]) -> Result<(u32, u32, Vec<u8>), ErrorWithBacktrace> {
+    if !frame.starts_with(b"BM") {
+        return Err(ErrorWithBacktrace::from("Frame is not a BMP"));
+    }
+
+    if frame.len() < 54 {
+        return Err(ErrorWithBacktrace::from("BMP frame is too small"));
+    }
+
+    let width = u32::from_le_bytes(frame[18..22].try_into().unwrap());
+    let height = u32::from_le_bytes(frame[22..26].try_into().unwrap());
+    let data_offset = u32::from_le_bytes(frame[10..14].try_into().unwrap()) as usize;
+    let row_size = ((width * 3 + 3) & !3) as usize;
+
+    if frame.len() < data_offset + row_size * height as usize {
+        return Err(ErrorWithBacktrace::from("BMP frame is truncated"));
+    }
+
+    let mut rgba = Vec::with_capacity((width * height * 4) as usize);
+    for y in (0..height as usize).rev() {
+        let row_start = data_offset + y * row_size;
+        for x in 0..width as usize {
+            let pixel = row_start + x * 3;
+            rgba.push(frame[pixel + 2]);
+            rgba.push(frame[pixel + 1]);
+            rgba.push(frame[pixel]);
+            rgba.push(255);
+        }
+    }
+
+    Ok((width, height, rgba))
+}
+
+// Returns the width and height of a frame that ExtractFrame returned,
+// as well as its format
+pub fn get_frame_info(frame: &[u8]) -> Result<(u32, u32, &'static str), ErrorWithBacktrace> {
+    if frame.starts_with(b"BM") && frame.len() >= 26 {
+        let width = u32::from_le_bytes(frame[18..22].try_into().unwrap());
+        let height = u32::from_le_bytes(frame[22..26].try_into().unwrap());
+        return Ok((width, height, "Bmp"));
+    }
+
+    // The IHDR chunk always comes first, right after the signature
+    if frame.starts_with(b"\x89PNG") && frame.len() >= 24 {
+        let width = u32::from_be_bytes(frame[16..20].try_into().unwrap());
+        let height = u32::from_be_bytes(frame[20..24].try_into().unwrap());
+        return Ok((width, height, "Png"));
+    }
+
+    Err(ErrorWithBacktrace::from("Frame is neither a BMP nor a PNG"))
+}
+
+// Converts an opaque BMP frame into a PNG.
+// Data that is already a PNG is returned as is.
+pub fn frame_to_png(frame: Vec<u8>) -> Result<Vec<u8>, ErrorWithBacktrace> {
+    if !frame.starts_with(b"BM") {
+        return Ok(frame);
+    }
+
+    let (width, height, rgba) = bmp_to_rgba(&frame)?;
+
+    get_png_data(&rgba, width, height)
+}
+
+#[derive(Clone)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+// Why a source could not be decoded
+enum DecodeFailure {
+    // The data is broken
+    Invalid(String),
+    // The data is valid, but uses a feature that is not supported
+    Unsupported(String),
+}
+
+fn read_png(bytes: &[u8], max_source_pixels: u64) -> Result<RgbaImage, DecodeFailure> {
+    // The size was already checked against max_source_pixels, the limit of the
+    // decoder guards its own buffers with the same budget. Rows are decoded
+    // before 16 bit samples are stripped, so they take up to 8 bytes per pixel.
+    let bytes_per_pixel = match get_png_bit_depth(bytes) {
+        Some(16) => 8,
+        _ => 4,
+    };
+    let limits = png::Limits {
+        bytes: usize::try_from(max_source_pixels.saturating_mul(bytes_per_pixel))
+            .unwrap_or(usize::MAX),
+    };
+    let mut decoder = png::Decoder::new_with_limits(bytes, limits);
+    // Palette and low bit depth images are expanded, 16 bit images are reduced to 8 bit
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder
+        .read_info()
+        .map_err(|err| DecodeFailure::Invalid(err.to_string()))?;
+
+    let size = reader.output_buffer_size();
+    let mut buf = vec![0; size];
+    let info = reader
+        .next_frame(&mut buf)
+        .map_err(|err| DecodeFailure::Invalid(err.to_string()))?;
+    let bytes = &buf[..info.buffer_size()];
+
+    let data: Vec<u8> = match info.color_type {
+        png::ColorType::Rgba => bytes.to_vec(),
+        png::ColorType::Rgb => bytes
+            .chunks(3)
+            .flat_map(|chunk| [chunk[0], chunk[1], chunk[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => bytes
+            .chunks(2)
+            .flat_map(|chunk| [chunk[0], chunk[0], chunk[0], chunk[1]])
+            .collect(),
+        png::ColorType::Grayscale => bytes
+            .iter()
+            .flat_map(|gray| [*gray, *gray, *gray, 255])
+            .collect(),
+        png::ColorType::Indexed => {
+            return Err(DecodeFailure::Unsupported(
+                "the palette could not be expanded".to_string(),
+            ))
+        }
+    };
+
+    Ok(RgbaImage {
+        width: info.width,
+        height: info.height,
+        data,
+    })
+}
+
+fn read_jpeg(bytes: &[u8]) -> Result<RgbaImage, DecodeFailure> {
+    let mut decoder = jpeg_decoder::Decoder::new(bytes);
+    let pixels = decoder
+        .decode()
+        .map_err(|err| DecodeFailure::Invalid(err.to_string()))?;
+    let info = match decoder.info() {
+        Some(info) => info,
+        None => {
+            return Err(DecodeFailure::Invalid(
+                "there is no frame header".to_string(),
+            ))
+        }
+    };
+
+    let data: Vec<u8> = match info.pixel_format {
+        jpeg_decoder::PixelFormat::RGB24 => pixels
+            .chunks(3)
+            .flat_map(|chunk| [chunk[0], chunk[1], chunk[2], 255])
+            .collect(),
+        jpeg_decoder::PixelFormat::L8 => pixels
+            .iter()
+            .flat_map(|gray| [*gray, *gray, *gray, 255])
+            .collect(),
+        _ => {
+            return Err(DecodeFailure::Unsupported(format!(
+                "the pixel format {:?} is not supported",
+                info.pixel_format
+            )))
+        }
+    };
+
+    Ok(RgbaImage {
+        width: info.width as u32,
+        height: info.height as u32,
+        data,
+    })
+}
+
+pub fn format_name(mime_type: &str) -> &'static str {
+    match mime_type {
+        "image/png" => "a PNG",
+        _ => "a JPEG",
+    }
+}
+
+// Whether the data stops before the end marker: the IEND chunk of a PNG,
+// the EOI marker of a JPEG
+fn is_truncated(mime_type: &str, bytes: &[u8]) -> bool {
+    match mime_type {
+        "image/png" => {
+            !bytes.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82])
+        }
+        _ => !bytes.ends_with(&[0xff, 0xd9]),
+    }
+}
+
+// Classifies failures as a wrong format, truncated, corrupt or unsupported data,
+// so that a broken download can be told apart from a bug.
+// The decoders may panic on malformed data, which would take down the
+// long running process, so panics are reported as corrupt data.
+fn decode_as(
+    src: &str,
+    bytes: &[u8],
+    mime_type: &str,
+    read: impl FnOnce() -> Result<RgbaImage, DecodeFailure> + std::panic::UnwindSafe,
+) -> Result<RgbaImage, ErrorWithBacktrace> {
+    let description = describe_source(src);
+    let message = match get_mime_type_from_bytes(bytes) {
+        Some(actual) if actual != mime_type => format!(
+            "{} is {}, but was expected to be {}",
+            description,
+            format_name(actual),
+            format_name(mime_type)
+        ),
+        None => format!(
+            "{} is not {}, its format is not supported",
+            description,
+            format_name(mime_type)
+        ),
+        Some(_) => match std::panic::catch_unwind(read) {
+            Ok(Ok(image)) => return Ok(image),
+            Ok(Err(DecodeFailure::Unsupported(reason))) => {
+                format!("{} is unsupported: {}", description, reason)
+            }
+            Ok(Err(DecodeFailure::Invalid(reason))) if is_truncated(mime_type, bytes) => {
+                format!("{} is truncated: {}", description, reason)
+            }
+            Ok(Err(DecodeFailure::Invalid(reason))) => {
+                format!("{} is corrupt: {}", description, reason)
+            }
+            Err(_) => format!("{} is corrupt: the decoder crashed", description),
+        },
+    };
+
+    Err(ErrorWithBacktrace::with_code(ErrorCode::Decode, message))
+}
+
+// `src` is only used in error messages
+pub fn decode_png(
+    src: &str,
+    bytes: &[u8],
+    max_source_pixels: u64,
+) -> Result<RgbaImage, ErrorWithBacktrace> {
+    decode_as(src, bytes, "image/png", || {
+        read_png(bytes, max_source_pixels)
+    })
+}
+
+// `src` is only used in error messages
+pub fn decode_jpeg(src: &str, bytes: &[u8]) -> Result<RgbaImage, ErrorWithBacktrace> {
+    decode_as(src, bytes, "image/jpeg", || read_jpeg(bytes))
+}
+
+// Decodes a PNG or JPEG, the format is detected from the first bytes
+pub fn decode_image(
+    src: &str,
+    bytes: &[u8],
+    max_source_pixels: u64,
+) -> Result<RgbaImage, ErrorWithBacktrace> {
+    match get_mime_type_from_bytes(bytes) {
+        Some("image/png") => decode_png(src, bytes, max_source_pixels),
+        Some("image/jpeg") => decode_jpeg(src, bytes),
+        _ => Err(unsupported_format_error(src)),
+    }
+}
+
+fn unsupported_format_error(src: &str) -> ErrorWithBacktrace {
+    ErrorWithBacktrace::with_code(
+        ErrorCode::Decode,
+        format!("{} is neither a PNG nor a JPEG", describe_source(src)),
+    )
+}
+
+// The size that the header declares, read without decoding the pixels
+// The bit depth follows the width and height in IHDR
+fn get_png_bit_depth(bytes: &[u8]) -> Option<u8> {
+    match bytes.get(12..16) {
+        Some(b"IHDR") => bytes.get(24).copied(),
+        _ => None,
+    }
+}
+
+fn get_declared_size(bytes: &[u8]) -> Option<(u32, u32)> {
+    match get_mime_type_from_bytes(bytes)? {
+        // IHDR is always the first chunk, its data starts with the width and height
+        "image/png" if bytes.len() >= 24 && &bytes[12..16] == b"IHDR" => Some((
+            u32::from_be_bytes([bytes[16], bytes[17], bytes[18], bytes[19]]),
+            u32::from_be_bytes([bytes[20], bytes[21], bytes[22], bytes[23]]),
+        )),
+        "image/jpeg" => {
+            let mut decoder = jpeg_decoder::Decoder::new(bytes);
+            decoder.read_info().ok()?;
+            let info = decoder.info()?;
+            Some((info.width as u32, info.height as u32))
+        }
+        _ => None,
+    }
+}
+
+// A small file can declare a huge size and use up all memory when it is decoded,
+// so the size is checked before decoding. Sources whose size can not be read
+// are rejected, since nothing would bound the memory of the decoder.
+pub fn check_source_pixels(
+    src: &str,
+    width: u32,
+    height: u32,
+    max_pixels: u64,
+) -> Result<(), ErrorWithBacktrace> {
+    let pixels = width as u64 * height as u64;
+    if pixels > max_pixels {
+        return Err(ErrorWithBacktrace::with_code(
+            ErrorCode::LimitExceeded,
+            format!(
+                "{} declares a size of {}x{} ({} pixels), which exceeds max_source_pixels of {}",
+                describe_source(src),
+                width,
+                height,
+                pixels,
+                max_pixels
+            ),
+        ));
+    }
     Ok(())
 }
+
+// Returns the declared width and height
+pub fn check_source_size(
+    src: &str,
+    bytes: &[u8],
+    max_pixels: u64,
+) -> Result<(u32, u32), ErrorWithBacktrace> {
+    let mime_type = match get_mime_type_from_bytes(bytes) {
+        Some(mime_type) => mime_type,
+        None => return Err(unsupported_format_error(src)),
+    };
+    match get_declared_size(bytes) {
+        Some((width, height)) => {
+            check_source_pixels(src, width, height, max_pixels)?;
+            Ok((width, height))
+        }
+        None => Err(ErrorWithBacktrace::with_code(
+            ErrorCode::Decode,
+            format!(
+                "{} is {}: the size could not be read from the header",
+                describe_source(src),
+                match is_truncated(mime_type, bytes) {
+                    true => "truncated",
+                    false => "corrupt",
+                }
+            ),
+        )),
+    }
+}
+
+// Mirrors the image in place. Flipping both axes is a 180° rotation.
+pub fn flip_image(image: &mut RgbaImage, flip_h: bool, flip_v: bool) {
+    let row_size = (image.width * 4) as usize;
+    if row_size == 0 {
+        return;
+    }
+
+    if flip_h {
+        for row in image.data.chunks_exact_mut(row_size) {
+            let width = image.width as usize;
+            for x in 0..width / 2 {
+                for channel in 0..4 {
+                    row.swap(x * 4 + channel, (width - 1 - x) * 4 + channel);
+                }
+            }
+        }
+    }
+
+    if flip_v {
+        let height = image.height as usize;
+        for y in 0..height / 2 {
+            let (top, bottom) = image.data.split_at_mut((height - 1 - y) * row_size);
+            top[y * row_size..(y + 1) * row_size].swap_with_slice(&mut bottom[..row_size]);
+        }
+    }
+}
+
+// Multiplies each channel with the tint, normalized to 0-1.
+// Rounded so that a white tint is an exact no-op.
+pub fn is_identity_tint(tint: [u8; 4]) -> bool {
+    tint == [255, 255, 255, 255]
+}
+
+pub fn tint_image(image: &mut RgbaImage, tint: [u8; 4]) {
+    if is_identity_tint(tint) {
+        return;
+    }
+
+    for pixel in image.data.chunks_exact_mut(4) {
+        for channel in 0..4 {
+            pixel[channel] = ((pixel[channel] as u32 * tint[channel] as u32 + 127) / 255) as u8;
+        }
+    }
+}
+
+fn clamp_adjustment(value: f32, max: f32, identity: f32) -> f32 {
+    match value.is_nan() {
+        true => identity,
+        false => value.clamp(0.0, max),
+    }
+}
+
+// Done on the sRGB encoded values, like CSS filters, in the order grayscale,
+// brightness, contrast. Every step clamps to 0-255 instead of wrapping, and
+// the alpha channel is left as is. The identity values are an exact no-op.
+pub fn is_identity_adjustment(grayscale: f32, brightness: f32, contrast: f32) -> bool {
+    clamp_adjustment(grayscale, 1.0, 0.0) == 0.0
+        && clamp_adjustment(brightness, f32::MAX, 1.0) == 1.0
+        && clamp_adjustment(contrast, f32::MAX, 1.0) == 1.0
+}
+
+pub fn adjust_image(image: &mut RgbaImage, grayscale: f32, brightness: f32, contrast: f32) {
+    if is_identity_adjustment(grayscale, brightness, contrast) {
+        return;
+    }
+    let grayscale = clamp_adjustment(grayscale, 1.0, 0.0);
+    let brightness = clamp_adjustment(brightness, f32::MAX, 1.0);
+    let contrast = clamp_adjustment(contrast, f32::MAX, 1.0);
+
+    for pixel in image.data.chunks_exact_mut(4) {
+        let [r, g, b] = [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32];
+        // Rec. 709 luma, like the Luminance mask
+        let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        for (channel, value) in [r, g, b].into_iter().enumerate() {
+            let gray = value + (luma - value) * grayscale;
+            let bright = (gray * brightness).clamp(0.0, 255.0);
+            let contrasted = (bright - 127.5) * contrast + 127.5;
+            pixel[channel] = contrasted.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+pub fn crop_image(
+    image: &RgbaImage,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> Result<RgbaImage, ErrorWithBacktrace> {
+    if width == 0
+        || height == 0
+        || x as u64 + width as u64 > image.width as u64
+        || y as u64 + height as u64 > image.height as u64
+    {
+        return Err(ErrorWithBacktrace::from(format!(
+            "Crop rectangle (x = {}, y = {}, width = {}, height = {}) is outside of the source image, which is {}x{}",
+            x, y, width, height, image.width, image.height
+        )));
+    }
+
+    let mut data = Vec::with_capacity((width * height * 4) as usize);
+    for row in y..(y + height) {
+        let start = ((row * image.width + x) * 4) as usize;
+        let end = start + (width * 4) as usize;
+        data.extend_from_slice(&image.data[start..end]);
+    }
+
+    Ok(RgbaImage {
+        width,
+        height,
+        data,
+    })
+}
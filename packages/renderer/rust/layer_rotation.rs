@@ -0,0 +1,217 @@
+use crate::{canvas::Band, payloads::payloads::BlendMode};
+
+pub fn needs_rotation(rotation: f32) -> bool {
+    rotation.is_finite() && rotation % 360.0 != 0.0
+}
+
+// Bilinear interpolation on premultiplied values, so that transparent
+// neighbours don't darken the edges of the layer
+fn sample_bilinear<F>(sample: &F, x: f32, y: f32) -> [f32; 4]
+where
+    F: Fn(i64, i64) -> [u8; 4],
+{
+    let x0 = x.floor();
+    let y0 = y.floor();
+    let fx = x - x0;
+    let fy = y - y0;
+
+    let neighbours = [
+        (x0 as i64, y0 as i64, (1.0 - fx) * (1.0 - fy)),
+        (x0 as i64 + 1, y0 as i64, fx * (1.0 - fy)),
+        (x0 as i64, y0 as i64 + 1, (1.0 - fx) * fy),
+        (x0 as i64 + 1, y0 as i64 + 1, fx * fy),
+    ];
+
+    let mut premultiplied = [0.0_f32; 4];
+    for (nx, ny, weight) in neighbours {
+        if weight == 0.0 {
+            continue;
+        }
+        let pixel = sample(nx, ny);
+        let alpha = pixel[3] as f32 * weight;
+        premultiplied[0] += pixel[0] as f32 * alpha;
+        premultiplied[1] += pixel[1] as f32 * alpha;
+        premultiplied[2] += pixel[2] as f32 * alpha;
+        premultiplied[3] += alpha;
+    }
+
+    premultiplied
+}
+
+fn blend_premultiplied(
+    band: &mut Band,
+    x: i64,
+    y: i64,
+    premultiplied: [f32; 4],
+    opacity: f32,
+    blend_mode: BlendMode,
+) {
+    let alpha = premultiplied[3] * opacity;
+    if alpha < 0.5 {
+        return;
+    }
+
+    let unpremultiply = |channel: f32| (channel / premultiplied[3]).round().clamp(0.0, 255.0) as u8;
+
+    band.blend_with_mode(
+        x,
+        y,
+        [
+            unpremultiply(premultiplied[0]),
+            unpremultiply(premultiplied[1]),
+            unpremultiply(premultiplied[2]),
+            alpha.round().clamp(0.0, 255.0) as u8,
+        ],
+        blend_mode,
+    );
+}
+
+pub fn is_fractional(x: f32, y: f32) -> bool {
+    x.fract() != 0.0 || y.fract() != 0.0
+}
+
+// Where the pixels of a layer end up on the canvas. Rotated layers turn
+// clockwise around their own center, unrotated layers at a fractional position
+// distribute every layer pixel between the canvas pixels it overlaps, so a
+// layer at x = 10.5 covers half of column 10 and half of column 11.
+// Masks use the same transform as the layer that they mask, so both line up.
+pub struct LayerTransform {
+    x: f32,
+    y: f32,
+    width: u32,
+    height: u32,
+    // Sine and cosine of the rotation, None if the layer is not rotated
+    rotation: Option<(f32, f32)>,
+}
+
+impl LayerTransform {
+    pub fn new(x: f32, y: f32, width: u32, height: u32, rotation: f32) -> LayerTransform {
+        LayerTransform {
+            x,
+            y,
+            width,
+            height,
+            rotation: match needs_rotation(rotation) {
+                true => Some(rotation.to_radians().sin_cos()),
+                false => None,
+            },
+        }
+    }
+
+    fn half_size(&self) -> (f32, f32) {
+        (self.width as f32 / 2.0, self.height as f32 / 2.0)
+    }
+
+    // The canvas pixels that the layer covers, as left, top, right and bottom,
+    // with right and bottom excluded. Rotated layers cover their bounding box.
+    pub fn bounds(&self) -> (i64, i64, i64, i64) {
+        let (sin, cos) = match self.rotation {
+            Some(rotation) => rotation,
+            None => {
+                return (
+                    self.x.floor() as i64,
+                    self.y.floor() as i64,
+                    (self.x + self.width as f32).ceil() as i64,
+                    (self.y + self.height as f32).ceil() as i64,
+                )
+            }
+        };
+
+        let (half_width, half_height) = self.half_size();
+        let center_x = self.x + half_width;
+        let center_y = self.y + half_height;
+        let bounding_half_width = (half_width * cos).abs() + (half_height * sin).abs();
+        let bounding_half_height = (half_width * sin).abs() + (half_height * cos).abs();
+
+        (
+            (center_x - bounding_half_width).floor() as i64,
+            (center_y - bounding_half_height).floor() as i64,
+            (center_x + bounding_half_width).ceil() as i64,
+            (center_y + bounding_half_height).ceil() as i64,
+        )
+    }
+
+    // The premultiplied value of a canvas pixel, interpolated between the layer
+    // pixels. `sample` returns the layer pixel at local coordinates.
+    pub fn sample<F>(&self, sample: &F, canvas_x: i64, canvas_y: i64) -> [f32; 4]
+    where
+        F: Fn(i64, i64) -> [u8; 4],
+    {
+        let (sin, cos) = match self.rotation {
+            Some(rotation) => rotation,
+            None => {
+                return sample_bilinear(sample, canvas_x as f32 - self.x, canvas_y as f32 - self.y)
+            }
+        };
+
+        let (half_width, half_height) = self.half_size();
+        let dx = canvas_x as f32 + 0.5 - (self.x + half_width);
+        let dy = canvas_y as f32 + 0.5 - (self.y + half_height);
+
+        // Inverse rotation to find the source position
+        let source_x = cos * dx + sin * dy + half_width;
+        let source_y = -sin * dx + cos * dy + half_height;
+
+        sample_bilinear(sample, source_x - 0.5, source_y - 0.5)
+    }
+}
+
+// `sample` must return a transparent pixel for coordinates outside the layer
+fn draw_transformed_layer<F>(
+    band: &mut Band,
+    transform: &LayerTransform,
+    opacity: f32,
+    blend_mode: BlendMode,
+    sample: F,
+) where
+    F: Fn(i64, i64) -> [u8; 4],
+{
+    let (left, top, right, bottom) = transform.bounds();
+    let rows = band.rows(top, bottom);
+    let columns = band.columns(left, right);
+
+    for canvas_y in rows {
+        for canvas_x in columns.clone() {
+            let premultiplied = transform.sample(&sample, canvas_x, canvas_y);
+            blend_premultiplied(band, canvas_x, canvas_y, premultiplied, opacity, blend_mode);
+        }
+    }
+}
+
+// Rotates a layer clockwise around its own center.
+// `sample` returns the unrotated layer pixel at local coordinates,
+// and must return a transparent pixel for coordinates outside the layer.
+// The destination covers the whole rotated bounding box, clipped to the band.
+pub fn draw_rotated_layer<F>(
+    band: &mut Band,
+    x: f32,
+    y: f32,
+    width: u32,
+    height: u32,
+    rotation: f32,
+    opacity: f32,
+    blend_mode: BlendMode,
+    sample: F,
+) where
+    F: Fn(i64, i64) -> [u8; 4],
+{
+    let transform = LayerTransform::new(x, y, width, height, rotation);
+    draw_transformed_layer(band, &transform, opacity, blend_mode, sample);
+}
+
+// Draws an unrotated layer at a fractional position
+pub fn draw_translated_layer<F>(
+    band: &mut Band,
+    x: f32,
+    y: f32,
+    width: u32,
+    height: u32,
+    opacity: f32,
+    blend_mode: BlendMode,
+    sample: F,
+) where
+    F: Fn(i64, i64) -> [u8; 4],
+{
+    let transform = LayerTransform::new(x, y, width, height, 0.0);
+    draw_transformed_layer(band, &transform, opacity, blend_mode, sample);
+}
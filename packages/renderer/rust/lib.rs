@@ -0,0 +1,77 @@
+// The compositor as a library, so that it can be embedded without spawning
+// the binary. The binary itself is a thin wrapper around `cli::run()`.
+
+mod anchor;
+mod asset_cache;
+mod assets;
+mod audio_waveform;
+mod blend_mode;
+mod blur;
+mod border;
+mod canvas;
+pub mod cli;
+mod color_profile;
+mod commands;
+mod compositor;
+mod copy_clipboard;
+pub mod errors;
+mod ffmpeg;
+mod fit;
+mod frame_cache;
+mod frame_cache_manager;
+mod get_silent_parts;
+mod global_printer;
+mod gradient;
+mod image;
+mod layer_rotation;
+mod logger;
+mod mask;
+mod memory;
+mod nine_slice;
+mod opened_stream;
+mod opened_video;
+mod opened_video_manager;
+pub mod payloads;
+mod rotation;
+mod scalable_frame;
+mod scaling;
+mod shadow;
+mod shapes;
+mod source;
+mod sprite_sheet;
+mod text;
+mod tone_map;
+
+extern crate png;
+
+use assets::DecodedAssets;
+pub use commands::EncodedImage;
+pub use errors::{error_code, error_to_string, ErrorCode, ErrorWithBacktrace};
+pub use payloads::payloads::{CliGenerateImageCommand, ExtractFrameCommand};
+
+pub type RenderError = ErrorWithBacktrace;
+
+// Composes the layers and returns the encoded image.
+// `output` of the command is ignored, nothing is written.
+pub fn compose(command: CliGenerateImageCommand) -> Result<EncodedImage, RenderError> {
+    commands::encode_compose(command, &DecodedAssets::default())
+}
+
+// Returns the frame as a BMP, or as a PNG if it is transparent.
+// `output` of the command is ignored, nothing is written. Decoded frames and
+// opened videos are kept for the next call, see `free_up_memory()`.
+pub fn extract_frame(command: &ExtractFrameCommand) -> Result<Vec<u8>, RenderError> {
+    let maximum_frame_cache_size = memory::get_ideal_maximum_frame_cache_size();
+    let frame = commands::extract_frame(command, Some(maximum_frame_cache_size))?;
+    Ok(frame.data)
+}
+
+// Evicts the oldest frames until the cache fits into the budget and closes the
+// videos that no longer have frames in it, like the long running process does
+// after every command. Defaults to a budget based on the available memory.
+pub fn free_up_memory(maximum_frame_cache_size_in_bytes: Option<u128>) -> Result<(), RenderError> {
+    ffmpeg::keep_only_latest_frames_and_close_videos(
+        maximum_frame_cache_size_in_bytes
+            .unwrap_or_else(memory::get_ideal_maximum_frame_cache_size),
+    )
+}
@@ -0,0 +1,146 @@
+use crate::{
+    canvas::Band,
+    compositor::ComposeOptions,
+    errors::ErrorWithBacktrace,
+    layer_rotation::LayerTransform,
+    payloads::payloads::{MaskLayer, MaskMode},
+    scaling::scale_bilinear,
+};
+
+// Coverage values between 0 and 255 in the rectangle of the masked layer
+pub struct Mask {
+    pub x: i64,
+    pub y: i64,
+    pub width: u32,
+    pub height: u32,
+    pub values: Vec<u8>,
+}
+
+impl Mask {
+    // Everything outside of the rectangle is masked away
+    fn value_at(&self, x: i64, y: i64) -> u8 {
+        let local_x = x - self.x;
+        let local_y = y - self.y;
+        if local_x < 0
+            || local_y < 0
+            || local_x >= self.width as i64
+            || local_y >= self.height as i64
+        {
+            return 0;
+        }
+        self.values[(local_y * self.width as i64 + local_x) as usize]
+    }
+}
+
+// The mask image is scaled to the rectangle of the layer that it masks, then
+// moved onto the canvas with the same transform as that layer
+pub fn prepare_mask(
+    layer: &MaskLayer,
+    x: f32,
+    y: f32,
+    width: u32,
+    height: u32,
+    rotation: f32,
+    options: &ComposeOptions,
+) -> Result<Mask, ErrorWithBacktrace> {
+    let loaded = options.assets.get_or_decode(
+        &layer.src,
+        options.network_timeout,
+        options.max_source_pixels,
+    )?;
+    let image = &loaded.image;
+    let scaled = scale_bilinear(&image.data, image.width, image.height, width, height);
+
+    let local: Vec<u8> = scaled
+        .chunks_exact(4)
+        .map(|pixel| match layer.mode {
+            MaskMode::Alpha => pixel[3],
+            // Rec. 709 luma, transparent pixels mask away like black ones
+            MaskMode::Luminance => {
+                let luma =
+                    0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32;
+                (luma * pixel[3] as f32 / 255.0).round() as u8
+            }
+        })
+        .collect();
+    if local.is_empty() {
+        return Ok(Mask {
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            values: local,
+        });
+    }
+
+    // The edges are extended instead of fading out, the layer itself already
+    // fades out there
+    let sample = |local_x: i64, local_y: i64| {
+        let local_x = local_x.clamp(0, width as i64 - 1);
+        let local_y = local_y.clamp(0, height as i64 - 1);
+        [0, 0, 0, local[(local_y * width as i64 + local_x) as usize]]
+    };
+    let transform = LayerTransform::new(x, y, width, height, rotation);
+    let (left, top, right, bottom) = transform.bounds();
+    let values = (top..bottom)
+        .flat_map(|canvas_y| (left..right).map(move |canvas_x| (canvas_x, canvas_y)))
+        .map(|(canvas_x, canvas_y)| {
+            let value = transform.sample(&sample, canvas_x, canvas_y)[3];
+            value.round().clamp(0.0, 255.0) as u8
+        })
+        .collect();
+
+    Ok(Mask {
+        x: left,
+        y: top,
+        width: (right - left) as u32,
+        height: (bottom - top) as u32,
+        values,
+    })
+}
+
+// Interpolates between the canvas before and after the layer was drawn,
+// which is the same as multiplying the alpha of the layer with the mask.
+// Done on premultiplied values, like scale_bilinear().
+fn mix(before: &[u8], after: &[u8], amount: f32) -> [u8; 4] {
+    let alpha_before = before[3] as f32 / 255.0;
+    let alpha_after = after[3] as f32 / 255.0;
+    let alpha = alpha_before + (alpha_after - alpha_before) * amount;
+    if alpha <= 0.0 {
+        return [0, 0, 0, 0];
+    }
+
+    let mut mixed = [0; 4];
+    for channel in 0..3 {
+        let premultiplied_before = before[channel] as f32 * alpha_before;
+        let premultiplied_after = after[channel] as f32 * alpha_after;
+        let premultiplied =
+            premultiplied_before + (premultiplied_after - premultiplied_before) * amount;
+        mixed[channel] = (premultiplied / alpha).round().clamp(0.0, 255.0) as u8;
+    }
+    mixed[3] = (alpha * 255.0).round() as u8;
+    mixed
+}
+
+// `before` is a copy of the band from before the masked layer was drawn
+pub fn apply_mask(band: &mut Band, before: &[u8], mask: &Mask) {
+    let canvas_width = band.canvas_width as i64;
+    let band_y = band.y as i64;
+
+    for (index, (pixel, before)) in band
+        .data
+        .chunks_exact_mut(4)
+        .zip(before.chunks_exact(4))
+        .enumerate()
+    {
+        let value = mask.value_at(
+            index as i64 % canvas_width,
+            band_y + index as i64 / canvas_width,
+        );
+        match value {
+            255 => {}
+            0 => pixel.copy_from_slice(before),
+            _ => pixel.copy_from_slice(&mix(before, pixel, value as f32 / 255.0)),
+        }
+    }
+}
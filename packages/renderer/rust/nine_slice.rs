@@ -0,0 +1,84 @@
+use crate::{
+    errors::ErrorWithBacktrace,
+    image::{crop_image, RgbaImage},
+    payloads::payloads::{NineSlice, ScaleMode},
+    scaling::scale,
+};
+
+// The start and size of the three slices along one axis. If the layer is smaller
+// than both insets together, the insets shrink proportionally and the center
+// disappears.
+fn get_slices(start_inset: u32, end_inset: u32, total: u32) -> [(u32, u32); 3] {
+    let insets = start_inset as u64 + end_inset as u64;
+    let (start, end) = match insets > total as u64 {
+        true => {
+            let start = (start_inset as u64 * total as u64 / insets) as u32;
+            (start, total - start)
+        }
+        false => (start_inset, end_inset),
+    };
+    [(0, start), (start, total - start - end), (total - end, end)]
+}
+
+fn validate_nine_slice(source: &RgbaImage, slice: &NineSlice) -> Result<(), ErrorWithBacktrace> {
+    if slice.left as u64 + slice.right as u64 > source.width as u64 {
+        return Err(ErrorWithBacktrace::from(format!(
+            "nine_slice insets left = {} and right = {} exceed the width of the source, which is {}",
+            slice.left, slice.right, source.width
+        )));
+    }
+    if slice.top as u64 + slice.bottom as u64 > source.height as u64 {
+        return Err(ErrorWithBacktrace::from(format!(
+            "nine_slice insets top = {} and bottom = {} exceed the height of the source, which is {}",
+            slice.top, slice.bottom, source.height
+        )));
+    }
+    Ok(())
+}
+
+// Scales the source to `width` x `height` with the corners at their original
+// size. The top and bottom edges only stretch horizontally, the left and right
+// edges only vertically, and the center in both directions.
+// Every slice is scaled on its own, so that no pixels bleed into a neighbouring slice.
+pub fn nine_slice_image(
+    source: &RgbaImage,
+    slice: &NineSlice,
+    scaling: &ScaleMode,
+    width: u32,
+    height: u32,
+) -> Result<Vec<u8>, ErrorWithBacktrace> {
+    validate_nine_slice(source, slice)?;
+
+    let source_columns = get_slices(slice.left, slice.right, source.width);
+    let source_rows = get_slices(slice.top, slice.bottom, source.height);
+    let columns = get_slices(slice.left, slice.right, width);
+    let rows = get_slices(slice.top, slice.bottom, height);
+
+    let mut data = vec![0; (width * height * 4) as usize];
+    for ((source_y, source_height), (y, slice_height)) in source_rows.into_iter().zip(rows) {
+        for ((source_x, source_width), (x, slice_width)) in source_columns.into_iter().zip(columns)
+        {
+            // A center without pixels in the source stays transparent
+            if source_width == 0 || source_height == 0 || slice_width == 0 || slice_height == 0 {
+                continue;
+            }
+
+            let cropped = crop_image(source, source_x, source_y, source_width, source_height)?;
+            let scaled = scale(
+                scaling,
+                &cropped.data,
+                source_width,
+                source_height,
+                slice_width,
+                slice_height,
+            );
+            let row_size = (slice_width * 4) as usize;
+            for (row, scaled_row) in scaled.chunks_exact(row_size).enumerate() {
+                let start = (((y + row as u32) * width + x) * 4) as usize;
+                data[start..start + row_size].copy_from_slice(scaled_row);
+            }
+        }
+    }
+
+    Ok(data)
+}
@@ -74,6 +74,10 @@ impl OpenedVideoManager {
         Ok(self.videos.read()?.get(src).unwrap().clone())
     }
 
+    pub fn is_video_open(&self, src: &str) -> Result<bool, ErrorWithBacktrace> {
+        Ok(self.videos.read()?.contains_key(src))
+    }
+
     pub fn remove_video(&self, src: &str) -> Result<(), ErrorWithBacktrace> {
         {
             self.videos
@@ -4,25 +4,370 @@ extern crate serde;
 extern crate serde_json;
 
 pub mod payloads {
-    use crate::errors::ErrorWithBacktrace;
-    use serde::{Deserialize, Serialize};
+    use crate::errors::{ErrorWithBacktrace, ParseError};
+    use serde::{de, Deserialize, Deserializer, Serialize};
+    use std::fmt;
+
+    // RGBA color. Accepts either [r, g, b, a] or a hex string
+    // in the form of "#rgb", "#rrggbb" or "#rrggbbaa"
+    #[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+    pub struct Color(pub [u8; 4]);
+
+    fn parse_hex_color(value: &str) -> Option<[u8; 4]> {
+        let hex = value.strip_prefix('#')?;
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        let channel = |index: usize| u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok();
+        let short_channel = |index: usize| {
+            u8::from_str_radix(&hex[index..index + 1], 16)
+                .ok()
+                .map(|value| value * 17)
+        };
+
+        match hex.len() {
+            3 => Some([short_channel(0)?, short_channel(1)?, short_channel(2)?, 255]),
+            6 => Some([channel(0)?, channel(1)?, channel(2)?, 255]),
+            8 => Some([channel(0)?, channel(1)?, channel(2)?, channel(3)?]),
+            _ => None,
+        }
+    }
+
+    struct ColorVisitor;
+
+    impl<'de> de::Visitor<'de> for ColorVisitor {
+        type Value = Color;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("an array of 4 numbers or a hex color like \"#rrggbb\"")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Color, E> {
+            match parse_hex_color(value) {
+                Some(color) => Ok(Color(color)),
+                None => Err(E::custom(format!(
+                    "invalid hex color \"{}\", expected \"#rgb\", \"#rrggbb\" or \"#rrggbbaa\"",
+                    value
+                ))),
+            }
+        }
+
+        fn visit_seq<A: de::SeqAccess<'de>>(self, seq: A) -> Result<Color, A::Error> {
+            let color = <[u8; 4]>::deserialize(de::value::SeqAccessDeserializer::new(seq))?;
+            Ok(Color(color))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Color {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+            deserializer.deserialize_any(ColorVisitor)
+        }
+    }
+
+    fn default_opacity() -> f32 {
+        1.0
+    }
+
+    // Brightness and contrast of 1 leave the source unchanged
+    fn default_adjustment() -> f32 {
+        1.0
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+    pub enum FitMode {
+        // Distorts the source to fill the layer
+        #[default]
+        Stretch,
+        // Scales the source to fit inside of the layer and centers it
+        Contain,
+        // Scales the source to fill the layer and crops what overflows
+        Cover,
+    }
+
+    // How the source of an image layer is scaled to its size
+    #[derive(Serialize, Deserialize, Debug, Default)]
+    pub enum ScaleMode {
+        // Each pixel takes the source pixel in which its center lies,
+        // which keeps edges crisp for any scale factor
+        Nearest,
+        // Interpolates between the 2x2 source pixels around the center of each pixel,
+        // on premultiplied values. Downscaling by more than 2x averages the
+        // covered source pixels first, so that none are skipped.
+        #[default]
+        Bilinear,
+    }
+
+    // How the colors of a layer are combined with the canvas below it.
+    // The result is blended over the canvas according to the alpha of the layer.
+    #[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+    pub enum BlendMode {
+        #[default]
+        Normal,
+        Multiply,
+        Screen,
+        // Multiply on dark parts of the canvas, screen on bright parts
+        Overlay,
+        // Sums the channels, clipped to white
+        Add,
+    }
+
+    // Drop shadow in the alpha shape of the layer, drawn underneath it
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct Shadow {
+        #[serde(default)]
+        pub offset_x: f32,
+        #[serde(default)]
+        pub offset_y: f32,
+        // Blur radius in pixels, 0 gives a hard shadow
+        #[serde(default)]
+        pub blur: u32,
+        pub color: Color,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+    pub enum BorderPosition {
+        // Covers the edge of the layer, the size of the layer stays the same
+        #[default]
+        Inner,
+        // Drawn around the layer, making it bigger by the border width on each side
+        Outer,
+    }
+
+    // Anti-aliased stroke that follows the shape of the layer, drawn on top of it
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct Border {
+        // 0 draws no border
+        #[serde(default)]
+        pub width: u32,
+        pub color: Color,
+        #[serde(default)]
+        pub position: BorderPosition,
+    }
+
+    // Pins the same point of the layer to a point of the canvas, see anchor.rs
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+    pub enum Anchor {
+        TopLeft,
+        Top,
+        TopRight,
+        Left,
+        Center,
+        Right,
+        BottomLeft,
+        Bottom,
+        BottomRight,
+    }
+
+    // Pixels from the edges of the source that make up the corners and edges
+    // of a nine-slice scaled image
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct NineSlice {
+        pub left: u32,
+        pub right: u32,
+        pub top: u32,
+        pub bottom: u32,
+    }
 
     #[derive(Serialize, Deserialize, Debug)]
     pub struct ImageLayer {
         pub src: String,
-        pub x: u32,
-        pub y: u32,
+        // Fractional positions are drawn with subpixel sampling
+        pub x: f32,
+        pub y: f32,
+        // Makes x and y an offset from this point of the canvas
+        #[serde(default)]
+        pub anchor: Option<Anchor>,
         pub width: u32,
         pub height: u32,
+        #[serde(default = "default_opacity")]
+        pub opacity: f32,
+        // Degrees, clockwise, around the center of the layer
+        #[serde(default)]
+        pub rotation: f32,
+        // Region of the source image, applied before scaling to width and height
+        pub crop_x: Option<u32>,
+        pub crop_y: Option<u32>,
+        pub crop_width: Option<u32>,
+        pub crop_height: Option<u32>,
+        // Mirrors the source, applied after cropping and before scaling
+        #[serde(default)]
+        pub flip_h: bool,
+        #[serde(default)]
+        pub flip_v: bool,
+        #[serde(default)]
+        pub scaling: ScaleMode,
+        // How the source is fit into width and height if the aspect ratios differ
+        #[serde(default)]
+        pub fit: FitMode,
+        // Fills the bars that Contain leaves next to the source, transparent by default
+        #[serde(default)]
+        pub pad_color: Option<Color>,
+        // Keeps the corners of the source at their size when scaling, only with fit = Stretch
+        #[serde(default)]
+        pub nine_slice: Option<NineSlice>,
+        // Mixes the source with its grayscale version, 0 leaves it unchanged and 1
+        // removes all color
+        #[serde(default)]
+        pub grayscale: f32,
+        // Multiplies the color channels, 0.5 halves the brightness
+        #[serde(default = "default_adjustment")]
+        pub brightness: f32,
+        // Scales the distance of the color channels from the middle gray, 0 gives gray
+        #[serde(default = "default_adjustment")]
+        pub contrast: f32,
+        // Multiplies every channel of the source, white leaves it unchanged
+        #[serde(default)]
+        pub tint: Option<Color>,
+        #[serde(default)]
+        pub blend_mode: BlendMode,
+        #[serde(default)]
+        pub shadow: Option<Shadow>,
+        #[serde(default)]
+        pub border: Option<Border>,
+        // Paint order, see compositor::sort_by_z_index()
+        #[serde(default)]
+        pub z_index: Option<i32>,
     }
 
     #[derive(Serialize, Deserialize, Debug)]
     pub struct SolidLayer {
-        pub fill: [u8; 4],
-        pub x: u32,
-        pub y: u32,
+        pub fill: Color,
+        // Fractional positions are drawn with subpixel sampling
+        pub x: f32,
+        pub y: f32,
+        #[serde(default)]
+        pub anchor: Option<Anchor>,
         pub width: u32,
         pub height: u32,
+        #[serde(default = "default_opacity")]
+        pub opacity: f32,
+        // Degrees, clockwise, around the center of the layer
+        #[serde(default)]
+        pub rotation: f32,
+        // Anti-aliased rounded corners, 0 draws a sharp rectangle
+        #[serde(default)]
+        pub corner_radius: u32,
+        #[serde(default)]
+        pub blend_mode: BlendMode,
+        #[serde(default)]
+        pub shadow: Option<Shadow>,
+        #[serde(default)]
+        pub border: Option<Border>,
+        #[serde(default)]
+        pub z_index: Option<i32>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct TextLayer {
+        pub text: String,
+        pub x: i32,
+        pub y: i32,
+        // Resolved with the size of the laid out text
+        #[serde(default)]
+        pub anchor: Option<Anchor>,
+        pub font_size: u32,
+        pub color: Color,
+        pub font_path: Option<String>,
+        #[serde(default)]
+        pub z_index: Option<i32>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub enum GradientDirection {
+        Horizontal,
+        Vertical,
+        // From the top left to the bottom right corner
+        Diagonal,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct GradientLayer {
+        pub x: i32,
+        pub y: i32,
+        #[serde(default)]
+        pub anchor: Option<Anchor>,
+        pub width: u32,
+        pub height: u32,
+        pub start_color: Color,
+        pub end_color: Color,
+        pub direction: GradientDirection,
+        #[serde(default)]
+        pub blend_mode: BlendMode,
+        #[serde(default)]
+        pub z_index: Option<i32>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct VideoLayer {
+        pub src: String,
+        pub time: f64,
+        pub x: i32,
+        pub y: i32,
+        #[serde(default)]
+        pub anchor: Option<Anchor>,
+        pub width: u32,
+        pub height: u32,
+        #[serde(default = "default_opacity")]
+        pub opacity: f32,
+        // Degrees, clockwise, around the center of the layer
+        #[serde(default)]
+        pub rotation: f32,
+        #[serde(default)]
+        pub blend_mode: BlendMode,
+        #[serde(default)]
+        pub z_index: Option<i32>,
+    }
+
+    // Filled ellipse inscribed into the rectangle
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct EllipseLayer {
+        pub x: i32,
+        pub y: i32,
+        #[serde(default)]
+        pub anchor: Option<Anchor>,
+        pub width: u32,
+        pub height: u32,
+        pub fill: Color,
+        #[serde(default = "default_opacity")]
+        pub opacity: f32,
+        #[serde(default)]
+        pub border: Option<Border>,
+        #[serde(default)]
+        pub z_index: Option<i32>,
+    }
+
+    // Blurs what is already on the canvas within the rectangle
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct BlurLayer {
+        pub x: i32,
+        pub y: i32,
+        #[serde(default)]
+        pub anchor: Option<Anchor>,
+        pub width: u32,
+        pub height: u32,
+        pub radius: u32,
+        #[serde(default)]
+        pub z_index: Option<i32>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+    pub enum MaskMode {
+        #[default]
+        Alpha,
+        Luminance,
+    }
+
+    // Masks the layer that is painted after it with a PNG or JPEG, which is
+    // scaled to the size of that layer and follows its position and rotation.
+    // The mask itself is not drawn.
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct MaskLayer {
+        pub src: String,
+        #[serde(default)]
+        pub mode: MaskMode,
+        #[serde(default)]
+        pub z_index: Option<i32>,
     }
 
     #[derive(Serialize, Deserialize, Debug)]
@@ -31,27 +376,162 @@ pub mod payloads {
         PngImage(ImageLayer),
         JpgImage(ImageLayer),
         Solid(SolidLayer),
+        Text(TextLayer),
+        Gradient(GradientLayer),
+        Video(VideoLayer),
+        Blur(BlurLayer),
+        Ellipse(EllipseLayer),
+        Mask(MaskLayer),
     }
 
     #[derive(Serialize, Debug)]
     pub struct ErrorPayload {
         pub error: String,
+        // Same as the exit code, see errors::ErrorCode
+        pub code: u32,
+        // Only sent when backtraces are enabled, see errors::set_backtraces()
+        #[serde(skip_serializing_if = "String::is_empty")]
         pub backtrace: String,
     }
 
+    #[derive(Serialize, Debug)]
+    pub struct SuccessPayload {
+        pub output: String,
+        pub width: u32,
+        pub height: u32,
+        pub format: String,
+        pub bytes_written: usize,
+        // Only for ExtractFrame, the timestamp of the decoded frame in seconds
+        pub frame_timestamp: Option<f64>,
+        // Only for ExtractFrame, if the frame was requested by its number
+        pub frame: Option<u64>,
+        pub warnings: Vec<String>,
+    }
+
     #[derive(Serialize, Deserialize, Debug)]
     pub enum ImageFormat {
         Png,
         Jpeg,
+        WebP,
+        Avif,
+        Gif,
     }
 
+    fn default_quality() -> u8 {
+        90
+    }
+
+    fn default_background() -> Color {
+        Color([0, 0, 0, 0])
+    }
+
+    fn default_bit_depth() -> u8 {
+        8
+    }
+
+    fn default_delay_ms() -> u32 {
+        100
+    }
+
+    fn default_network_timeout_ms() -> u64 {
+        30_000
+    }
+
+    // Embedded into PNG and JPEG output so that color managed viewers
+    // don't have to guess how to interpret the pixels
     #[derive(Serialize, Deserialize, Debug)]
-    pub struct CliGenerateImageCommand {
+    pub enum ColorProfile {
+        Srgb,
+        // Path to an .icc file that is embedded verbatim
+        Icc(String),
+    }
+
+    // Resolution of the color channels relative to the brightness
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+    pub enum ChromaSubsampling {
+        // Full resolution, keeps colored edges of text crisp
+        S444,
+        // Half the horizontal resolution
+        S422,
+        // Half the horizontal and vertical resolution, the smallest files
+        S420,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct ExportCrop {
+        pub x: u32,
+        pub y: u32,
         pub width: u32,
         pub height: u32,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct CliGenerateImageCommand {
+        // Can be omitted with a base_image, which then determines the size
+        #[serde(default)]
+        pub width: Option<u32>,
+        #[serde(default)]
+        pub height: Option<u32>,
+        #[serde(default)]
         pub layers: Vec<Layer>,
+        // Instead of `layers`, every item is composed into one frame of an animated GIF
+        #[serde(default)]
+        pub frames: Option<Vec<Vec<Layer>>>,
+        // How long each frame of an animated GIF is shown
+        #[serde(default = "default_delay_ms")]
+        pub delay_ms: u32,
         pub output_format: ImageFormat,
         pub output: String,
+        // Only used for lossy formats, ignored for PNG
+        #[serde(default = "default_quality")]
+        pub quality: u8,
+        // Only used for JPEG, ignored for other formats. Without it, the encoder
+        // uses 4:2:0 below quality 90 and 4:4:4 from quality 90.
+        #[serde(default)]
+        pub chroma_subsampling: Option<ChromaSubsampling>,
+        // AVIF encoding speed, 0-10. Slower speeds compress better
+        #[serde(default)]
+        pub speed: Option<u8>,
+        // Deadline for downloading each http(s) source, including the body
+        #[serde(default = "default_network_timeout_ms")]
+        pub network_timeout_ms: u64,
+        // The canvas is filled with this color before the layers are drawn.
+        // JPEG output is flattened onto its RGB values, so a transparent
+        // background turns into the opaque version of the color.
+        #[serde(default = "default_background")]
+        pub background: Color,
+        // Density metadata for PNG and JPEG, the pixel dimensions stay the same
+        #[serde(default)]
+        pub dpi: Option<u32>,
+        // PNG or JPEG that the layers are drawn onto. It is scaled to
+        // width and height if they are given.
+        #[serde(default)]
+        pub base_image: Option<String>,
+        // Reject the command before anything is allocated if the canvas or the
+        // amount of layers is larger. Fall back to the REMOTION_COMPOSITOR_MAX_PIXELS
+        // and REMOTION_COMPOSITOR_MAX_LAYERS environment variables.
+        #[serde(default)]
+        pub max_pixels: Option<u64>,
+        #[serde(default)]
+        pub max_layers: Option<usize>,
+        // Image sources whose header declares more pixels are rejected before they
+        // are decoded. Falls back to REMOTION_COMPOSITOR_MAX_SOURCE_PIXELS, then to
+        // 100 megapixels.
+        #[serde(default)]
+        pub max_source_pixels: Option<u64>,
+        // Video URLs that are larger are rejected while they are downloaded. Falls
+        // back to REMOTION_COMPOSITOR_MAX_DOWNLOAD_BYTES, then to 4 GiB.
+        #[serde(default)]
+        pub max_download_bytes: Option<u64>,
+        // Bits per channel, 16 is only supported for PNG
+        #[serde(default = "default_bit_depth")]
+        pub bit_depth: u8,
+        #[serde(default)]
+        pub color_profile: Option<ColorProfile>,
+        // Window of the composed canvas that is encoded, must be inside of the canvas.
+        // The success payload reports its size.
+        #[serde(default)]
+        pub export_crop: Option<ExportCrop>,
     }
 
     #[derive(Serialize, Deserialize, Debug)]
@@ -59,13 +539,48 @@ pub mod payloads {
         pub src: String,
     }
 
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct ComposeBatchCommand {
+        // Sources that are decoded once and reused by every output that refers to them
+        pub assets: Vec<String>,
+        pub outputs: Vec<CliGenerateImageCommand>,
+        #[serde(default = "default_network_timeout_ms")]
+        pub network_timeout_ms: u64,
+        // Applies to the assets, every output has its own limit for its other sources
+        #[serde(default)]
+        pub max_source_pixels: Option<u64>,
+    }
+
     #[derive(Serialize, Deserialize, Debug)]
     pub struct ExtractFrameCommand {
         pub src: String,
         pub original_src: String,
-        pub time: f64,
+        // Either `time` in seconds, or `frame` together with `fps` must be passed
+        #[serde(default)]
+        pub time: Option<f64>,
+        #[serde(default)]
+        pub frame: Option<u64>,
+        #[serde(default)]
+        pub fps: Option<f64>,
+        pub transparent: bool,
+        pub tone_mapped: bool,
+        // If set, the frame is written to this path ("-" for stdout) instead of being returned
+        #[serde(default)]
+        pub output: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct ExtractFramesCommand {
+        pub src: String,
+        pub original_src: String,
+        pub times: Vec<f64>,
+        // {index} is replaced with the zero-padded position of the time in `times`
+        pub output_pattern: String,
         pub transparent: bool,
         pub tone_mapped: bool,
+        // Prints a progress line to stderr after every frame
+        #[serde(default)]
+        pub progress: bool,
     }
 
     #[derive(Serialize, Deserialize, Debug)]
@@ -81,11 +596,17 @@ pub mod payloads {
         pub concurrency: usize,
         pub maximum_frame_cache_size_in_bytes: Option<u128>,
         pub verbose: bool,
+        // Decoded image sources that are kept for the following commands
+        #[serde(default)]
+        pub asset_cache_size_in_bytes: Option<u64>,
     }
 
     #[derive(Serialize, Deserialize, Debug)]
     pub struct GetOpenVideoStats {}
 
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct GetAssetCacheStats {}
+
     #[derive(Serialize, Deserialize, Debug)]
     pub struct DeliberatePanic {}
 
@@ -99,6 +620,16 @@ pub mod payloads {
         pub frames_in_cache: usize,
     }
 
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct AssetCacheStats {
+        // Hits skipped reading and decoding the source
+        pub hits: u64,
+        pub misses: u64,
+        pub entries: usize,
+        pub size_in_bytes: u64,
+        pub capacity_in_bytes: u64,
+    }
+
     #[derive(Serialize, Deserialize, Debug)]
     pub enum KnownCodecs {
         #[serde(rename = "h264")]
@@ -266,21 +797,123 @@ pub mod payloads {
         pub output_path: String,
     }
 
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct Probe {
+        pub input: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct ProbeResult {
+        pub width: u32,
+        pub height: u32,
+        pub duration_seconds: f64,
+        pub fps: f64,
+        pub frame_count: u64,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct ExtractSpriteSheetCommand {
+        pub input: String,
+        pub columns: u32,
+        pub rows: u32,
+        pub thumb_width: u32,
+        pub thumb_height: u32,
+        // Seconds, the whole video by default
+        #[serde(default)]
+        pub start: Option<f64>,
+        #[serde(default)]
+        pub end: Option<f64>,
+        pub output: String,
+        pub output_format: ImageFormat,
+        #[serde(default = "default_quality")]
+        pub quality: u8,
+        // Like max_pixels of Compose, checked before the sprite sheet is allocated
+        #[serde(default)]
+        pub max_pixels: Option<u64>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct SpriteSheetCell {
+        pub column: u32,
+        pub row: u32,
+        // Top left corner of the cell in the sprite sheet
+        pub x: u32,
+        pub y: u32,
+        pub frame: u64,
+        pub timestamp: f64,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct SpriteSheetPayload {
+        pub output: String,
+        pub width: u32,
+        pub height: u32,
+        pub format: String,
+        pub bytes_written: usize,
+        // The grid that was actually filled, rows without cells are left out
+        pub columns: u32,
+        pub rows: u32,
+        pub thumb_width: u32,
+        pub thumb_height: u32,
+        // Fewer than columns * rows if the range has fewer frames
+        pub cell_count: u32,
+        // Left to right, top to bottom
+        pub cells: Vec<SpriteSheetCell>,
+    }
+
+    fn default_waveform_sample_rate() -> u32 {
+        8000
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct GetAudioWaveform {
+        pub input: String,
+        pub start: f64,
+        pub duration: f64,
+        #[serde(default = "default_waveform_sample_rate")]
+        pub sample_rate: u32,
+        // Returns the lowest and highest sample of this many buckets instead of every sample
+        #[serde(default)]
+        pub envelope_buckets: Option<u32>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct EnvelopeBucket {
+        pub min: f32,
+        pub max: f32,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct AudioWaveform {
+        // false for videos without an audio track, the samples are then empty
+        pub has_audio: bool,
+        pub sample_rate: u32,
+        // Mono samples between -1 and 1, empty if envelope_buckets was passed
+        pub samples: Vec<f32>,
+        pub envelope: Option<Vec<EnvelopeBucket>>,
+    }
+
     #[derive(Serialize, Deserialize, Debug)]
     #[serde(tag = "type", content = "params")]
     pub enum CliInputCommandPayload {
         ExtractFrame(ExtractFrameCommand),
+        ExtractFrames(ExtractFramesCommand),
         Compose(CliGenerateImageCommand),
+        ComposeBatch(ComposeBatchCommand),
         StartLongRunningProcess(StartPayLoad),
         DeliberatePanic(DeliberatePanic),
         CloseAllVideos(CloseAllVideos),
         GetOpenVideoStats(GetOpenVideoStats),
+        GetAssetCacheStats(GetAssetCacheStats),
         FreeUpMemory(FreeUpMemory),
         Echo(EchoPayload),
         GetVideoMetadata(GetVideoMetadata),
         CopyImageToClipboard(CopyImageToClipboard),
         GetSilences(GetSilences),
         ExtractAudio(ExtractAudio),
+        Probe(Probe),
+        GetAudioWaveform(GetAudioWaveform),
+        ExtractSpriteSheet(ExtractSpriteSheetCommand),
     }
 
     #[derive(Serialize, Deserialize, Debug)]
@@ -289,9 +922,55 @@ pub mod payloads {
         pub nonce: String,
     }
 
+    pub fn try_parse_cli(json: &str) -> Result<CliInputCommand, ParseError> {
+        let err = match serde_json::from_str::<CliInputCommand>(json) {
+            Ok(cli_input) => return Ok(cli_input),
+            Err(err) => err,
+        };
+
+        if err.is_syntax() || err.is_eof() {
+            return Err(ParseError::MalformedJson(err));
+        }
+
+        let command = serde_json::from_str::<serde_json::Value>(json)
+            .ok()
+            .and_then(|value| {
+                value
+                    .get("payload")
+                    .and_then(|payload| payload.get("type"))
+                    .and_then(|command| command.as_str())
+                    .map(|command| command.to_string())
+            });
+
+        match command {
+            Some(command)
+                if err
+                    .to_string()
+                    .contains(&format!("unknown variant `{}`", command)) =>
+            {
+                Err(ParseError::UnknownCommand(command, err))
+            }
+            _ => Err(ParseError::InvalidPayload(err)),
+        }
+    }
+
     pub fn parse_cli(json: &str) -> Result<CliInputCommand, ErrorWithBacktrace> {
-        let cli_input: CliInputCommand = serde_json::from_str(json)?;
+        let cli_input: CliInputCommand = try_parse_cli(json)?;
 
         return Ok(cli_input);
     }
+
+    // Best effort to find the nonce of a command that could not be parsed,
+    // so that the error can still be sent to the caller that is waiting for it.
+    pub fn parse_nonce(json: &str) -> String {
+        serde_json::from_str::<serde_json::Value>(json)
+            .ok()
+            .and_then(|value| {
+                value
+                    .get("nonce")
+                    .and_then(|nonce| nonce.as_str())
+                    .map(|nonce| nonce.to_string())
+            })
+            .unwrap_or("0".to_string())
+    }
 }
@@ -0,0 +1,228 @@
+use crate::payloads::payloads::ScaleMode;
+
+fn write_unpremultiplied(pixel: &mut [u8], premultiplied: [f32; 4]) {
+    if premultiplied[3] > 0.0 {
+        for channel in 0..3 {
+            pixel[channel] = (premultiplied[channel] / premultiplied[3])
+                .round()
+                .clamp(0.0, 255.0) as u8;
+        }
+    }
+    pixel[3] = premultiplied[3].round().clamp(0.0, 255.0) as u8;
+}
+
+// The source pixels that each target pixel covers when an axis is reduced from
+// `source` to `target` pixels, together with the share of each of them
+fn get_area_weights(source: u32, target: u32) -> Vec<Vec<(usize, f32)>> {
+    let ratio = source as f64 / target as f64;
+    (0..target)
+        .map(|index| {
+            let start = index as f64 * ratio;
+            let end = ((index + 1) as f64 * ratio).min(source as f64);
+            (start.floor() as usize..end.ceil() as usize)
+                .map(|source_index| {
+                    let overlap =
+                        end.min(source_index as f64 + 1.0) - start.max(source_index as f64);
+                    (source_index, (overlap / ratio) as f32)
+                })
+                .filter(|(_, weight)| *weight > 0.0)
+                .collect()
+        })
+        .collect()
+}
+
+// Averages all source pixels that a target pixel covers, one axis after the
+// other. Like scale_bilinear(), on premultiplied values.
+fn downscale_area(
+    data: &[u8],
+    source_width: u32,
+    source_height: u32,
+    target_width: u32,
+    target_height: u32,
+) -> Vec<u8> {
+    let columns = get_area_weights(source_width, target_width);
+    let rows = get_area_weights(source_height, target_height);
+    let source_width = source_width as usize;
+    let target_width = target_width as usize;
+
+    // Every source row, reduced to target_width premultiplied pixels
+    let mut reduced_rows = vec![0.0_f32; source_height as usize * target_width * 4];
+    for (y, reduced_row) in reduced_rows.chunks_exact_mut(target_width * 4).enumerate() {
+        for (reduced, weights) in reduced_row.chunks_exact_mut(4).zip(&columns) {
+            for &(source_x, weight) in weights {
+                let index = (y * source_width + source_x) * 4;
+                let alpha = data[index + 3] as f32 * weight;
+                reduced[0] += data[index] as f32 * alpha;
+                reduced[1] += data[index + 1] as f32 * alpha;
+                reduced[2] += data[index + 2] as f32 * alpha;
+                reduced[3] += alpha;
+            }
+        }
+    }
+
+    let mut scaled = vec![0; target_width * target_height as usize * 4];
+    for (row, weights) in scaled.chunks_exact_mut(target_width * 4).zip(&rows) {
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let mut premultiplied = [0.0_f32; 4];
+            for &(source_y, weight) in weights {
+                let index = (source_y * target_width + x) * 4;
+                for channel in 0..4 {
+                    premultiplied[channel] += reduced_rows[index + channel] * weight;
+                }
+            }
+            write_unpremultiplied(pixel, premultiplied);
+        }
+    }
+
+    scaled
+}
+
+// Bilinear only looks at the 2x2 source pixels around a target pixel, so an
+// axis that is reduced to less than half of its size is first averaged down to
+// twice the target size. Halving it then still takes every pixel into account.
+fn get_prefilter_size(source: u32, target: u32) -> u32 {
+    match source as u64 > target as u64 * 2 {
+        true => target * 2,
+        false => source,
+    }
+}
+
+// Bilinear scaling of an RGBA buffer.
+// Pixel centers are aligned and the interpolation is done on premultiplied
+// values, so that transparent pixels don't bleed dark fringes into the image.
+// Downscaling by more than 2x averages the covered pixels first.
+pub fn scale_bilinear(
+    data: &[u8],
+    source_width: u32,
+    source_height: u32,
+    target_width: u32,
+    target_height: u32,
+) -> Vec<u8> {
+    if source_width == target_width && source_height == target_height {
+        return data.to_vec();
+    }
+
+    let mut scaled = vec![0; (target_width * target_height * 4) as usize];
+    if source_width == 0 || source_height == 0 || scaled.is_empty() {
+        return scaled;
+    }
+
+    let prefilter_width = get_prefilter_size(source_width, target_width);
+    let prefilter_height = get_prefilter_size(source_height, target_height);
+    if (prefilter_width, prefilter_height) != (source_width, source_height) {
+        let prefiltered = downscale_area(
+            data,
+            source_width,
+            source_height,
+            prefilter_width,
+            prefilter_height,
+        );
+        return scale_bilinear(
+            &prefiltered,
+            prefilter_width,
+            prefilter_height,
+            target_width,
+            target_height,
+        );
+    }
+
+    let x_ratio = source_width as f32 / target_width as f32;
+    let y_ratio = source_height as f32 / target_height as f32;
+
+    for y in 0..target_height {
+        let source_y = ((y as f32 + 0.5) * y_ratio - 0.5).max(0.0);
+        let y0 = (source_y.floor() as u32).min(source_height - 1);
+        let y1 = (y0 + 1).min(source_height - 1);
+        let fy = source_y - y0 as f32;
+
+        for x in 0..target_width {
+            let source_x = ((x as f32 + 0.5) * x_ratio - 0.5).max(0.0);
+            let x0 = (source_x.floor() as u32).min(source_width - 1);
+            let x1 = (x0 + 1).min(source_width - 1);
+            let fx = source_x - x0 as f32;
+
+            let neighbours = [
+                (x0, y0, (1.0 - fx) * (1.0 - fy)),
+                (x1, y0, fx * (1.0 - fy)),
+                (x0, y1, (1.0 - fx) * fy),
+                (x1, y1, fx * fy),
+            ];
+
+            let mut premultiplied = [0.0_f32; 4];
+            for (nx, ny, weight) in neighbours {
+                let index = ((ny * source_width + nx) * 4) as usize;
+                let alpha = data[index + 3] as f32 * weight;
+                premultiplied[0] += data[index] as f32 * alpha;
+                premultiplied[1] += data[index + 1] as f32 * alpha;
+                premultiplied[2] += data[index + 2] as f32 * alpha;
+                premultiplied[3] += alpha;
+            }
+
+            let index = ((y * target_width + x) * 4) as usize;
+            write_unpremultiplied(&mut scaled[index..index + 4], premultiplied);
+        }
+    }
+
+    scaled
+}
+
+// Takes the source pixel in which the center of the target pixel lies.
+// Pixels are repeated or skipped, but never mixed.
+pub fn scale_nearest(
+    data: &[u8],
+    source_width: u32,
+    source_height: u32,
+    target_width: u32,
+    target_height: u32,
+) -> Vec<u8> {
+    if source_width == target_width && source_height == target_height {
+        return data.to_vec();
+    }
+
+    let mut scaled = vec![0; (target_width * target_height * 4) as usize];
+    if source_width == 0 || source_height == 0 {
+        return scaled;
+    }
+
+    for y in 0..target_height {
+        let source_y = ((y as u64 * 2 + 1) * source_height as u64 / (target_height as u64 * 2))
+            .min(source_height as u64 - 1) as u32;
+
+        for x in 0..target_width {
+            let source_x = ((x as u64 * 2 + 1) * source_width as u64 / (target_width as u64 * 2))
+                .min(source_width as u64 - 1) as u32;
+
+            let source_index = ((source_y * source_width + source_x) * 4) as usize;
+            let index = ((y * target_width + x) * 4) as usize;
+            scaled[index..index + 4].copy_from_slice(&data[source_index..source_index + 4]);
+        }
+    }
+
+    scaled
+}
+
+pub fn scale(
+    mode: &ScaleMode,
+    data: &[u8],
+    source_width: u32,
+    source_height: u32,
+    target_width: u32,
+    target_height: u32,
+) -> Vec<u8> {
+    match mode {
+        ScaleMode::Nearest => scale_nearest(
+            data,
+            source_width,
+            source_height,
+            target_width,
+            target_height,
+        ),
+        ScaleMode::Bilinear => scale_bilinear(
+            data,
+            source_width,
+            source_height,
+            target_width,
+            target_height,
+        ),
+    }
+}
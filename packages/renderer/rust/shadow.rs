@@ -0,0 +1,66 @@
+use crate::{
+    blur::draw_blur_layer,
+    compositor::Bitmap,
+    payloads::payloads::{BlendMode, BlurLayer, Shadow},
+};
+
+// A bitmap with the alpha shape of the layer in the color of the shadow,
+// offset and blurred. It is drawn right before the layer itself.
+// `alpha` returns the alpha of the layer at local coordinates, from 0 to 255.
+// The bitmap is padded by the blur radius on each side so the blur is not cut off,
+// and rotated like the layer around the offset center of the layer.
+pub fn prepare_shadow<F>(
+    shadow: &Shadow,
+    x: f32,
+    y: f32,
+    width: u32,
+    height: u32,
+    rotation: f32,
+    alpha: F,
+) -> Bitmap
+where
+    F: Fn(u32, u32) -> f32,
+{
+    let padding = shadow.blur;
+    let shadow_width = width + 2 * padding;
+    let shadow_height = height + 2 * padding;
+    let color = shadow.color.0;
+
+    let mut data = vec![0; shadow_width as usize * shadow_height as usize * 4];
+    for local_y in 0..height {
+        for local_x in 0..width {
+            let index = (((local_y + padding) * shadow_width + local_x + padding) * 4) as usize;
+            data[index..index + 3].copy_from_slice(&color[..3]);
+            data[index + 3] = (alpha(local_x, local_y) * color[3] as f32 / 255.0)
+                .round()
+                .clamp(0.0, 255.0) as u8;
+        }
+    }
+
+    // A radius of 0 leaves the shadow hard
+    draw_blur_layer(
+        &mut data,
+        shadow_width,
+        shadow_height,
+        &BlurLayer {
+            x: 0,
+            y: 0,
+            anchor: None,
+            width: shadow_width,
+            height: shadow_height,
+            radius: shadow.blur,
+            z_index: None,
+        },
+    );
+
+    Bitmap {
+        x: x + shadow.offset_x - padding as f32,
+        y: y + shadow.offset_y - padding as f32,
+        width: shadow_width,
+        height: shadow_height,
+        data,
+        opacity: 1.0,
+        rotation,
+        blend_mode: BlendMode::Normal,
+    }
+}
@@ -0,0 +1,42 @@
+// Coverage of the pixel at the local coordinates by a rounded rectangle,
+// from 0 (outside) to 1 (inside). Edges within half a pixel are anti-aliased.
+// The radius is clamped to half of the smaller side, which results in a capsule.
+pub fn rounded_rect_coverage(x: i64, y: i64, width: u32, height: u32, radius: u32) -> f32 {
+    let half_width = width as f32 / 2.0;
+    let half_height = height as f32 / 2.0;
+    let radius = (radius as f32).min(half_width).min(half_height);
+
+    // Signed distance from the pixel center to the edge of the shape
+    let qx = (x as f32 + 0.5 - half_width).abs() - (half_width - radius);
+    let qy = (y as f32 + 0.5 - half_height).abs() - (half_height - radius);
+    let outside = (qx.max(0.0).powi(2) + qy.max(0.0).powi(2)).sqrt();
+    let inside = qx.max(qy).min(0.0);
+    let distance = outside + inside - radius;
+
+    (0.5 - distance).clamp(0.0, 1.0)
+}
+
+// Coverage of the pixel at the local coordinates by the ellipse inscribed
+// into the rectangle, anti-aliased like rounded_rect_coverage()
+pub fn ellipse_coverage(x: i64, y: i64, width: u32, height: u32) -> f32 {
+    if width == 0 || height == 0 {
+        return 0.0;
+    }
+
+    let radius_x = width as f32 / 2.0;
+    let radius_y = height as f32 / 2.0;
+    let dx = x as f32 + 0.5 - radius_x;
+    let dy = y as f32 + 0.5 - radius_y;
+
+    // Approximates the distance to the edge by dividing the implicit
+    // function of the ellipse by the length of its gradient
+    let value = (dx / radius_x).powi(2) + (dy / radius_y).powi(2) - 1.0;
+    let gradient =
+        2.0 * ((dx / (radius_x * radius_x)).powi(2) + (dy / (radius_y * radius_y)).powi(2)).sqrt();
+    if gradient == 0.0 {
+        return 1.0;
+    }
+    let distance = value / gradient;
+
+    (0.5 - distance).clamp(0.0, 1.0)
+}
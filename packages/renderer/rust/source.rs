@@ -0,0 +1,265 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+use crate::{
+    errors::{ErrorCode, ErrorWithBacktrace},
+    ffmpeg,
+};
+
+const DATA_URI_PREFIX: &str = "data:";
+
+static DOWNLOAD_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+pub fn is_url(src: &str) -> bool {
+    src.starts_with("http://") || src.starts_with("https://")
+}
+
+pub fn is_data_uri(src: &str) -> bool {
+    src.starts_with(DATA_URI_PREFIX)
+}
+
+pub fn get_mime_type_from_bytes(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some("image/png");
+    }
+    if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+        return Some("image/jpeg");
+    }
+    None
+}
+
+// Data URIs can be megabytes long, so they are shortened in error messages
+pub fn describe_source(src: &str) -> String {
+    match src.starts_with(DATA_URI_PREFIX) {
+        true => match src.find(',') {
+            Some(index) => format!("{}... ({} characters)", &src[..index + 1], src.len()),
+            None => format!("data URI ({} characters)", src.len()),
+        },
+        false => src.to_string(),
+    }
+}
+
+// Decodes a `data:image/png;base64,...` URI.
+// The MIME type must match the decoded bytes.
+fn read_data_uri(src: &str) -> Result<Vec<u8>, ErrorWithBacktrace> {
+    let (header, payload) = match src[DATA_URI_PREFIX.len()..].split_once(',') {
+        Some(parts) => parts,
+        None => Err(ErrorWithBacktrace::from(format!(
+            "Invalid data URI {}: missing ','",
+            describe_source(src)
+        )))?,
+    };
+
+    let mime_type = match header.strip_suffix(";base64") {
+        Some(mime_type) => mime_type,
+        None => Err(ErrorWithBacktrace::from(format!(
+            "Invalid data URI {}: only base64 encoded data URIs are supported",
+            describe_source(src)
+        )))?,
+    };
+
+    let bytes = STANDARD.decode(payload).map_err(|err| {
+        ErrorWithBacktrace::from(format!(
+            "Invalid base64 in data URI {}: {}",
+            describe_source(src),
+            err
+        ))
+    })?;
+
+    match get_mime_type_from_bytes(&bytes) {
+        Some(actual) if actual == mime_type => Ok(bytes),
+        actual => Err(ErrorWithBacktrace::from(format!(
+            "Data URI {} declares {}, but the data is {}",
+            describe_source(src),
+            mime_type,
+            actual.unwrap_or("not a supported image")
+        ))),
+    }
+}
+
+// The timeout is a deadline for the whole request including the body,
+// so a slow server can not stall the composition
+fn fetch(url: &str, timeout: Duration) -> Result<Box<dyn Read + Send + Sync>, ErrorWithBacktrace> {
+    let agent = ureq::AgentBuilder::new().timeout(timeout).build();
+    match agent.get(url).call() {
+        Ok(response) => Ok(response.into_reader()),
+        Err(ureq::Error::Status(status, response)) => Err(ErrorWithBacktrace::with_code(
+            ErrorCode::Network,
+            format!(
+                "Could not fetch {}: the server responded with status {} {}",
+                url,
+                status,
+                response.status_text()
+            ),
+        )),
+        Err(ureq::Error::Transport(err)) => Err(ErrorWithBacktrace::with_code(
+            ErrorCode::Network,
+            format!("Could not fetch {}: {}", url, err),
+        )),
+    }
+}
+
+fn read_body_error(url: &str, err: io::Error) -> ErrorWithBacktrace {
+    ErrorWithBacktrace::with_code(
+        ErrorCode::Network,
+        format!("Could not read the response of {}: {}", url, err),
+    )
+}
+
+fn source_too_large_error(src: &str, max_bytes: u64) -> ErrorWithBacktrace {
+    ErrorWithBacktrace::with_code(
+        ErrorCode::LimitExceeded,
+        format!(
+            "{} is larger than {} bytes, which is more than an image within max_source_pixels can take up",
+            describe_source(src),
+            max_bytes
+        ),
+    )
+}
+
+// One byte more than allowed is read to tell whether the source is too large
+fn read_to_end_capped(reader: impl Read, max_bytes: u64) -> io::Result<Option<Vec<u8>>> {
+    let mut bytes = vec![];
+    reader
+        .take(max_bytes.saturating_add(1))
+        .read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > max_bytes {
+        return Ok(None);
+    }
+    Ok(Some(bytes))
+}
+
+fn read_url(url: &str, timeout: Duration, max_bytes: u64) -> Result<Vec<u8>, ErrorWithBacktrace> {
+    read_to_end_capped(fetch(url, timeout)?, max_bytes)
+        .map_err(|err| read_body_error(url, err))?
+        .ok_or_else(|| source_too_large_error(url, max_bytes))
+}
+
+// The bytes of a source, which is either a file path, a data URI or an http(s) URL.
+// Files and URLs larger than max_bytes are rejected before they are read completely.
+pub fn read_source(
+    src: &str,
+    network_timeout: Duration,
+    max_bytes: u64,
+) -> Result<Vec<u8>, ErrorWithBacktrace> {
+    if is_data_uri(src) {
+        return read_data_uri(src);
+    }
+    if is_url(src) {
+        return read_url(src, network_timeout, max_bytes);
+    }
+
+    read_to_end_capped(File::open(src)?, max_bytes)?
+        .ok_or_else(|| source_too_large_error(src, max_bytes))
+}
+
+fn download_too_large_error(src: &str, max_bytes: u64) -> ErrorWithBacktrace {
+    ErrorWithBacktrace::with_code(
+        ErrorCode::LimitExceeded,
+        format!(
+            "{} is larger than {} bytes, which exceeds max_download_bytes",
+            src, max_bytes
+        ),
+    )
+}
+
+fn download(
+    src: &str,
+    network_timeout: Duration,
+    max_bytes: u64,
+    path: &Path,
+) -> Result<(), ErrorWithBacktrace> {
+    let response = fetch(src, network_timeout)?;
+    let mut file = File::create(path)?;
+    let written = io::copy(&mut response.take(max_bytes.saturating_add(1)), &mut file)
+        .map_err(|err| read_body_error(src, err))?;
+    if written > max_bytes {
+        return Err(download_too_large_error(src, max_bytes));
+    }
+    Ok(())
+}
+
+// The extension of the URL is kept, FFmpeg uses it to guess the format
+fn get_download_path(src: &str) -> PathBuf {
+    let extension = Path::new(src.split(['?', '#']).next().unwrap_or(src))
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| format!(".{}", extension))
+        .unwrap_or_default();
+    std::env::temp_dir().join(format!(
+        "remotion-download-{}-{}{}",
+        std::process::id(),
+        DOWNLOAD_COUNTER.fetch_add(1, Ordering::Relaxed),
+        extension
+    ))
+}
+
+// FFmpeg needs a file to seek in, so video URLs are downloaded into the temp
+// directory first. Each URL is downloaded once per command, even if several
+// layers use it in parallel. The files are deleted when the command is done,
+// so the temp directory does not fill up and changed URLs are fetched again.
+#[derive(Default)]
+pub struct Downloads {
+    paths: Mutex<HashMap<String, Arc<Mutex<Option<PathBuf>>>>>,
+}
+
+impl Downloads {
+    pub fn get_local_path(
+        &self,
+        src: &str,
+        network_timeout: Duration,
+        max_bytes: u64,
+    ) -> Result<String, ErrorWithBacktrace> {
+        if !is_url(src) {
+            return Ok(src.to_string());
+        }
+
+        let entry = self
+            .paths
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .entry(src.to_string())
+            .or_default()
+            .clone();
+        // Other layers with the same URL wait until the download is done
+        let mut path = entry.lock().unwrap_or_else(|err| err.into_inner());
+        if path.is_none() {
+            let download_path = get_download_path(src);
+            if let Err(err) = download(src, network_timeout, max_bytes, &download_path) {
+                let _ = fs::remove_file(&download_path);
+                return Err(err);
+            }
+            *path = Some(download_path);
+        }
+
+        Ok(path
+            .as_ref()
+            .map(|path| path.to_string_lossy().to_string())
+            .unwrap_or_default())
+    }
+}
+
+impl Drop for Downloads {
+    fn drop(&mut self) {
+        let paths = self.paths.get_mut().unwrap_or_else(|err| err.into_inner());
+        for entry in paths.values() {
+            let path = entry.lock().unwrap_or_else(|err| err.into_inner());
+            if let Some(path) = path.as_ref() {
+                // Closed first, since open files can not be deleted on Windows
+                let _ = ffmpeg::close_video(&path.to_string_lossy());
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
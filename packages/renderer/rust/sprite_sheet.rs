@@ -0,0 +1,188 @@
+use crate::{
+    commands::check_max_pixels,
+    compositor::get_pixel_count,
+    errors::{error_to_string, ErrorCode, ErrorWithBacktrace},
+    ffmpeg,
+    image::{
+        bmp_to_rgba, encode_avif, encode_gif, encode_jpeg, encode_png, encode_webp,
+        validate_avif_speed, validate_quality, write_output,
+    },
+    payloads::payloads::{
+        ExtractSpriteSheetCommand, ImageFormat, SpriteSheetCell, SpriteSheetPayload,
+    },
+    scaling::scale_bilinear,
+};
+
+// The frame numbers of the cells, spread evenly over the frames in the range.
+// If there are fewer frames than cells, every frame gets a cell.
+fn get_cell_frames(first_frame: u64, frames_in_range: u64, cells: u64) -> Vec<u64> {
+    let count = cells.min(frames_in_range);
+    (0..count)
+        .map(|cell| first_frame + cell * frames_in_range / count)
+        .collect()
+}
+
+fn validate_grid(command: &ExtractSpriteSheetCommand) -> Result<(), ErrorWithBacktrace> {
+    let sizes = [
+        ("columns", command.columns),
+        ("rows", command.rows),
+        ("thumb_width", command.thumb_width),
+        ("thumb_height", command.thumb_height),
+    ];
+    for (name, value) in sizes {
+        if value == 0 {
+            return Err(ErrorWithBacktrace::from(format!(
+                "{} must be positive, but got 0",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Grids whose width or height does not fit into an image are rejected
+fn get_sheet_size(
+    columns: u32,
+    rows: u32,
+    command: &ExtractSpriteSheetCommand,
+) -> Result<(u32, u32), ErrorWithBacktrace> {
+    let width = columns as u64 * command.thumb_width as u64;
+    let height = rows as u64 * command.thumb_height as u64;
+    match (u32::try_from(width), u32::try_from(height)) {
+        (Ok(width), Ok(height)) => Ok((width, height)),
+        _ => Err(ErrorWithBacktrace::with_code(
+            ErrorCode::LimitExceeded,
+            format!("A sprite sheet of {}x{} pixels is too large", width, height),
+        )),
+    }
+}
+
+pub fn extract_sprite_sheet(
+    command: ExtractSpriteSheetCommand,
+) -> Result<SpriteSheetPayload, ErrorWithBacktrace> {
+    validate_grid(&command)?;
+    let metadata = ffmpeg::get_video_metadata(&command.input)?;
+    let fps = metadata.fps as f64;
+    if fps <= 0.0 {
+        return Err(ErrorWithBacktrace::from(format!(
+            "{} has no frame rate, so no frames can be picked from it",
+            command.input
+        )));
+    }
+
+    let start = command.start.unwrap_or(0.0).max(0.0);
+    let end = command
+        .end
+        .unwrap_or(metadata.durationInSeconds)
+        .min(metadata.durationInSeconds);
+    if end <= start {
+        return Err(ErrorWithBacktrace::from(format!(
+            "The time range {}-{} is empty, {} is {} seconds long",
+            start, end, command.input, metadata.durationInSeconds
+        )));
+    }
+
+    // Frames that start within the range
+    let first_frame = (start * fps).ceil() as u64;
+    let frames_in_range = ffmpeg::get_frame_count(end, fps).saturating_sub(first_frame);
+    let cells = command.columns as u64 * command.rows as u64;
+    let frames = get_cell_frames(first_frame, frames_in_range, cells);
+    if frames.is_empty() {
+        return Err(ErrorWithBacktrace::from(format!(
+            "There is no frame between {} and {} in {}",
+            start, end, command.input
+        )));
+    }
+
+    // Rows that would stay empty are left out
+    let columns = command.columns.min(frames.len() as u32);
+    let rows = (frames.len() as u32).div_ceil(columns);
+    let (width, height) = get_sheet_size(columns, rows, &command)?;
+    check_max_pixels(command.max_pixels, width, height)?;
+    let mut data = vec![0; get_pixel_count(width, height)? * 4];
+
+    let mut cells = vec![];
+    for (index, frame) in frames.into_iter().enumerate() {
+        let timestamp = frame as f64 / fps;
+        let bmp = ffmpeg::extract_frame(
+            command.input.clone(),
+            command.input.clone(),
+            timestamp,
+            false,
+            true,
+            None,
+        )
+        .map_err(|err| {
+            format!(
+                "Could not extract frame {} of {}: {}",
+                frame,
+                command.input,
+                error_to_string(&err)
+            )
+        })?;
+        let (frame_width, frame_height, rgba) = bmp_to_rgba(&bmp)?;
+        let thumb = scale_bilinear(
+            &rgba,
+            frame_width,
+            frame_height,
+            command.thumb_width,
+            command.thumb_height,
+        );
+
+        let column = index as u32 % columns;
+        let row = index as u32 / columns;
+        let x = column * command.thumb_width;
+        let y = row * command.thumb_height;
+        let thumb_row_size = command.thumb_width as usize * 4;
+        for (thumb_y, thumb_row) in thumb.chunks_exact(thumb_row_size).enumerate() {
+            let start = ((y as usize + thumb_y) * width as usize + x as usize) * 4;
+            data[start..start + thumb_row_size].copy_from_slice(thumb_row);
+        }
+
+        cells.push(SpriteSheetCell {
+            column,
+            row,
+            x,
+            y,
+            frame,
+            timestamp,
+        });
+    }
+
+    let encoded = match command.output_format {
+        ImageFormat::Png => encode_png(width, height, data, None, 8, None)?,
+        ImageFormat::Jpeg => encode_jpeg(
+            width,
+            height,
+            data,
+            validate_quality(command.quality)?,
+            None,
+            None,
+            None,
+        )?,
+        ImageFormat::WebP => encode_webp(width, height, data, validate_quality(command.quality)?)?,
+        ImageFormat::Avif => encode_avif(
+            width,
+            height,
+            data,
+            validate_quality(command.quality)?,
+            validate_avif_speed(None)?,
+        )?,
+        ImageFormat::Gif => encode_gif(width, height, vec![data], 0)?,
+    };
+    let bytes_written = write_output(&command.output, &encoded)?;
+
+    Ok(SpriteSheetPayload {
+        output: command.output,
+        width,
+        height,
+        format: format!("{:?}", command.output_format),
+        bytes_written,
+        columns,
+        rows,
+        thumb_width: command.thumb_width,
+        thumb_height: command.thumb_height,
+        cell_count: cells.len() as u32,
+        cells,
+    })
+}
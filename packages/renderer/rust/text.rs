@@ -0,0 +1,128 @@
+use ab_glyph::{point, Font, FontVec, Glyph, GlyphId, PxScale, ScaleFont};
+
+use crate::{errors::ErrorWithBacktrace, payloads::payloads::TextLayer};
+
+pub struct TextPixel {
+    pub x: i64,
+    pub y: i64,
+    pub color: [u8; 4],
+}
+
+// Used if no font_path is passed. The first one that exists is taken.
+// Which one that is depends on the machine, so the output can differ between
+// systems. Pass font_path for output that is the same everywhere.
+const FALLBACK_FONTS: [&str; 6] = [
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+
+fn resolve_font_path(font_path: Option<String>) -> Result<String, ErrorWithBacktrace> {
+    match font_path {
+        Some(path) => Ok(path),
+        None => match FALLBACK_FONTS
+            .iter()
+            .find(|path| std::path::Path::new(path).exists())
+        {
+            Some(path) => Ok(path.to_string()),
+            None => Err(ErrorWithBacktrace::from(
+                "No font_path was passed to the Text layer and no fallback font was found on the system",
+            )),
+        },
+    }
+}
+
+fn load_font(font_path: Option<String>) -> Result<FontVec, ErrorWithBacktrace> {
+    let path = resolve_font_path(font_path)?;
+
+    let data = std::fs::read(&path)
+        .map_err(|err| format!("Could not read font file {}: {}", path, err))?;
+
+    let font = FontVec::try_from_vec(data)
+        .map_err(|err| format!("Could not parse font file {}: {}", path, err))?;
+
+    Ok(font)
+}
+
+// The glyphs of the text, placed as if the text box started at 0, 0, together
+// with the size of the text box: the widest line and the height of all lines.
+// The first line is placed so that its ascent starts at the top.
+fn layout_text(font: &FontVec, layer: &TextLayer) -> (Vec<Glyph>, u32, u32) {
+    let scale = PxScale::from(layer.font_size as f32);
+    let scaled_font = font.as_scaled(scale);
+    let line_height = scaled_font.height() + scaled_font.line_gap();
+
+    let mut caret = point(0.0, scaled_font.ascent());
+    let mut previous_glyph: Option<GlyphId> = None;
+    let mut glyphs: Vec<Glyph> = vec![];
+    let mut width: f32 = 0.0;
+
+    // Iterating over chars so that multi-byte UTF-8 sequences map to a single glyph
+    for character in layer.text.chars() {
+        if character == '\n' {
+            caret.x = 0.0;
+            caret.y += line_height;
+            previous_glyph = None;
+            continue;
+        }
+        if character.is_control() {
+            continue;
+        }
+
+        let glyph_id = scaled_font.glyph_id(character);
+        if let Some(previous) = previous_glyph {
+            caret.x += scaled_font.kern(previous, glyph_id);
+        }
+
+        glyphs.push(glyph_id.with_scale_and_position(scale, caret));
+        caret.x += scaled_font.h_advance(glyph_id);
+        width = width.max(caret.x);
+        previous_glyph = Some(glyph_id);
+    }
+
+    let height = caret.y - scaled_font.ascent() + scaled_font.height();
+    (glyphs, width.ceil() as u32, height.ceil() as u32)
+}
+
+// The size of the text box, which anchors are resolved with
+pub fn measure_text_layer(layer: &TextLayer) -> Result<(u32, u32), ErrorWithBacktrace> {
+    let font = load_font(layer.font_path.clone())?;
+    let (_, width, height) = layout_text(&font, layer);
+    Ok((width, height))
+}
+
+// x and y are the top left corner of the text box.
+// Returns the pixels to blend in drawing order, stably sorted by row.
+pub fn rasterize_text_layer(layer: TextLayer) -> Result<Vec<TextPixel>, ErrorWithBacktrace> {
+    let font = load_font(layer.font_path.clone())?;
+    let (glyphs, _, _) = layout_text(&font, &layer);
+    let mut pixels: Vec<TextPixel> = vec![];
+
+    for glyph in glyphs {
+        let outlined = match font.outline_glyph(glyph) {
+            Some(outlined) => outlined,
+            // Whitespace has no outline
+            None => continue,
+        };
+
+        // Laid out at whole pixels, so moving the box does not change the glyphs
+        let bounds = outlined.px_bounds();
+        outlined.draw(|glyph_x, glyph_y, coverage| {
+            let alpha = (layer.color.0[3] as f32 * coverage.min(1.0)).round() as u8;
+
+            pixels.push(TextPixel {
+                x: layer.x as i64 + bounds.min.x as i64 + glyph_x as i64,
+                y: layer.y as i64 + bounds.min.y as i64 + glyph_y as i64,
+                color: [layer.color.0[0], layer.color.0[1], layer.color.0[2], alpha],
+            });
+        });
+    }
+
+    // Pixels outside of the canvas are skipped while drawing
+    pixels.sort_by_key(|pixel| pixel.y);
+
+    Ok(pixels)
+}
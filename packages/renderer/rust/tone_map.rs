@@ -65,7 +65,8 @@ pub fn make_tone_map_filtergraph(
         _ => "input",
     };
 
-    let matrix_is_target = matrix_in == "input" || matrix_in == "470bg" || matrix_in == "709" || matrix_in == "170m";
+    let matrix_is_target =
+        matrix_in == "input" || matrix_in == "470bg" || matrix_in == "709" || matrix_in == "170m";
     let transfer_is_target = transfer_in == "input" || transfer_in == "709";
     let primaries_is_target = primaries == "input" || primaries == "709" || primaries == "170m";
 
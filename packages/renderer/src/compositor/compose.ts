@@ -127,7 +127,9 @@ export const callCompositor = (
 					// Try to see if the error is a JSON
 					const parsed = JSON.parse(message) as ErrorPayload;
 					const msg = `Compositor error: ${parsed.error}`;
-					const err = new Error(`${msg}\n${parsed.backtrace}`);
+					const err = new Error(
+						parsed.backtrace ? `${msg}\n${parsed.backtrace}` : msg,
+					);
 
 					reject(err);
 				} catch (err) {
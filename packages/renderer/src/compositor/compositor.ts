@@ -116,8 +116,9 @@ export const startCompositor = <T extends keyof CompositorCommand>({
 			if (statusType === 'error') {
 				try {
 					const parsed = JSON.parse(data.toString('utf8')) as ErrorPayload;
+					const msg = `Compositor error: ${parsed.error}`;
 					(waiters.get(nonce) as Waiter).reject(
-						new Error(`Compositor error: ${parsed.error}\n${parsed.backtrace}`),
+						new Error(parsed.backtrace ? `${msg}\n${parsed.backtrace}` : msg),
 					);
 				} catch (err) {
 					(waiters.get(nonce) as Waiter).reject(
@@ -1,4 +1,54 @@
 // Must keep this file synced with payloads.rs!
+
+// [r, g, b, a] or "#rgb", "#rrggbb", "#rrggbbaa"
+export type Color = [number, number, number, number] | `#${string}`;
+
+// Drop shadow in the alpha shape of the layer, drawn underneath it
+export type Shadow = {
+	offset_x?: number;
+	offset_y?: number;
+	// 0 gives a hard shadow
+	blur?: number;
+	color: Color;
+};
+
+// Inner borders cover the edge of the layer, outer borders are drawn around it
+export type Border = {
+	// 0 draws no border
+	width?: number;
+	color: Color;
+	position?: 'Inner' | 'Outer';
+};
+
+// With an anchor, x and y are offsets from that point of the canvas, towards
+// the center. BottomRight with x = 20 and y = 20 leaves a 20px margin.
+export type Anchor =
+	| 'TopLeft'
+	| 'Top'
+	| 'TopRight'
+	| 'Left'
+	| 'Center'
+	| 'Right'
+	| 'BottomLeft'
+	| 'Bottom'
+	| 'BottomRight';
+
+export type FitMode = 'Stretch' | 'Contain' | 'Cover';
+
+// Pixels from the edges of the source that keep their size when the layer is
+// scaled. Edges stretch along one axis, the center along both.
+export type NineSlice = {
+	left: number;
+	right: number;
+	top: number;
+	bottom: number;
+};
+
+export type BlendMode = 'Normal' | 'Multiply' | 'Screen' | 'Overlay' | 'Add';
+
+// Layers are painted from the lowest to the highest z_index, layers without
+// one count as 0. Layers with the same z_index are painted in array order.
+// Without any z_index, the array order is the paint order.
 export type Layer =
 	| {
 			type: 'PngImage';
@@ -6,8 +56,32 @@ export type Layer =
 				src: string;
 				x: number;
 				y: number;
+				anchor?: Anchor | null;
 				width: number;
 				height: number;
+				opacity?: number;
+				rotation?: number;
+				crop_x?: number | null;
+				crop_y?: number | null;
+				crop_width?: number | null;
+				crop_height?: number | null;
+				flip_h?: boolean;
+				flip_v?: boolean;
+				scaling?: 'Nearest' | 'Bilinear';
+				fit?: FitMode;
+				pad_color?: Color | null;
+				// Only with fit = 'Stretch'
+				nine_slice?: NineSlice | null;
+				// 0-1, mixes the source with its grayscale version
+				grayscale?: number;
+				// Multiply the color channels and their distance from the middle gray, 1 by default
+				brightness?: number;
+				contrast?: number;
+				tint?: Color | null;
+				blend_mode?: BlendMode;
+				shadow?: Shadow | null;
+				border?: Border | null;
+				z_index?: number | null;
 			};
 	  }
 	| {
@@ -16,22 +90,136 @@ export type Layer =
 				src: string;
 				x: number;
 				y: number;
+				anchor?: Anchor | null;
 				width: number;
 				height: number;
+				opacity?: number;
+				rotation?: number;
+				crop_x?: number | null;
+				crop_y?: number | null;
+				crop_width?: number | null;
+				crop_height?: number | null;
+				flip_h?: boolean;
+				flip_v?: boolean;
+				scaling?: 'Nearest' | 'Bilinear';
+				fit?: FitMode;
+				pad_color?: Color | null;
+				// Only with fit = 'Stretch'
+				nine_slice?: NineSlice | null;
+				// 0-1, mixes the source with its grayscale version
+				grayscale?: number;
+				// Multiply the color channels and their distance from the middle gray, 1 by default
+				brightness?: number;
+				contrast?: number;
+				tint?: Color | null;
+				blend_mode?: BlendMode;
+				shadow?: Shadow | null;
+				border?: Border | null;
+				z_index?: number | null;
 			};
 	  }
 	| {
 			type: 'Solid';
 			params: {
-				fill: [number, number, number, number];
+				fill: Color;
+				x: number;
+				y: number;
+				anchor?: Anchor | null;
+				width: number;
+				height: number;
+				opacity?: number;
+				rotation?: number;
+				corner_radius?: number;
+				blend_mode?: BlendMode;
+				shadow?: Shadow | null;
+				border?: Border | null;
+				z_index?: number | null;
+			};
+	  }
+	| {
+			type: 'Text';
+			params: {
+				text: string;
+				x: number;
+				y: number;
+				// Resolved with the size of the laid out text
+				anchor?: Anchor | null;
+				font_size: number;
+				color: Color;
+				font_path?: string | null;
+				z_index?: number | null;
+			};
+	  }
+	| {
+			type: 'Gradient';
+			params: {
+				x: number;
+				y: number;
+				anchor?: Anchor | null;
+				width: number;
+				height: number;
+				start_color: Color;
+				end_color: Color;
+				direction: 'Horizontal' | 'Vertical' | 'Diagonal';
+				blend_mode?: BlendMode;
+				z_index?: number | null;
+			};
+	  }
+	| {
+			type: 'Video';
+			params: {
+				src: string;
+				time: number;
+				x: number;
+				y: number;
+				anchor?: Anchor | null;
+				width: number;
+				height: number;
+				opacity?: number;
+				rotation?: number;
+				blend_mode?: BlendMode;
+				z_index?: number | null;
+			};
+	  }
+	| {
+			type: 'Blur';
+			params: {
+				x: number;
+				y: number;
+				anchor?: Anchor | null;
+				width: number;
+				height: number;
+				radius: number;
+				z_index?: number | null;
+			};
+	  }
+	| {
+			type: 'Ellipse';
+			params: {
 				x: number;
 				y: number;
+				anchor?: Anchor | null;
 				width: number;
 				height: number;
+				fill: Color;
+				opacity?: number;
+				border?: Border | null;
+				z_index?: number | null;
+			};
+	  }
+	| {
+			// Masks the layer that is painted after it with a PNG or JPEG, which is
+			// scaled to the size of that layer and follows its position and
+			// rotation. The mask itself is not drawn.
+			type: 'Mask';
+			params: {
+				src: string;
+				mode?: 'Alpha' | 'Luminance';
+				z_index?: number | null;
 			};
 	  };
 
-export type CompositorImageFormat = 'Png' | 'Jpeg';
+export type CompositorImageFormat = 'Png' | 'Jpeg' | 'WebP' | 'Avif' | 'Gif';
 
 export type VideoMetadata = {
 	fps: number;
@@ -197,6 +385,47 @@ export type VideoMetadata = {
 		| 'unknown';
 };
 
+export type ProbeResult = {
+	width: number;
+	height: number;
+	duration_seconds: number;
+	fps: number;
+	frame_count: number;
+};
+
+export type SpriteSheet = {
+	output: string;
+	width: number;
+	height: number;
+	format: CompositorImageFormat;
+	bytes_written: number;
+	// The grid that was actually filled, rows without cells are left out
+	columns: number;
+	rows: number;
+	thumb_width: number;
+	thumb_height: number;
+	// Fewer than columns * rows if the range has fewer frames
+	cell_count: number;
+	// Left to right, top to bottom
+	cells: {
+		column: number;
+		row: number;
+		x: number;
+		y: number;
+		frame: number;
+		timestamp: number;
+	}[];
+};
+
+export type AudioWaveform = {
+	// false for videos without an audio track, the samples are then empty
+	has_audio: boolean;
+	sample_rate: number;
+	// Mono samples between -1 and 1, empty if envelope_buckets was passed
+	samples: number[];
+	envelope: {min: number; max: number}[] | null;
+};
+
 type SilentPart = {
 	startInSeconds: number;
 	endInSeconds: number;
@@ -213,20 +442,75 @@ export type GetSilentPartsResponse = GetSilentPartsResponseRust & {
 	audibleParts: SilentParts;
 };
 
+export type ComposeCommand = {
+	output: string;
+	// Can be omitted with a base_image, which then determines the size
+	width?: number;
+	height?: number;
+	layers: Layer[];
+	// Instead of `layers`, composes every item into a frame of an animated GIF
+	frames?: Layer[][] | null;
+	delay_ms?: number;
+	output_format: CompositorImageFormat;
+	quality?: number;
+	// Only used for Jpeg. Defaults to 4:2:0 below quality 90, 4:4:4 otherwise
+	chroma_subsampling?: 'S444' | 'S422' | 'S420' | null;
+	speed?: number | null;
+	network_timeout_ms?: number;
+	background?: Color;
+	// Density metadata for PNG and JPEG
+	dpi?: number | null;
+	// PNG or JPEG that the layers are drawn onto
+	base_image?: string | null;
+	// Reject the command before allocating the canvas. Fall back to the
+	// REMOTION_COMPOSITOR_MAX_PIXELS and REMOTION_COMPOSITOR_MAX_LAYERS env variables
+	max_pixels?: number | null;
+	max_layers?: number | null;
+	// Image sources whose header declares more pixels are rejected before decoding.
+	// Falls back to REMOTION_COMPOSITOR_MAX_SOURCE_PIXELS, then to 100 megapixels
+	max_source_pixels?: number | null;
+	// Video URLs that are larger are rejected while they are downloaded.
+	// Falls back to REMOTION_COMPOSITOR_MAX_DOWNLOAD_BYTES, then to 4 GiB
+	max_download_bytes?: number | null;
+	// Bits per channel, 16 is only supported for Png
+	bit_depth?: 8 | 16;
+	// Embedded into Png and Jpeg output. Icc is the path to an .icc file,
+	// which is embedded verbatim
+	color_profile?: 'Srgb' | {Icc: string} | null;
+	// Window of the composed canvas that is encoded, must be inside of the canvas
+	export_crop?: {x: number; y: number; width: number; height: number} | null;
+};
+
 export type CompositorCommand = {
-	Compose: {
-		output: string;
-		width: number;
-		height: number;
-		layers: Layer[];
-		output_format: CompositorImageFormat;
+	Compose: ComposeCommand;
+	ComposeBatch: {
+		// Sources that are decoded once and reused by every output that refers to them
+		assets: string[];
+		outputs: ComposeCommand[];
+		network_timeout_ms?: number;
+		// Applies to the assets, every output has its own limit for its other sources
+		max_source_pixels?: number | null;
 	};
 	ExtractFrame: {
 		src: string;
 		original_src: string;
-		time: number;
+		// Either `time`, or `frame` together with `fps`
+		time?: number | null;
+		frame?: number | null;
+		fps?: number | null;
 		transparent: boolean;
 		tone_mapped: boolean;
+		output?: string | null;
+	};
+	ExtractFrames: {
+		src: string;
+		original_src: string;
+		times: number[];
+		output_pattern: string;
+		transparent: boolean;
+		tone_mapped: boolean;
+		// Prints {"progress":{"done":number,"total":number}} to stderr after every frame
+		progress?: boolean;
 	};
 	GetSilences: {
 		src: string;
@@ -240,11 +524,14 @@ export type CompositorCommand = {
 		concurrency: number;
 		maximum_frame_cache_size_in_bytes: number | null;
 		verbose: boolean;
+		// Decoded image sources that are kept for the following commands, 256MB by default
+		asset_cache_size_in_bytes?: number | null;
 	};
 	CopyImageToClipboard: {
 		src: string;
 	};
 	GetOpenVideoStats: {};
+	GetAssetCacheStats: {};
 	DeliberatePanic: {};
 	CloseAllVideos: {};
 	FreeUpMemory: {
@@ -252,6 +539,30 @@ export type CompositorCommand = {
 	};
 	GetVideoMetadata: {src: string};
 	ExtractAudio: {input_path: string; output_path: string};
+	Probe: {input: string};
+	ExtractSpriteSheet: {
+		input: string;
+		columns: number;
+		rows: number;
+		thumb_width: number;
+		thumb_height: number;
+		// Seconds, the whole video by default
+		start?: number | null;
+		end?: number | null;
+		output: string;
+		output_format: CompositorImageFormat;
+		quality?: number;
+		// Like max_pixels of Compose, checked before the sprite sheet is allocated
+		max_pixels?: number | null;
+	};
+	GetAudioWaveform: {
+		input: string;
+		start: number;
+		duration: number;
+		sample_rate?: number;
+		// Returns the lowest and highest sample of this many buckets instead of every sample
+		envelope_buckets?: number | null;
+	};
 	VideoMetadata: VideoMetadata;
 };
 
@@ -263,7 +574,34 @@ export type CompositorCommandSerialized<T extends keyof CompositorCommand> = {
 	};
 };
 
+// Same as the exit code of the binary:
+// 1 = other, 2 = parse error, 3 = missing input file, 4 = encode failure,
+// 5 = decode failure, 6 = network failure,
+// 7 = max_pixels, max_layers or max_source_pixels exceeded
+export type ErrorPayloadCode = 1 | 2 | 3 | 4 | 5 | 6 | 7;
+
 export type ErrorPayload = {
 	error: string;
-	backtrace: string;
+	code: ErrorPayloadCode;
+	// Only sent with verbose logging or REMOTION_COMPOSITOR_BACKTRACE=1
+	backtrace?: string;
+};
+
+export type AssetCacheStats = {
+	hits: number;
+	misses: number;
+	entries: number;
+	size_in_bytes: number;
+	capacity_in_bytes: number;
+};
+
+export type SuccessPayload = {
+	output: string;
+	width: number;
+	height: number;
+	format: CompositorImageFormat | 'Bmp';
+	bytes_written: number;
+	frame_timestamp: number | null;
+	frame: number | null;
+	warnings: string[];
 };
@@ -0,0 +1,174 @@
+import {readFileSync, rmSync} from 'node:fs';
+import os from 'node:os';
+import path from 'node:path';
+import {expect, test} from 'vitest';
+import {startLongRunningCompositor} from '../compositor/compositor';
+import type {ComposeCommand, SuccessPayload} from '../compositor/payloads';
+
+const outputs = 30;
+
+const startTestCompositor = () =>
+	startLongRunningCompositor({
+		maximumFrameCacheItemsInBytes: null,
+		logLevel: 'info',
+		indent: false,
+		binariesDirectory: null,
+	});
+
+// Large enough that decoding it dominates composing the small outputs
+const createLogo = async (logo: string) => {
+	const compositor = startTestCompositor();
+	await compositor.executeCommand('Compose', {
+		output: logo,
+		width: 3000,
+		height: 3000,
+		layers: [
+			{
+				type: 'Gradient',
+				params: {
+					x: 0,
+					y: 0,
+					width: 3000,
+					height: 3000,
+					start_color: '#f00',
+					end_color: '#00f8',
+					direction: 'Diagonal',
+				},
+			},
+		],
+		output_format: 'Png',
+	});
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+};
+
+const getOutputs = (logo: string, prefix: string): ComposeCommand[] =>
+	new Array(outputs).fill(true).map((_, i) => ({
+		output: path.join(os.tmpdir(), `${prefix}-${i}.png`),
+		width: 300,
+		height: 300,
+		background: [(i * 8) % 255, 100, 200, 255],
+		layers: [
+			{
+				type: 'PngImage',
+				params: {
+					src: logo,
+					x: 0,
+					y: 0,
+					width: 100,
+					height: 100,
+					anchor: 'BottomRight',
+				},
+			},
+		],
+		output_format: 'Png',
+	}));
+
+// Composes the outputs once with separate Compose calls and once as a ComposeBatch
+const composeBothWays = async (logo: string) => {
+	const compositor = startTestCompositor();
+
+	const separateOutputs = getOutputs(logo, 'compose-separate');
+	const separateStart = performance.now();
+	for (const output of separateOutputs) {
+		await compositor.executeCommand('Compose', output);
+	}
+	const separate = performance.now() - separateStart;
+
+	const batchOutputs = getOutputs(logo, 'compose-batch');
+	const batchStart = performance.now();
+	const response = await compositor.executeCommand('ComposeBatch', {
+		assets: [logo],
+		outputs: batchOutputs,
+	});
+	const batch = performance.now() - batchStart;
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+
+	return {separateOutputs, separate, batchOutputs, batch, response};
+};
+
+const removeOutputs = (outputs: ComposeCommand[]) => {
+	for (const {output} of outputs) {
+		rmSync(output);
+	}
+};
+
+// Timings depend on the machine, so they are only compared when asked for
+const runBenchmarks = Boolean(process.env.REMOTION_COMPOSITOR_BENCH);
+
+test(
+	'ComposeBatch should give the same result as separate Compose calls',
+	async () => {
+		const logo = path.join(os.tmpdir(), 'compose-batch-logo.png');
+		await createLogo(logo);
+
+		const {separateOutputs, batchOutputs, response} =
+			await composeBothWays(logo);
+
+		const results = JSON.parse(response.toString('utf-8')) as SuccessPayload[];
+		expect(results.map((r) => r.output)).toEqual(
+			batchOutputs.map((o) => o.output),
+		);
+		// Sharing the decoded logo does not change the result
+		for (let i = 0; i < outputs; i++) {
+			expect(readFileSync(batchOutputs[i].output)).toEqual(
+				readFileSync(separateOutputs[i].output),
+			);
+		}
+
+		removeOutputs(batchOutputs);
+		removeOutputs(separateOutputs);
+		rmSync(logo);
+	},
+	{timeout: 120000},
+);
+
+test.skipIf(!runBenchmarks)(
+	'ComposeBatch should decode shared assets once and be faster than separate Compose calls',
+	async () => {
+		const logo = path.join(os.tmpdir(), 'compose-batch-bench-logo.png');
+		await createLogo(logo);
+
+		const {separateOutputs, separate, batchOutputs, batch} =
+			await composeBothWays(logo);
+
+		expect(batch).toBeLessThan(separate);
+
+		removeOutputs(batchOutputs);
+		removeOutputs(separateOutputs);
+		rmSync(logo);
+	},
+	{timeout: 120000},
+);
+
+test('ComposeBatch should name the output that failed', async () => {
+	const compositor = startTestCompositor();
+
+	await expect(
+		compositor.executeCommand('ComposeBatch', {
+			assets: [],
+			outputs: [
+				{
+					output: path.join(os.tmpdir(), 'compose-batch-ok.png'),
+					width: 1,
+					height: 1,
+					layers: [],
+					output_format: 'Png',
+				},
+				{
+					output: path.join(os.tmpdir(), 'compose-batch-fails.png'),
+					layers: [],
+					output_format: 'Png',
+				},
+			],
+		}),
+	).rejects.toThrow(
+		'Output 1: width and height are required when there is no base_image',
+	);
+	rmSync(path.join(os.tmpdir(), 'compose-batch-ok.png'));
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});
@@ -0,0 +1,89 @@
+import {readFileSync, rmSync} from 'node:fs';
+import os from 'node:os';
+import path from 'node:path';
+import {expect, test} from 'vitest';
+import {startCompositor} from '../compositor/compositor';
+import type {Layer} from '../compositor/payloads';
+
+const width = 7680;
+const height = 1080;
+
+// 60 full-canvas, semi-transparent layers, so that the order of blending matters
+const layers: Layer[] = new Array(60).fill(true).map((_, i) => {
+	return {
+		type: 'Solid',
+		params: {
+			fill: [(i * 40) % 255, (i * 90) % 255, (i * 20) % 255, 100],
+			x: 0,
+			y: 0,
+			width,
+			height,
+			rotation: i % 10 === 0 ? 5 : 0,
+		},
+	};
+});
+
+const composeWithConcurrency = async (concurrency: number, output: string) => {
+	const compositor = startCompositor({
+		type: 'StartLongRunningProcess',
+		payload: {
+			concurrency,
+			maximum_frame_cache_size_in_bytes: null,
+			verbose: false,
+		},
+		logLevel: 'info',
+		indent: false,
+		binariesDirectory: null,
+	});
+
+	const start = performance.now();
+	await compositor.executeCommand('Compose', {
+		output,
+		width,
+		height,
+		layers,
+		output_format: 'Png',
+	});
+	const time = performance.now() - start;
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+
+	return time;
+};
+
+// Timings depend on the machine, so they are only compared when asked for
+const runBenchmarks = Boolean(process.env.REMOTION_COMPOSITOR_BENCH);
+
+test(
+	'Compositing in parallel bands should give the same result',
+	async () => {
+		const sequentialOutput = path.join(os.tmpdir(), 'compose-bench-1.png');
+		const parallelOutput = path.join(os.tmpdir(), 'compose-bench-n.png');
+
+		await composeWithConcurrency(1, sequentialOutput);
+		await composeWithConcurrency(os.cpus().length, parallelOutput);
+
+		expect(readFileSync(parallelOutput)).toEqual(
+			readFileSync(sequentialOutput),
+		);
+
+		rmSync(sequentialOutput);
+		rmSync(parallelOutput);
+	},
+	{timeout: 60000},
+);
+
+test.skipIf(!runBenchmarks || os.cpus().length === 1)(
+	'Compositing in parallel bands should be faster',
+	async () => {
+		const output = path.join(os.tmpdir(), 'compose-bench.png');
+
+		const sequential = await composeWithConcurrency(1, output);
+		const parallel = await composeWithConcurrency(os.cpus().length, output);
+
+		expect(parallel).toBeLessThan(sequential);
+		rmSync(output);
+	},
+	{timeout: 60000},
+);
@@ -0,0 +1,121 @@
+import {createHash} from 'node:crypto';
+import {readFileSync, rmSync} from 'node:fs';
+import os from 'node:os';
+import path from 'node:path';
+import {expect, test} from 'vitest';
+import {startCompositor} from '../compositor/compositor';
+import type {Layer} from '../compositor/payloads';
+
+const pixel =
+	'iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNk+M9QDwADhgGAWjR9awAAAABJRU5ErkJggg==';
+
+// Covers every layer type that does not depend on installed fonts or videos
+const layers: Layer[] = [
+	{
+		type: 'Gradient',
+		params: {
+			x: 0,
+			y: 0,
+			width: 320,
+			height: 180,
+			start_color: '#1e3a8a',
+			end_color: '#f472b6',
+			direction: 'Diagonal',
+		},
+	},
+	{
+		type: 'Solid',
+		params: {
+			fill: [255, 255, 255, 180],
+			x: 20.5,
+			y: 15.25,
+			width: 120,
+			height: 80,
+			corner_radius: 12,
+		},
+	},
+	{
+		type: 'Solid',
+		params: {
+			fill: '#22c55e',
+			x: 160,
+			y: 40,
+			width: 90,
+			height: 50,
+			rotation: 17,
+			blend_mode: 'Multiply',
+		},
+	},
+	{
+		type: 'Ellipse',
+		params: {x: 200, y: 90, width: 100, height: 70, fill: '#facc15cc'},
+	},
+	{
+		type: 'PngImage',
+		params: {
+			src: `data:image/png;base64,${pixel}`,
+			x: 40,
+			y: 110,
+			width: 60,
+			height: 40,
+			tint: '#ff8800',
+			opacity: 0.8,
+			blend_mode: 'Screen',
+		},
+	},
+	{type: 'Blur', params: {x: 100, y: 60, width: 140, height: 90, radius: 6}},
+	{
+		type: 'Solid',
+		params: {
+			fill: [0, 0, 0, 90],
+			x: 0,
+			y: 150,
+			width: 320,
+			height: 30,
+			blend_mode: 'Overlay',
+		},
+	},
+];
+
+const composeWithConcurrency = async (concurrency: number) => {
+	const output = path.join(os.tmpdir(), `determinism-${concurrency}.png`);
+	const compositor = startCompositor({
+		type: 'StartLongRunningProcess',
+		payload: {
+			concurrency,
+			maximum_frame_cache_size_in_bytes: null,
+			verbose: false,
+		},
+		logLevel: 'info',
+		indent: false,
+		binariesDirectory: null,
+	});
+
+	await compositor.executeCommand('Compose', {
+		output,
+		width: 320,
+		height: 180,
+		layers,
+		output_format: 'Png',
+	});
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+
+	const hash = createHash('sha256').update(readFileSync(output)).digest('hex');
+	rmSync(output);
+	return hash;
+};
+
+test('Compose should give the same bytes regardless of the amount of threads', async () => {
+	const hashes = [];
+	for (const concurrency of [1, 3, os.cpus().length]) {
+		hashes.push(await composeWithConcurrency(concurrency));
+	}
+
+	expect(new Set(hashes).size).toBe(1);
+	// The rendering contract. If this changes, the output of existing
+	// payloads changed, which needs to be deliberate.
+	expect(hashes[0]).toBe(
+		'6d711cd9254a33c7122a8ca5ae263f2fae97ca3223682d5245969673722c7521',
+	);
+});
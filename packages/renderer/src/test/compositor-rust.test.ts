@@ -1,15 +1,98 @@
+import {spawnSync} from 'node:child_process';
+import {readdirSync, readFileSync, rmSync, writeFileSync} from 'node:fs';
+import http from 'node:http';
+import type {AddressInfo} from 'node:net';
+import os from 'node:os';
+import path from 'node:path';
+import {inflateSync} from 'node:zlib';
 import {expect, test} from 'vitest';
+import {serializeCommand} from '../compositor/compose';
 import {startLongRunningCompositor} from '../compositor/compositor';
+import {getExecutablePath} from '../compositor/get-executable-path';
+import type {
+	Anchor,
+	AssetCacheStats,
+	BlendMode,
+	Color,
+	ComposeCommand,
+	CompositorCommand,
+	ErrorPayload,
+	Layer,
+	NineSlice,
+	SuccessPayload,
+} from '../compositor/payloads';
+import {exampleVideos} from './example-videos';
+
+const startTestCompositor = () =>
+	startLongRunningCompositor({
+		maximumFrameCacheItemsInBytes: null,
+		logLevel: 'info',
+		indent: false,
+		binariesDirectory: null,
+	});
+
+const runCompositor = (arg: string, env?: NodeJS.ProcessEnv) =>
+	spawnSync(
+		getExecutablePath({
+			type: 'compositor',
+			indent: false,
+			logLevel: 'info',
+			binariesDirectory: null,
+		}),
+		[arg],
+		{env},
+	);
+
+// Runs a single Compose command, with output: '-' the image is in result.stdout
+const composeToStdout = (command: CompositorCommand['Compose']) =>
+	runCompositor(JSON.stringify(serializeCommand('Compose', command)));
+
+// For a single pixel, every PNG filter leaves the bytes unchanged
+const readSinglePixelPng = (png: Buffer) => {
+	const idatStart = png.indexOf('IDAT') + 4;
+	const length = png.readUInt32BE(idatStart - 8);
+	const raw = inflateSync(png.subarray(idatStart, idatStart + length));
+	return [...raw.subarray(1, 5)];
+};
+
+const paeth = (a: number, b: number, c: number) => {
+	const p = a + b - c;
+	const pa = Math.abs(p - a);
+	const pb = Math.abs(p - b);
+	const pc = Math.abs(p - c);
+	return pa <= pb && pa <= pc ? a : pb <= pc ? b : c;
+};
+
+// Undoes the filters of an 8-bit RGBA PNG with a single IDAT chunk,
+// returns the RGBA values of every row
+const readPngRows = (png: Buffer) => {
+	const width = png.readUInt32BE(png.indexOf('IHDR') + 4);
+	const idatStart = png.indexOf('IDAT') + 4;
+	const length = png.readUInt32BE(idatStart - 8);
+	const raw = inflateSync(png.subarray(idatStart, idatStart + length));
+	const stride = width * 4;
+	const rows: number[][] = [];
+	for (let offset = 0; offset < raw.length; offset += stride + 1) {
+		const previous = rows[rows.length - 1] ?? new Array(stride).fill(0);
+		const row: number[] = [];
+		for (let i = 0; i < stride; i++) {
+			const a = i >= 4 ? row[i - 4] : 0;
+			const b = previous[i];
+			const c = i >= 4 ? previous[i - 4] : 0;
+			const predictor = [0, a, b, Math.floor((a + b) / 2), paeth(a, b, c)][
+				raw[offset]
+			];
+			row.push((raw[offset + 1 + i] + predictor) & 0xff);
+		}
+		rows.push(row);
+	}
+	return rows;
+};
 
 test(
 	'Compositor should process messages in the right order',
 	async () => {
-		const compositor = startLongRunningCompositor({
-			maximumFrameCacheItemsInBytes: null,
-			logLevel: 'info',
-			indent: false,
-			binariesDirectory: null,
-		});
+		const compositor = startTestCompositor();
 
 		const matching = await Promise.all(
 			new Array(100).fill(true).map(async (_, i) => {
@@ -31,3 +114,2304 @@ test(
 	},
 	{timeout: 5000},
 );
+
+test('Compositor should encode WebP and keep the alpha channel', async () => {
+	const compositor = startTestCompositor();
+
+	const output = path.join(os.tmpdir(), 'compose.webp');
+	await compositor.executeCommand('Compose', {
+		output,
+		width: 2,
+		height: 2,
+		layers: [
+			{
+				type: 'Solid',
+				params: {fill: [255, 0, 0, 128], x: 0, y: 0, width: 2, height: 2},
+			},
+		],
+		output_format: 'WebP',
+	});
+
+	const webp = readFileSync(output);
+	expect(webp.subarray(0, 4).toString('ascii')).toBe('RIFF');
+	expect(webp.subarray(8, 12).toString('ascii')).toBe('WEBP');
+	// Lossy WebP stores the transparency in a separate ALPH chunk
+	expect(webp.includes('ALPH')).toBe(true);
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+	rmSync(output);
+});
+
+test('Compositor should pass the quality to the JPEG encoder', async () => {
+	const compositor = startTestCompositor();
+
+	const compose = async (quality: number) => {
+		const output = path.join(os.tmpdir(), `quality-${quality}.jpg`);
+		await compositor.executeCommand('Compose', {
+			output,
+			width: 8,
+			height: 8,
+			layers: [
+				{
+					type: 'Solid',
+					params: {fill: [255, 0, 0, 255], x: 0, y: 0, width: 4, height: 8},
+				},
+			],
+			output_format: 'Jpeg',
+			quality,
+		});
+		const jpeg = readFileSync(output);
+		rmSync(output);
+		return jpeg;
+	};
+	// The first value of the first quantization table, 1 means no loss
+	const getQuantization = (jpeg: Buffer) =>
+		jpeg[jpeg.indexOf(Buffer.from([0xff, 0xdb])) + 5];
+
+	expect(getQuantization(await compose(100))).toBe(1);
+	expect(getQuantization(await compose(10))).toBeGreaterThan(1);
+	// Values above 100 are clamped
+	expect(await compose(255)).toEqual(await compose(100));
+	await expect(compose(0)).rejects.toThrow(
+		'quality must be between 1 and 100, but got 0',
+	);
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});
+
+test('Compositor should rotate layers around their center', async () => {
+	const compositor = startTestCompositor();
+
+	const output = path.join(os.tmpdir(), 'rotation.png');
+	const draw = async (params: {rotation?: number; opacity?: number}) => {
+		await compositor.executeCommand('Compose', {
+			output,
+			width: 3,
+			height: 3,
+			layers: [
+				{
+					type: 'Solid',
+					params: {
+						fill: [255, 0, 0, 255],
+						x: 0,
+						y: 1,
+						width: 3,
+						height: 1,
+						...params,
+					},
+				},
+			],
+			output_format: 'Png',
+		});
+		return readFileSync(output);
+	};
+
+	// A horizontal bar turns into a vertical one
+	const rotated = readPngRows(await draw({rotation: 90}));
+	expect(rotated.map((row) => row[4 + 3])).toEqual([255, 255, 255]);
+	expect(rotated.map((row) => row[3])).toEqual([0, 0, 0]);
+	// No rotation is the same as leaving it out
+	expect(await draw({rotation: 0})).toEqual(await draw({}));
+	expect(
+		readPngRows(await draw({rotation: 90, opacity: 0.5}))[1].slice(4, 8),
+	).toEqual([255, 0, 0, 128]);
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+	rmSync(output);
+});
+
+test('Compositor should interpolate gradients in linear light', async () => {
+	const compositor = startTestCompositor();
+
+	const output = path.join(os.tmpdir(), 'gradient.png');
+	await compositor.executeCommand('Compose', {
+		output,
+		width: 3,
+		height: 1,
+		layers: [
+			{
+				type: 'Gradient',
+				params: {
+					x: 0,
+					y: 0,
+					width: 3,
+					height: 1,
+					start_color: [0, 0, 0, 255],
+					end_color: [255, 255, 255, 255],
+					direction: 'Horizontal',
+				},
+			},
+		],
+		output_format: 'Png',
+	});
+
+	// Half of the light is 188 in sRGB, not 128
+	expect(readPngRows(readFileSync(output))).toEqual([
+		[0, 0, 0, 255, 188, 188, 188, 255, 255, 255, 255, 255],
+	]);
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+	rmSync(output);
+});
+
+test('Compositor should keep running after an invalid command', async () => {
+	const compositor = startTestCompositor();
+
+	try {
+		// @ts-expect-error
+		await compositor.executeCommand('Echo', {});
+		throw new Error('should not be reached');
+	} catch (err) {
+		expect((err as Error).message).toContain('missing field `message`');
+	}
+
+	const output = await compositor.executeCommand('Echo', {
+		message: 'still-alive',
+	});
+	expect(output.toString('utf8')).toBe('Echo still-alive');
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});
+
+test('Compositor should report why a command could not be parsed', () => {
+	const getError = (arg: string) =>
+		(JSON.parse(runCompositor(arg).stderr.toString('utf8')) as ErrorPayload)
+			.error;
+
+	expect(getError('{not json')).toContain('at line 1 column 2');
+	expect(
+		getError(
+			JSON.stringify({nonce: '1', payload: {type: 'Unknown', params: {}}}),
+		),
+	).toContain('unknown variant `Unknown`');
+});
+
+test('Compositor should crop the source of image layers', async () => {
+	const compositor = startTestCompositor();
+
+	// A red and a blue pixel
+	const src = path.join(os.tmpdir(), 'crop-source.png');
+	await compositor.executeCommand('Compose', {
+		output: src,
+		width: 2,
+		height: 1,
+		layers: [
+			{
+				type: 'Solid',
+				params: {fill: [255, 0, 0, 255], x: 0, y: 0, width: 1, height: 1},
+			},
+			{
+				type: 'Solid',
+				params: {fill: [0, 0, 255, 255], x: 1, y: 0, width: 1, height: 1},
+			},
+		],
+		output_format: 'Png',
+	});
+
+	const output = path.join(os.tmpdir(), 'crop.png');
+	const crop = (crop_width: number) =>
+		compositor.executeCommand('Compose', {
+			output,
+			width: 1,
+			height: 1,
+			layers: [
+				{
+					type: 'PngImage',
+					params: {
+						src,
+						x: 0,
+						y: 0,
+						width: 1,
+						height: 1,
+						crop_x: 1,
+						crop_y: 0,
+						crop_width,
+						crop_height: 1,
+					},
+				},
+			],
+			output_format: 'Png',
+		});
+
+	await crop(1);
+	expect(readSinglePixelPng(readFileSync(output))).toEqual([0, 0, 255, 255]);
+	await expect(crop(2)).rejects.toThrow(
+		'Crop rectangle (x = 1, y = 0, width = 2, height = 1) is outside of the source image, which is 2x1',
+	);
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+	rmSync(src);
+	rmSync(output);
+});
+
+test('Compositor should write the image to stdout if the output is "-"', () => {
+	const result = composeToStdout({
+		output: '-',
+		width: 2,
+		height: 2,
+		layers: [
+			{
+				type: 'Solid',
+				params: {fill: [255, 0, 0, 255], x: 0, y: 0, width: 2, height: 2},
+			},
+		],
+		output_format: 'Png',
+	});
+
+	expect(result.status).toBe(0);
+	// Only the PNG should be on stdout, starting with its signature
+	expect(result.stdout.subarray(0, 8)).toEqual(
+		Buffer.from([0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a]),
+	);
+});
+
+test('Compositor should encode AVIF and keep the alpha channel', () => {
+	const compose = (speed: number) =>
+		composeToStdout({
+			output: '-',
+			width: 2,
+			height: 2,
+			layers: [
+				{
+					type: 'Solid',
+					params: {fill: [255, 0, 0, 128], x: 0, y: 0, width: 2, height: 2},
+				},
+			],
+			output_format: 'Avif',
+			speed,
+		});
+
+	const result = compose(10);
+	expect(result.status).toBe(0);
+	expect(result.stdout.subarray(4, 12).toString('ascii')).toBe('ftypavif');
+	// The transparency is stored as a separate auxiliary image
+	expect(result.stdout.includes('auxiliary:alpha')).toBe(true);
+
+	const invalid = compose(11);
+	expect(invalid.status).toBe(1);
+	expect(invalid.stderr.toString('utf8')).toContain(
+		'speed must be between 0 and 10, but got 11',
+	);
+});
+
+test('Compositor should round the corners of solid layers', () => {
+	const draw = (corner_radius?: number) => {
+		const result = composeToStdout({
+			output: '-',
+			width: 6,
+			height: 6,
+			layers: [
+				{
+					type: 'Solid',
+					params: {
+						fill: [255, 0, 0, 255],
+						x: 0,
+						y: 0,
+						width: 6,
+						height: 6,
+						corner_radius,
+					},
+				},
+			],
+			output_format: 'Png',
+		});
+		expect(result.status).toBe(0);
+		return result.stdout;
+	};
+
+	const alphas = readPngRows(draw(3)).map((row) =>
+		row.filter((_, i) => i % 4 === 3),
+	);
+	expect(alphas[0][0]).toBe(0);
+	expect(alphas[3][3]).toBe(255);
+	// Anti-aliased edge
+	expect(alphas[0][1]).toBeGreaterThan(0);
+	expect(alphas[0][1]).toBeLessThan(255);
+	expect(draw(0)).toEqual(draw());
+	// Clamped to half of the size
+	expect(draw(100)).toEqual(draw(3));
+});
+
+test('Compositor should accept hex colors and reject invalid ones', async () => {
+	const compositor = startTestCompositor();
+
+	const output = path.join(os.tmpdir(), 'hex-color.png');
+	await compositor.executeCommand('Compose', {
+		output,
+		width: 2,
+		height: 2,
+		layers: [
+			{
+				type: 'Solid',
+				params: {fill: '#f00', x: 0, y: 0, width: 1, height: 2},
+			},
+			{
+				type: 'Solid',
+				params: {fill: '#00ff0080', x: 1, y: 0, width: 1, height: 2},
+			},
+		],
+		output_format: 'Png',
+	});
+	rmSync(output);
+
+	try {
+		await compositor.executeCommand('Compose', {
+			output,
+			width: 2,
+			height: 2,
+			layers: [
+				{
+					type: 'Solid',
+					params: {fill: '#zz0000', x: 0, y: 0, width: 2, height: 2},
+				},
+			],
+			output_format: 'Png',
+		});
+		throw new Error('should not be reached');
+	} catch (err) {
+		expect((err as Error).message).toContain('invalid hex color "#zz0000"');
+	}
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});
+
+test('Compositor should blur what is below a Blur layer', () => {
+	const draw = (layers: Layer[]) => {
+		const result = composeToStdout({
+			output: '-',
+			width: 5,
+			height: 1,
+			layers,
+			output_format: 'Png',
+		});
+		expect(result.status).toBe(0);
+		return readPngRows(result.stdout)[0].filter((_, i) => i % 4 === 0);
+	};
+	const black: Layer = {
+		type: 'Solid',
+		params: {fill: '#000', x: 0, y: 0, width: 5, height: 1},
+	};
+	const white: Layer = {
+		type: 'Solid',
+		params: {fill: '#fff', x: 2, y: 0, width: 1, height: 1},
+	};
+	const blur = (radius: number): Layer => ({
+		type: 'Blur',
+		params: {x: 0, y: 0, width: 5, height: 1, radius},
+	});
+
+	const blurred = draw([black, white, blur(2)]);
+	expect(blurred[2]).toBeLessThan(255);
+	expect(blurred[1]).toBeGreaterThan(0);
+	expect(blurred[1]).toBe(blurred[3]);
+	expect(draw([black, white, blur(0)])).toEqual([0, 0, 255, 0, 0]);
+	// Layers above the blur stay sharp
+	expect(draw([black, blur(2), white])).toEqual([0, 0, 255, 0, 0]);
+});
+
+test('Compositor should flip image layers', () => {
+	const red = [255, 0, 0, 255];
+	const blue = [0, 0, 255, 255];
+	const green = [0, 255, 0, 255];
+	const white = [255, 255, 255, 255];
+	const src = path.join(os.tmpdir(), 'flip-source.png');
+	const fills: Color[] = ['#f00', '#00f', '#0f0', '#fff'];
+	composeToStdout({
+		output: src,
+		width: 2,
+		height: 2,
+		layers: fills.map(
+			(fill, i): Layer => ({
+				type: 'Solid',
+				params: {fill, x: i % 2, y: Math.floor(i / 2), width: 1, height: 1},
+			}),
+		),
+		output_format: 'Png',
+	});
+
+	const draw = (flip_h: boolean, flip_v: boolean) => {
+		const result = composeToStdout({
+			output: '-',
+			width: 2,
+			height: 2,
+			layers: [
+				{
+					type: 'PngImage',
+					params: {src, x: 0, y: 0, width: 2, height: 2, flip_h, flip_v},
+				},
+			],
+			output_format: 'Png',
+		});
+		expect(result.status).toBe(0);
+		return readPngRows(result.stdout);
+	};
+
+	expect(draw(false, false)).toEqual([
+		[...red, ...blue],
+		[...green, ...white],
+	]);
+	expect(draw(true, false)).toEqual([
+		[...blue, ...red],
+		[...white, ...green],
+	]);
+	expect(draw(false, true)).toEqual([
+		[...green, ...white],
+		[...red, ...blue],
+	]);
+	// The same as a rotation by 180 degrees
+	expect(draw(true, true)).toEqual([
+		[...white, ...green],
+		[...blue, ...red],
+	]);
+	rmSync(src);
+});
+
+test('Compositor should warn about layers that are clipped by the canvas', async () => {
+	const compositor = startTestCompositor();
+
+	const output = path.join(os.tmpdir(), 'clipped-layer.png');
+	const data = await compositor.executeCommand('Compose', {
+		output,
+		width: 10,
+		height: 10,
+		layers: [
+			{
+				type: 'Solid',
+				params: {fill: '#f00', x: 0, y: 0, width: 10, height: 10},
+			},
+			{
+				type: 'Solid',
+				params: {fill: '#00f', x: 5, y: 5, width: 10, height: 2},
+			},
+		],
+		output_format: 'Png',
+	});
+	rmSync(output);
+
+	const result = JSON.parse(data.toString('utf8')) as SuccessPayload;
+	expect(result.warnings).toHaveLength(1);
+	expect(result.warnings[0]).toContain('Layer 1 (Solid) is clipped');
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});
+
+test('Compositor should draw anti-aliased ellipses', () => {
+	const result = composeToStdout({
+		output: '-',
+		width: 5,
+		height: 5,
+		layers: [
+			{
+				type: 'Ellipse',
+				params: {x: 0, y: 0, width: 5, height: 5, fill: '#00f'},
+			},
+		],
+		output_format: 'Png',
+	});
+	expect(result.status).toBe(0);
+
+	const rows = readPngRows(result.stdout);
+	expect(rows[2]).toEqual(new Array(5).fill([0, 0, 255, 255]).flat());
+	// The corners are only partly covered, without darkening the color
+	const corner = rows[0].slice(0, 4);
+	expect(corner.slice(0, 3)).toEqual([0, 0, 255]);
+	expect(corner[3]).toBeGreaterThan(0);
+	expect(corner[3]).toBeLessThan(128);
+});
+
+test('Compositor should scale images with the chosen scaling mode', () => {
+	const src = path.join(os.tmpdir(), 'scaling-source.png');
+	composeToStdout({
+		output: src,
+		width: 2,
+		height: 1,
+		layers: [
+			{
+				type: 'Solid',
+				params: {fill: '#f00', x: 0, y: 0, width: 1, height: 1},
+			},
+			{
+				type: 'Solid',
+				params: {fill: '#00f', x: 1, y: 0, width: 1, height: 1},
+			},
+		],
+		output_format: 'Png',
+	});
+
+	const draw = (width: number, scaling?: 'Nearest' | 'Bilinear') => {
+		const result = composeToStdout({
+			output: '-',
+			width,
+			height: 1,
+			layers: [
+				{
+					type: 'PngImage',
+					params: {src, x: 0, y: 0, width, height: 1, scaling},
+				},
+			],
+			output_format: 'Png',
+		});
+		expect(result.status).toBe(0);
+		// The red channel of every pixel
+		return readPngRows(result.stdout)[0].filter((_, i) => i % 4 === 0);
+	};
+
+	expect(draw(4, 'Nearest')).toEqual([255, 255, 0, 0]);
+	expect(draw(3, 'Nearest')).toEqual([255, 0, 0]);
+	// Bilinear is the default and mixes the neighbouring pixels
+	expect(draw(4)).toEqual([255, 191, 64, 0]);
+	expect(draw(4, 'Bilinear')).toEqual(draw(4));
+	rmSync(src);
+});
+
+test('Compositor should average all covered pixels when downscaling by more than 2x', () => {
+	// A single white pixel on the left of 8 black ones
+	const src = path.join(os.tmpdir(), 'downscaling-source.png');
+	composeToStdout({
+		output: src,
+		width: 8,
+		height: 1,
+		layers: [
+			{
+				type: 'Solid',
+				params: {fill: '#fff', x: 0, y: 0, width: 1, height: 1},
+			},
+		],
+		background: '#000',
+		output_format: 'Png',
+	});
+
+	const result = composeToStdout({
+		output: '-',
+		width: 1,
+		height: 1,
+		layers: [
+			{
+				type: 'PngImage',
+				params: {src, x: 0, y: 0, width: 1, height: 1},
+			},
+		],
+		output_format: 'Png',
+	});
+	expect(result.status).toBe(0);
+	// Without averaging, only the two black pixels in the middle would be sampled
+	expect(readSinglePixelPng(result.stdout)).toEqual([32, 32, 32, 255]);
+	rmSync(src);
+});
+
+test('Compositor should accept base64 data URIs as image sources', async () => {
+	const compositor = startTestCompositor();
+
+	const pixel =
+		'iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNk+M9QDwADhgGAWjR9awAAAABJRU5ErkJggg==';
+	const output = path.join(os.tmpdir(), 'data-uri.png');
+
+	await compositor.executeCommand('Compose', {
+		output,
+		width: 4,
+		height: 4,
+		layers: [
+			{
+				type: 'PngImage',
+				params: {
+					src: `data:image/png;base64,${pixel}`,
+					x: 0,
+					y: 0,
+					width: 4,
+					height: 4,
+				},
+			},
+		],
+		output_format: 'Png',
+	});
+	rmSync(output);
+
+	try {
+		await compositor.executeCommand('Compose', {
+			output,
+			width: 4,
+			height: 4,
+			layers: [
+				{
+					type: 'PngImage',
+					params: {
+						src: 'data:image/png;base64,not*base64',
+						x: 0,
+						y: 0,
+						width: 4,
+						height: 4,
+					},
+				},
+			],
+			output_format: 'Png',
+		});
+		throw new Error('should not be reached');
+	} catch (err) {
+		expect((err as Error).message).toContain('Invalid base64 in data URI');
+	}
+
+	try {
+		await compositor.executeCommand('Compose', {
+			output,
+			width: 4,
+			height: 4,
+			layers: [
+				{
+					type: 'JpgImage',
+					params: {
+						src: `data:image/jpeg;base64,${pixel}`,
+						x: 0,
+						y: 0,
+						width: 4,
+						height: 4,
+					},
+				},
+			],
+			output_format: 'Png',
+		});
+		throw new Error('should not be reached');
+	} catch (err) {
+		expect((err as Error).message).toContain(
+			'declares image/jpeg, but the data is image/png',
+		);
+	}
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});
+
+test('Compositor should multiply image layers with the tint', () => {
+	const draw = (fill: Color, tint?: Color) => {
+		const src = path.join(os.tmpdir(), 'tint-source.png');
+		composeToStdout({
+			output: src,
+			width: 1,
+			height: 1,
+			layers: [
+				{
+					type: 'Solid',
+					params: {fill, x: 0, y: 0, width: 1, height: 1},
+				},
+			],
+			output_format: 'Png',
+		});
+		const result = composeToStdout({
+			output: '-',
+			width: 1,
+			height: 1,
+			layers: [
+				{
+					type: 'PngImage',
+					params: {src, x: 0, y: 0, width: 1, height: 1, tint},
+				},
+			],
+			output_format: 'Png',
+		});
+		rmSync(src);
+		expect(result.status).toBe(0);
+		return readSinglePixelPng(result.stdout);
+	};
+
+	expect(draw('#fff', '#f00')).toEqual([255, 0, 0, 255]);
+	expect(draw('#808080', '#f00')).toEqual([128, 0, 0, 255]);
+	expect(draw('#808080', '#ffffff80')).toEqual([128, 128, 128, 128]);
+	// White leaves the image untouched
+	expect(draw('#808080', '#fff')).toEqual(draw('#808080'));
+});
+
+test('Compositor should fetch http sources and report failed requests', async () => {
+	const pixel = Buffer.from(
+		'iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNk+M9QDwADhgGAWjR9awAAAABJRU5ErkJggg==',
+		'base64',
+	);
+	const server = http.createServer((req, res) => {
+		if (req.url === '/pixel.png') {
+			res.writeHead(200, {'content-type': 'image/png'});
+			res.end(pixel);
+		} else if (req.url === '/slow.png') {
+			// Never responds, so the timeout has to kick in
+		} else if (req.url === '/large.png') {
+			// Larger than any PNG with a single pixel can be
+			res.writeHead(200, {'content-type': 'image/png'});
+			res.end(Buffer.alloc(17 * 1024 * 1024));
+		} else {
+			res.writeHead(404);
+			res.end();
+		}
+	});
+	await new Promise<void>((resolve) => {
+		server.listen(0, '127.0.0.1', () => resolve());
+	});
+	const {port} = server.address() as AddressInfo;
+
+	const compositor = startTestCompositor();
+	const output = path.join(os.tmpdir(), 'http-source.png');
+	const compose = (file: string, max_source_pixels?: number) =>
+		compositor.executeCommand('Compose', {
+			output,
+			width: 4,
+			height: 4,
+			layers: [
+				{
+					type: 'PngImage',
+					params: {
+						src: `http://127.0.0.1:${port}/${file}`,
+						x: 0,
+						y: 0,
+						width: 4,
+						height: 4,
+					},
+				},
+			],
+			output_format: 'Png',
+			network_timeout_ms: 500,
+			max_source_pixels,
+		});
+
+	await compose('pixel.png');
+	rmSync(output);
+
+	await expect(compose('missing.png')).rejects.toThrow(
+		'the server responded with status 404',
+	);
+	await expect(compose('slow.png')).rejects.toThrow(
+		`Could not fetch http://127.0.0.1:${port}/slow.png`,
+	);
+	await expect(compose('large.png', 1)).rejects.toThrow(
+		`http://127.0.0.1:${port}/large.png is larger than 16777224 bytes`,
+	);
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+	server.closeAllConnections();
+	server.close();
+});
+
+test('Compositor should download a video URL once for parallel layers', async () => {
+	const video = readFileSync(exampleVideos.framer24fps);
+	let requests = 0;
+	const server = http.createServer((req, res) => {
+		if (req.url === '/video.mp4') {
+			requests++;
+			res.writeHead(200, {'content-type': 'video/mp4'});
+			res.end(video);
+		} else {
+			res.writeHead(404);
+			res.end();
+		}
+	});
+	await new Promise<void>((resolve) => {
+		server.listen(0, '127.0.0.1', () => resolve());
+	});
+	const {port} = server.address() as AddressInfo;
+	const getDownloads = () =>
+		readdirSync(os.tmpdir()).filter((file) =>
+			file.startsWith('remotion-download-'),
+		);
+
+	const compositor = startTestCompositor();
+	const output = path.join(os.tmpdir(), 'http-video.png');
+	const compose = (file: string, max_download_bytes?: number) =>
+		compositor.executeCommand('Compose', {
+			output,
+			width: 4,
+			height: 2,
+			layers: [0, 2].map(
+				(x): Layer => ({
+					type: 'Video',
+					params: {
+						src: `http://127.0.0.1:${port}/${file}`,
+						time: 0,
+						x,
+						y: 0,
+						width: 2,
+						height: 2,
+					},
+				}),
+			),
+			output_format: 'Png',
+			max_download_bytes,
+		});
+
+	// Both layers are prepared in parallel and download the same URL
+	await compose('video.mp4');
+	rmSync(output);
+	expect(requests).toBe(1);
+	expect(getDownloads()).toEqual([]);
+
+	// The download only lives as long as the command
+	await compose('video.mp4');
+	rmSync(output);
+	expect(requests).toBe(2);
+
+	await expect(compose('missing.mp4')).rejects.toThrow(
+		'the server responded with status 404',
+	);
+	await expect(compose('video.mp4', 100)).rejects.toThrow(
+		`http://127.0.0.1:${port}/video.mp4 is larger than 100 bytes, which exceeds max_download_bytes`,
+	);
+	expect(getDownloads()).toEqual([]);
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+	server.closeAllConnections();
+	server.close();
+});
+
+test('Compositor should fill the canvas with the background color', () => {
+	const result = composeToStdout({
+		output: '-',
+		width: 1,
+		height: 1,
+		layers: [],
+		output_format: 'Png',
+		background: [12, 34, 56, 255],
+	});
+	expect(result.status).toBe(0);
+	expect(readSinglePixelPng(result.stdout)).toEqual([12, 34, 56, 255]);
+});
+
+test('Compositor should paint layers in the order of their z_index', () => {
+	const draw = (zIndices: (number | null)[]) => {
+		const fills: Color[] = ['#f00', '#00f', '#0f0'];
+		const result = composeToStdout({
+			output: '-',
+			width: 1,
+			height: 1,
+			layers: zIndices.map((z_index, i): Layer => ({
+				type: 'Solid',
+				params: {fill: fills[i], x: 0, y: 0, width: 1, height: 1, z_index},
+			})),
+			output_format: 'Png',
+		});
+		expect(result.status).toBe(0);
+		return readSinglePixelPng(result.stdout);
+	};
+
+	// Without a z_index, the last layer in the array is on top
+	expect(draw([null, null, null])).toEqual([0, 255, 0, 255]);
+	// Negative indices paint first, layers without one count as 0
+	expect(draw([1, null, -1])).toEqual([255, 0, 0, 255]);
+	// Equal indices keep the array order
+	expect(draw([2, 2, 0])).toEqual([0, 0, 255, 255]);
+});
+
+test('Compositor should exit with a code that matches the error category', () => {
+	const run = (arg: string) => {
+		const result = runCompositor(arg);
+		const payload = JSON.parse(result.stderr.toString('utf8')) as ErrorPayload;
+		expect(payload.code).toBe(result.status);
+		return result.status;
+	};
+
+	expect(run('{not json')).toBe(2);
+
+	const missingInput = serializeCommand('Compose', {
+		output: path.join(os.tmpdir(), 'missing-input.png'),
+		width: 2,
+		height: 2,
+		layers: [
+			{
+				type: 'PngImage',
+				params: {
+					src: path.join(os.tmpdir(), 'does-not-exist.png'),
+					x: 0,
+					y: 0,
+					width: 2,
+					height: 2,
+				},
+			},
+		],
+		output_format: 'Png',
+	});
+	expect(run(JSON.stringify(missingInput))).toBe(3);
+});
+
+test('Compositor should only send backtraces when they are enabled', () => {
+	const run = (env: NodeJS.ProcessEnv) => {
+		const result = runCompositor('{not json', env);
+		return JSON.parse(result.stderr.toString('utf8')) as ErrorPayload;
+	};
+
+	const env = {...process.env};
+	delete env.REMOTION_COMPOSITOR_BACKTRACE;
+	expect(run(env).backtrace).toBe(undefined);
+	expect(run({...env, REMOTION_COMPOSITOR_BACKTRACE: '1'}).backtrace).toEqual(
+		expect.any(String),
+	);
+});
+
+test('Compositor should apply blend modes', () => {
+	const blend = (fill: Color, blendMode: BlendMode) => {
+		const result = composeToStdout({
+			output: '-',
+			width: 1,
+			height: 1,
+			layers: [
+				{
+					type: 'Solid',
+					params: {fill: [200, 100, 50, 255], x: 0, y: 0, width: 1, height: 1},
+				},
+				{
+					type: 'Solid',
+					params: {fill, x: 0, y: 0, width: 1, height: 1, blend_mode: blendMode},
+				},
+			],
+			output_format: 'Png',
+		});
+		expect(result.status).toBe(0);
+		return readSinglePixelPng(result.stdout);
+	};
+
+	const fill: Color = [40, 100, 150, 255];
+	expect(blend(fill, 'Normal')).toEqual([40, 100, 150, 255]);
+	// 200 * 40 / 255 = 31.4, 100 * 100 / 255 = 39.2, 50 * 150 / 255 = 29.4
+	expect(blend(fill, 'Multiply')).toEqual([31, 39, 29, 255]);
+	// 200 + 40 - 31.4 = 208.6, 100 + 100 - 39.2 = 160.8, 50 + 150 - 29.4 = 170.6
+	expect(blend(fill, 'Screen')).toEqual([209, 161, 171, 255]);
+	// Screen for the bright red channel: 255 - 2 * 55 * 215 / 255 = 162.3,
+	// multiply for the others: 2 * 39.2 = 78.4, 2 * 29.4 = 58.8
+	expect(blend(fill, 'Overlay')).toEqual([162, 78, 59, 255]);
+	expect(blend(fill, 'Add')).toEqual([240, 200, 200, 255]);
+	expect(blend([200, 100, 50, 255], 'Add')).toEqual([255, 200, 100, 255]);
+	// Half transparent: halfway between the multiplied color and the canvas,
+	// 0.502 * 31.4 + 0.498 * 200 = 115.4, 0.502 * 39.2 + 0.498 * 100 = 69.5,
+	// 0.502 * 29.4 + 0.498 * 50 = 39.7
+	expect(blend([40, 100, 150, 128], 'Multiply')).toEqual([115, 69, 40, 255]);
+});
+
+test('Compositor should encode animated GIFs', async () => {
+	const compositor = startTestCompositor();
+	const output = path.join(os.tmpdir(), 'animated.gif');
+	const frame = (fill: Color): Layer[] => [
+		{type: 'Solid', params: {fill, x: 0, y: 0, width: 4, height: 4}},
+	];
+
+	const result = JSON.parse(
+		(
+			await compositor.executeCommand('Compose', {
+				output,
+				width: 4,
+				height: 4,
+				layers: [],
+				frames: [frame('#f00'), frame('#0f0'), frame('#00f')],
+				delay_ms: 50,
+				output_format: 'Gif',
+			})
+		).toString('utf8'),
+	) as SuccessPayload;
+	expect(result.format).toBe('Gif');
+	expect(readFileSync(output).subarray(0, 6).toString('ascii')).toBe('GIF89a');
+	rmSync(output);
+
+	await expect(
+		compositor.executeCommand('Compose', {
+			output,
+			width: 4,
+			height: 4,
+			layers: [],
+			frames: [],
+			output_format: 'Gif',
+		}),
+	).rejects.toThrow('`frames` must contain at least one frame');
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});
+
+test('Compositor should distribute layers at fractional positions between pixels', () => {
+	const draw = (x: number) => {
+		const result = composeToStdout({
+			output: '-',
+			width: 1,
+			height: 1,
+			layers: [
+				{
+					type: 'Solid',
+					params: {fill: [255, 0, 0, 255], x, y: 0, width: 1, height: 1},
+				},
+			],
+			output_format: 'Png',
+		});
+		expect(result.status).toBe(0);
+		return readSinglePixelPng(result.stdout);
+	};
+
+	expect(draw(0)).toEqual([255, 0, 0, 255]);
+	// Half of the layer overlaps the pixel
+	expect(draw(0.5)).toEqual([255, 0, 0, 128]);
+	expect(draw(-0.75)).toEqual([255, 0, 0, 64]);
+});
+
+test('Compositor should write density metadata for PNG and JPEG', () => {
+	const compose = (outputFormat: 'Png' | 'Jpeg') => {
+		const result = composeToStdout({
+			output: '-',
+			width: 2,
+			height: 2,
+			layers: [],
+			output_format: outputFormat,
+			dpi: 300,
+		});
+		expect(result.status).toBe(0);
+		return result.stdout;
+	};
+
+	// pHYs: pixels per meter for x and y, then 1 for the meter unit
+	const png = compose('Png');
+	const phys = png.indexOf('pHYs') + 4;
+	expect(png.readUInt32BE(phys)).toBe(11811);
+	expect(png.readUInt32BE(phys + 4)).toBe(11811);
+	expect(png[phys + 8]).toBe(1);
+
+	// JFIF: version, 1 for the inch unit, then the x and y density
+	const jpeg = compose('Jpeg');
+	const jfif = jpeg.indexOf('JFIF\0') + 5;
+	expect(jpeg[jfif + 2]).toBe(1);
+	expect(jpeg.readUInt16BE(jfif + 3)).toBe(300);
+	expect(jpeg.readUInt16BE(jfif + 5)).toBe(300);
+});
+
+test('Compositor should apply the chroma subsampling to JPEG output', () => {
+	const compose = (chroma_subsampling: ComposeCommand['chroma_subsampling']) => {
+		const result = composeToStdout({
+			output: '-',
+			width: 2,
+			height: 2,
+			layers: [],
+			output_format: 'Jpeg',
+			chroma_subsampling,
+		});
+		expect(result.status).toBe(0);
+		// SOF0: length, precision, height, width, component count, then the id
+		// and the horizontal and vertical sampling factors of the luma component
+		const jpeg = result.stdout;
+		return jpeg[jpeg.indexOf(Buffer.from([0xff, 0xc0])) + 11];
+	};
+
+	expect(compose('S444')).toBe(0x11);
+	expect(compose('S422')).toBe(0x21);
+	expect(compose('S420')).toBe(0x22);
+	// The default quality of 90 keeps the full resolution
+	expect(compose(null)).toBe(0x11);
+});
+
+test('Compositor should draw onto a base image', async () => {
+	const compositor = startTestCompositor();
+	const base = path.join(os.tmpdir(), 'base-image.png');
+	const output = path.join(os.tmpdir(), 'on-base-image.jpg');
+
+	await compositor.executeCommand('Compose', {
+		output: base,
+		width: 3,
+		height: 2,
+		layers: [],
+		background: '#00f',
+		output_format: 'Png',
+	});
+
+	const result = JSON.parse(
+		(
+			await compositor.executeCommand('Compose', {
+				output,
+				base_image: base,
+				layers: [
+					{
+						type: 'Solid',
+						params: {fill: '#f00', x: 0, y: 0, width: 1, height: 1},
+					},
+				],
+				output_format: 'Jpeg',
+			})
+		).toString('utf8'),
+	) as SuccessPayload;
+	// The size of the base image is used
+	expect(result.width).toBe(3);
+	expect(result.height).toBe(2);
+	expect(result.format).toBe('Jpeg');
+	rmSync(output);
+	rmSync(base);
+
+	await expect(
+		compositor.executeCommand('Compose', {
+			output,
+			base_image: base,
+			layers: [],
+			output_format: 'Png',
+		}),
+	).rejects.toThrow('Could not load base_image');
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});
+
+test('Compositor should draw shadows in the alpha shape of the layer', () => {
+	const draw = (opacity: number) => {
+		const result = composeToStdout({
+			output: '-',
+			width: 1,
+			height: 1,
+			layers: [
+				{
+					type: 'Solid',
+					params: {
+						fill: '#f00',
+						// The layer itself is outside of the canvas, only its shadow is visible
+						x: 1,
+						y: 0,
+						width: 1,
+						height: 1,
+						opacity,
+						shadow: {offset_x: -1, offset_y: 0, blur: 0, color: '#00f'},
+					},
+				},
+			],
+			output_format: 'Png',
+		});
+		expect(result.status).toBe(0);
+		return readSinglePixelPng(result.stdout);
+	};
+
+	expect(draw(1)).toEqual([0, 0, 255, 255]);
+	expect(draw(0.5)).toEqual([0, 0, 255, 128]);
+});
+
+test('Compositor should reject commands that exceed the limits', async () => {
+	const compositor = startTestCompositor();
+	const output = path.join(os.tmpdir(), 'limits.png');
+
+	await expect(
+		compositor.executeCommand('Compose', {
+			output,
+			// Would need 4 GB if it was allocated
+			width: 40000,
+			height: 25000,
+			layers: [],
+			output_format: 'Png',
+			max_pixels: 1_000_000,
+		}),
+	).rejects.toThrow(
+		'The canvas has 1000000000 pixels (40000x25000), which exceeds max_pixels of 1000000',
+	);
+
+	// Without max_pixels, the size must still not overflow
+	await expect(
+		compositor.executeCommand('Compose', {
+			output,
+			width: 4294967295,
+			height: 4294967295,
+			layers: [],
+			output_format: 'Png',
+		}),
+	).rejects.toThrow('A canvas of 4294967295x4294967295 pixels is too large');
+
+	await expect(
+		compositor.executeCommand('Compose', {
+			output,
+			width: 2,
+			height: 2,
+			layers: new Array(3).fill(true).map(
+				(): Layer => ({
+					type: 'Solid',
+					params: {fill: '#fff', x: 0, y: 0, width: 2, height: 2},
+				}),
+			),
+			output_format: 'Png',
+			max_layers: 2,
+		}),
+	).rejects.toThrow('The command has 3 layers, which exceeds max_layers of 2');
+
+	// Only the header of the base image exists, so decoding it would fail
+	const ihdr = Buffer.alloc(13);
+	ihdr.writeUInt32BE(4000, 0);
+	ihdr.writeUInt32BE(4000, 4);
+	ihdr.writeUInt8(8, 8);
+	ihdr.writeUInt8(6, 9);
+	const baseImage = path.join(os.tmpdir(), 'limits-base.png');
+	writeFileSync(
+		baseImage,
+		Buffer.concat([
+			Buffer.from([0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a]),
+			Buffer.from([0, 0, 0, 13]),
+			Buffer.from('IHDR'),
+			ihdr,
+			Buffer.alloc(4),
+		]),
+	);
+	await expect(
+		compositor.executeCommand('Compose', {
+			output,
+			base_image: baseImage,
+			layers: [],
+			output_format: 'Png',
+			max_pixels: 1000,
+		}),
+	).rejects.toThrow(
+		'The canvas has 16000000 pixels (4000x4000), which exceeds max_pixels of 1000',
+	);
+	rmSync(baseImage);
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});
+
+test('Compositor should encode 16-bit PNGs', async () => {
+	const result = composeToStdout({
+		output: '-',
+		width: 1,
+		height: 1,
+		layers: [],
+		output_format: 'Png',
+		background: [255, 128, 0, 255],
+		bit_depth: 16,
+	});
+	expect(result.status).toBe(0);
+
+	// IHDR: width, height, then the bit depth
+	const png = result.stdout;
+	expect(png[png.indexOf('IHDR') + 12]).toBe(16);
+	// Every 8-bit value v becomes v * 257
+	const idatStart = png.indexOf('IDAT') + 4;
+	const length = png.readUInt32BE(idatStart - 8);
+	const raw = inflateSync(png.subarray(idatStart, idatStart + length));
+	expect([0, 1, 2, 3].map((i) => raw.readUInt16BE(1 + i * 2))).toEqual([
+		65535, 32896, 0, 65535,
+	]);
+
+	const compositor = startTestCompositor();
+	await expect(
+		compositor.executeCommand('Compose', {
+			output: path.join(os.tmpdir(), 'bit-depth.jpg'),
+			width: 1,
+			height: 1,
+			layers: [],
+			output_format: 'Jpeg',
+			bit_depth: 16,
+		}),
+	).rejects.toThrow(
+		'bit_depth 16 is only supported for Png output, but the output format is Jpeg',
+	);
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});
+
+test('Compositor should multiply the next layer with a mask', async () => {
+	const compositor = startTestCompositor();
+	const mask = path.join(os.tmpdir(), 'mask.png');
+	// Left half opaque white, right half transparent
+	await compositor.executeCommand('Compose', {
+		output: mask,
+		width: 2,
+		height: 1,
+		layers: [
+			{
+				type: 'Solid',
+				params: {fill: '#fff', x: 0, y: 0, width: 1, height: 1},
+			},
+		],
+		output_format: 'Png',
+	});
+
+	await expect(
+		compositor.executeCommand('Compose', {
+			output: path.join(os.tmpdir(), 'masked.png'),
+			width: 1,
+			height: 1,
+			layers: [{type: 'Mask', params: {src: mask}}],
+			output_format: 'Png',
+		}),
+	).rejects.toThrow('there is no layer after it to apply it to');
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+
+	const draw = (layers: Layer[], width: number) => {
+		const result = composeToStdout({
+			output: '-',
+			width,
+			height: 1,
+			layers,
+			output_format: 'Png',
+		});
+		expect(result.status).toBe(0);
+		return readPngRows(result.stdout)[0];
+	};
+
+	const solid: Layer = {
+		type: 'Solid',
+		params: {fill: '#f00', x: 0, y: 0, width: 4, height: 1},
+	};
+	// The 2x1 mask is scaled to the 4x1 layer
+	const masked = draw([{type: 'Mask', params: {src: mask}}, solid], 4);
+	expect(masked.slice(0, 4)).toEqual([255, 0, 0, 255]);
+	expect(masked.slice(12, 16)).toEqual([0, 0, 0, 0]);
+	// Without a mask, nothing changes
+	expect(draw([solid], 4).slice(12, 16)).toEqual([255, 0, 0, 255]);
+
+	const gray = path.join(os.tmpdir(), 'mask-gray.png');
+	composeToStdout({
+		output: gray,
+		width: 1,
+		height: 1,
+		layers: [],
+		background: '#808080',
+		output_format: 'Png',
+	});
+	expect(
+		draw([{type: 'Mask', params: {src: gray, mode: 'Luminance'}}, solid], 1),
+	).toEqual([255, 0, 0, 128]);
+	rmSync(gray);
+	rmSync(mask);
+});
+
+test('Compositor should move masks with the position and rotation of the layer', () => {
+	// Left half opaque white, right half transparent
+	const mask = path.join(os.tmpdir(), 'mask-transform.png');
+	composeToStdout({
+		output: mask,
+		width: 4,
+		height: 4,
+		layers: [
+			{
+				type: 'Solid',
+				params: {fill: '#fff', x: 0, y: 0, width: 2, height: 4},
+			},
+		],
+		output_format: 'Png',
+	});
+	const drawAlpha = (layer: Layer, width: number, height: number) => {
+		const result = composeToStdout({
+			output: '-',
+			width,
+			height,
+			layers: [{type: 'Mask', params: {src: mask}}, layer],
+			output_format: 'Png',
+		});
+		expect(result.status).toBe(0);
+		return readPngRows(result.stdout).map((row) =>
+			row.filter((_, index) => index % 4 === 3),
+		);
+	};
+
+	// Half a pixel to the right, the edges of the mask are shared with the neighbours
+	expect(
+		drawAlpha(
+			{
+				type: 'Solid',
+				params: {fill: '#f00', x: 0.5, y: 0, width: 4, height: 4},
+			},
+			5,
+			1,
+		),
+	).toEqual([[128, 255, 128, 0, 0]]);
+	// Rotated clockwise, the left half of the layer ends up on top
+	expect(
+		drawAlpha(
+			{
+				type: 'Solid',
+				params: {fill: '#f00', x: 0, y: 1, width: 4, height: 2, rotation: 90},
+			},
+			4,
+			4,
+		),
+	).toEqual([
+		[0, 255, 255, 0],
+		[0, 255, 255, 0],
+		[0, 0, 0, 0],
+		[0, 0, 0, 0],
+	]);
+	rmSync(mask);
+});
+
+test('Compositor should embed color profiles', () => {
+	const compose = (
+		outputFormat: 'Png' | 'Jpeg',
+		colorProfile: 'Srgb' | {Icc: string} | null,
+	) => {
+		const result = composeToStdout({
+			output: '-',
+			width: 2,
+			height: 2,
+			layers: [],
+			output_format: outputFormat,
+			color_profile: colorProfile,
+		});
+		return result;
+	};
+
+	// Without a profile, the output is unchanged
+	const plain = compose('Png', null).stdout;
+	expect(plain.indexOf('sRGB')).toBe(-1);
+	expect(plain.indexOf('iCCP')).toBe(-1);
+	expect(compose('Png', 'Srgb').stdout.indexOf('sRGB')).not.toBe(-1);
+	expect(compose('Jpeg', 'Srgb').stdout.indexOf('ICC_PROFILE\0')).not.toBe(-1);
+
+	// Only the header is checked, the rest is embedded verbatim
+	const icc = Buffer.alloc(256, 7);
+	icc.write('acsp', 36);
+	const iccPath = path.join(os.tmpdir(), 'custom.icc');
+	writeFileSync(iccPath, icc);
+
+	const png = compose('Png', {Icc: iccPath}).stdout;
+	const iccpStart = png.indexOf('iCCP') + 4;
+	const iccpLength = png.readUInt32BE(iccpStart - 8);
+	const iccp = png.subarray(iccpStart, iccpStart + iccpLength);
+	const nameEnd = iccp.indexOf(0);
+	// Null terminated name, then 0 for zlib compression
+	expect(iccp[nameEnd + 1]).toBe(0);
+	expect(inflateSync(iccp.subarray(nameEnd + 2)).equals(icc)).toBe(true);
+
+	// APP2 segment: ICC_PROFILE, chunk number and count, then the profile
+	const jpeg = compose('Jpeg', {Icc: iccPath}).stdout;
+	const app2 = jpeg.indexOf('ICC_PROFILE\0') + 12;
+	expect(jpeg.subarray(app2 + 2, app2 + 2 + icc.length).equals(icc)).toBe(true);
+
+	writeFileSync(iccPath, 'not a profile');
+	const invalid = compose('Png', {Icc: iccPath});
+	expect(invalid.status).toBe(1);
+	expect(invalid.stderr.toString('utf-8')).toContain('is not an ICC profile');
+	rmSync(iccPath);
+});
+
+test('Compositor should resolve anchored positions against the canvas', () => {
+	const draw = (layer: Layer) => {
+		const result = composeToStdout({
+			output: '-',
+			width: 4,
+			height: 3,
+			layers: [layer],
+			output_format: 'Png',
+		});
+		expect(result.status).toBe(0);
+		// The alpha of every pixel
+		return readPngRows(result.stdout).map((row) =>
+			[0, 1, 2, 3].map((x) => row[x * 4 + 3]),
+		);
+	};
+
+	// 1px margin to the right edge, no margin to the bottom
+	expect(
+		draw({
+			type: 'Solid',
+			params: {
+				fill: '#fff',
+				x: 1,
+				y: 0,
+				width: 1,
+				height: 1,
+				anchor: 'BottomRight',
+			},
+		}),
+	).toEqual([
+		[0, 0, 0, 0],
+		[0, 0, 0, 0],
+		[0, 0, 255, 0],
+	]);
+
+	// Centered at x = 1, then moved 1px to the right
+	const centered = draw({
+		type: 'Ellipse',
+		params: {
+			fill: '#fff',
+			x: 1,
+			y: 0,
+			width: 2,
+			height: 1,
+			anchor: 'Center',
+		},
+	});
+	expect(centered[0]).toEqual([0, 0, 0, 0]);
+	expect(centered[1].slice(0, 2)).toEqual([0, 0]);
+	expect(centered[1][2]).toBeGreaterThan(0);
+
+	// Without an anchor, negative coordinates are clipped
+	expect(
+		draw({
+			type: 'Gradient',
+			params: {
+				x: -3,
+				y: -2,
+				width: 4,
+				height: 3,
+				start_color: '#fff',
+				end_color: '#fff',
+				direction: 'Horizontal',
+			},
+		}),
+	).toEqual([
+		[255, 0, 0, 0],
+		[0, 0, 0, 0],
+		[0, 0, 0, 0],
+	]);
+});
+
+test('Compositor should resolve the anchor of text with the size of the laid out text', () => {
+	const font = path.join(
+		__dirname,
+		'..',
+		'..',
+		'..',
+		'example',
+		'public',
+		'Roboto-Medium.ttf',
+	);
+	// The columns and rows that the text covers
+	const draw = (anchor: Anchor | null) => {
+		const result = composeToStdout({
+			output: '-',
+			width: 60,
+			height: 40,
+			layers: [
+				{
+					type: 'Text',
+					params: {
+						text: 'Hi',
+						x: 0,
+						y: 0,
+						anchor,
+						font_size: 20,
+						color: [255, 0, 0, 255],
+						font_path: font,
+					},
+				},
+			],
+			output_format: 'Png',
+		});
+		expect(result.status).toBe(0);
+		const covered = readPngRows(result.stdout).flatMap((row, y) =>
+			row.flatMap((value, i) =>
+				i % 4 === 3 && value > 0 ? [[(i - 3) / 4, y]] : [],
+			),
+		);
+		return {
+			columns: covered.map(([x]) => x),
+			rows: covered.map(([, y]) => y),
+		};
+	};
+
+	const topLeft = draw(null);
+	const bottomRight = draw('BottomRight');
+	// The text box of "Hi" is 17x20, so it is moved by 60 - 17 and 40 - 20
+	expect(bottomRight.columns).toEqual(topLeft.columns.map((x) => x + 43));
+	expect(bottomRight.rows).toEqual(topLeft.rows.map((y) => y + 20));
+});
+
+test('Compositor should report corrupt images and keep running', async () => {
+	const compositor = startTestCompositor();
+	const gradient: Layer = {
+		type: 'Gradient',
+		params: {
+			x: 0,
+			y: 0,
+			width: 64,
+			height: 64,
+			start_color: '#f00',
+			end_color: '#00f',
+			direction: 'Vertical',
+		},
+	};
+	const png = path.join(os.tmpdir(), 'corrupt-source.png');
+	const jpeg = path.join(os.tmpdir(), 'corrupt-source.jpg');
+	for (const [output, output_format] of [
+		[png, 'Png'],
+		[jpeg, 'Jpeg'],
+	] as const) {
+		await compositor.executeCommand('Compose', {
+			output,
+			width: 64,
+			height: 64,
+			layers: [gradient],
+			output_format,
+		});
+	}
+
+	const truncated = (file: string) => {
+		const bytes = readFileSync(file);
+		const truncatedFile = file.replace('corrupt-source', 'truncated-source');
+		writeFileSync(truncatedFile, bytes.subarray(0, bytes.length / 2));
+		return truncatedFile;
+	};
+	const compose = (type: 'PngImage' | 'JpgImage', src: string) =>
+		compositor.executeCommand('Compose', {
+			output: path.join(os.tmpdir(), 'corrupt-output.png'),
+			width: 64,
+			height: 64,
+			layers: [
+				{
+					type: 'Solid',
+					params: {fill: '#fff', x: 0, y: 0, width: 64, height: 64},
+				},
+				{type, params: {src, x: 0, y: 0, width: 64, height: 64}},
+			],
+			output_format: 'Png',
+		});
+
+	await expect(compose('PngImage', truncated(png))).rejects.toThrow(
+		/Layer 1: .*truncated-source\.png is truncated/,
+	);
+	await expect(compose('JpgImage', truncated(jpeg))).rejects.toThrow(
+		/Layer 1: .*truncated-source\.jpg is truncated/,
+	);
+
+	// A mislabeled file is decoded in the format of its data
+	const mislabeled = JSON.parse(
+		(await compose('PngImage', jpeg)).toString('utf8'),
+	) as SuccessPayload;
+	expect(mislabeled.warnings).toEqual([
+		`${jpeg} is declared as a PNG, but was decoded as a JPEG, which is its actual format`,
+	]);
+
+	// The failed commands do not affect the ones after them
+	const result = JSON.parse(
+		(await compose('PngImage', png)).toString('utf8'),
+	) as SuccessPayload;
+	expect(result.width).toBe(64);
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});
+
+test('Compositor should fit images with Contain and Cover', () => {
+	const compose = (command: ComposeCommand) => {
+		const result = composeToStdout(command);
+		expect(result.status).toBe(0);
+		return result.stdout;
+	};
+	const stripes = (colors: Color[]) => {
+		const file = path.join(os.tmpdir(), `fit-${colors.length}.png`);
+		compose({
+			output: file,
+			width: colors.length,
+			height: 1,
+			layers: colors.map(
+				(fill, x): Layer => ({
+					type: 'Solid',
+					params: {fill, x, y: 0, width: 1, height: 1},
+				}),
+			),
+			output_format: 'Png',
+		});
+		return file;
+	};
+	const draw = (src: string, fit: 'Contain' | 'Cover', size: number) =>
+		readPngRows(
+			compose({
+				output: '-',
+				width: size,
+				height: size,
+				layers: [
+					{
+						type: 'PngImage',
+						params: {
+							src,
+							x: 0,
+							y: 0,
+							width: size,
+							height: size,
+							scaling: 'Nearest',
+							fit,
+							pad_color: '#0f0',
+						},
+					},
+				],
+				output_format: 'Png',
+			}),
+		);
+
+	const red = [255, 0, 0, 255];
+	const green = [0, 255, 0, 255];
+	const blue = [0, 0, 255, 255];
+
+	// The 2x1 source becomes 4x2, with a 1px bar above and below it
+	const wide = stripes(['#f00', '#00f']);
+	expect(draw(wide, 'Contain', 4)).toEqual([
+		[...green, ...green, ...green, ...green],
+		[...red, ...red, ...blue, ...blue],
+		[...red, ...red, ...blue, ...blue],
+		[...green, ...green, ...green, ...green],
+	]);
+
+	// The same amount is cropped on the left and on the right
+	const wider = stripes(['#f00', '#0f0', '#0f0', '#00f']);
+	expect(draw(wider, 'Cover', 2)).toEqual([
+		[...green, ...green],
+		[...green, ...green],
+	]);
+
+	rmSync(wide);
+	rmSync(wider);
+});
+
+test('Compositor should draw borders along the shape of the layer', () => {
+	const draw = (width: number, height: number, layers: Layer[]) => {
+		const result = composeToStdout({
+			output: '-',
+			width,
+			height,
+			layers,
+			output_format: 'Png',
+		});
+		expect(result.status).toBe(0);
+		return readPngRows(result.stdout);
+	};
+	const pixel = (rows: number[][], x: number, y: number) =>
+		rows[y].slice(x * 4, x * 4 + 4);
+
+	// An inner border covers the outermost pixels of the layer
+	const inner = draw(4, 4, [
+		{
+			type: 'Solid',
+			params: {
+				fill: '#fff',
+				x: 0,
+				y: 0,
+				width: 4,
+				height: 4,
+				border: {width: 1, color: '#f00'},
+			},
+		},
+	]);
+	expect(pixel(inner, 0, 0)).toEqual([255, 0, 0, 255]);
+	expect(pixel(inner, 3, 2)).toEqual([255, 0, 0, 255]);
+	expect(pixel(inner, 1, 1)).toEqual([255, 255, 255, 255]);
+
+	// An outer border is drawn around it
+	const outer = draw(6, 6, [
+		{
+			type: 'Solid',
+			params: {
+				fill: '#fff',
+				x: 1,
+				y: 1,
+				width: 4,
+				height: 4,
+				border: {width: 1, color: '#f00', position: 'Outer'},
+			},
+		},
+	]);
+	expect(pixel(outer, 0, 2)).toEqual([255, 0, 0, 255]);
+	expect(pixel(outer, 1, 1)).toEqual([255, 255, 255, 255]);
+
+	// Rounded corners and ellipses leave the corner of the rectangle empty
+	const rounded: Layer[] = [
+		{
+			type: 'Solid',
+			params: {
+				fill: '#fff',
+				x: 0,
+				y: 0,
+				width: 20,
+				height: 20,
+				corner_radius: 10,
+				border: {width: 2, color: '#f00'},
+			},
+		},
+	];
+	const ellipse: Layer[] = [
+		{
+			type: 'Ellipse',
+			params: {
+				fill: '#fff',
+				x: 0,
+				y: 0,
+				width: 20,
+				height: 20,
+				border: {width: 2, color: '#f00'},
+			},
+		},
+	];
+	for (const rows of [draw(20, 20, rounded), draw(20, 20, ellipse)]) {
+		expect(pixel(rows, 0, 0)).toEqual([0, 0, 0, 0]);
+		expect(pixel(rows, 10, 1)).toEqual([255, 0, 0, 255]);
+		expect(pixel(rows, 10, 10)).toEqual([255, 255, 255, 255]);
+	}
+
+	// A width of 0 draws nothing
+	expect(
+		draw(1, 1, [
+			{
+				type: 'Solid',
+				params: {
+					fill: '#fff',
+					x: 0,
+					y: 0,
+					width: 1,
+					height: 1,
+					border: {width: 0, color: '#f00'},
+				},
+			},
+		]),
+	).toEqual([[255, 255, 255, 255]]);
+});
+
+test('Compositor should reject sources that declare a huge size', async () => {
+	// A valid signature and IHDR chunk for 50000x50000 pixels, without any image data
+	const ihdr = Buffer.alloc(13);
+	ihdr.writeUInt32BE(50000, 0);
+	ihdr.writeUInt32BE(50000, 4);
+	ihdr.writeUInt8(8, 8);
+	ihdr.writeUInt8(6, 9);
+	const bomb = Buffer.concat([
+		Buffer.from([0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a]),
+		Buffer.from([0, 0, 0, 13]),
+		Buffer.from('IHDR'),
+		ihdr,
+		Buffer.alloc(4),
+	]);
+	const file = path.join(os.tmpdir(), 'bomb.png');
+	writeFileSync(file, bomb);
+
+	const compositor = startTestCompositor();
+	const compose = (src: string, max_source_pixels?: number) =>
+		compositor.executeCommand('Compose', {
+			output: path.join(os.tmpdir(), 'bomb-output.png'),
+			width: 4,
+			height: 4,
+			layers: [
+				{type: 'PngImage', params: {src, x: 0, y: 0, width: 4, height: 4}},
+			],
+			output_format: 'Png',
+			max_source_pixels,
+		});
+
+	for (const src of [
+		file,
+		`data:image/png;base64,${bomb.toString('base64')}`,
+	]) {
+		await expect(compose(src)).rejects.toThrow(
+			'declares a size of 50000x50000 (2500000000 pixels), which exceeds max_source_pixels of 100000000',
+		);
+	}
+
+	// Without a readable size, nothing would bound the memory of the decoder
+	const headless = path.join(os.tmpdir(), 'bomb-headless.png');
+	writeFileSync(headless, bomb.subarray(0, 12));
+	await expect(compose(headless)).rejects.toThrow(
+		'is truncated: the size could not be read from the header',
+	);
+
+	// The limit can be lowered for each command
+	const small = path.join(os.tmpdir(), 'bomb-small.png');
+	await compositor.executeCommand('Compose', {
+		output: small,
+		width: 4,
+		height: 4,
+		layers: [],
+		output_format: 'Png',
+	});
+	await compose(small);
+	await expect(compose(small, 15)).rejects.toThrow(
+		'declares a size of 4x4 (16 pixels), which exceeds max_source_pixels of 15',
+	);
+
+	// 16 bit images take up more memory while decoding, but the same pixels
+	const sixteenBit = path.join(os.tmpdir(), 'bomb-16-bit.png');
+	writeFileSync(
+		sixteenBit,
+		Buffer.from(
+			'iVBORw0KGgoAAAANSUhEUgAAAAQAAAAEEAYAAAD5YUI9AAAAFUlEQVR4nGP4/58BCP7/x0Uz0F4BAHD0P8Gcv2AZAAAAAElFTkSuQmCC',
+			'base64',
+		),
+	);
+	await compose(sixteenBit, 16);
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+	rmSync(file);
+	rmSync(headless);
+	rmSync(small);
+	rmSync(sixteenBit);
+});
+
+test('Compositor should only encode the export_crop window', async () => {
+	const compositor = startTestCompositor();
+	const output = path.join(os.tmpdir(), 'export-crop.png');
+	const compose = (export_crop: ComposeCommand['export_crop']) =>
+		compositor.executeCommand('Compose', {
+			output,
+			width: 4,
+			height: 4,
+			layers: [
+				{
+					type: 'Solid',
+					params: {fill: '#f00', x: 2, y: 1, width: 2, height: 2},
+				},
+			],
+			output_format: 'Png',
+			export_crop,
+		});
+
+	const result = JSON.parse(
+		(await compose({x: 2, y: 1, width: 2, height: 3})).toString('utf8'),
+	) as SuccessPayload;
+	expect([result.width, result.height]).toEqual([2, 3]);
+	const red = [255, 0, 0, 255];
+	expect(readPngRows(readFileSync(output))).toEqual([
+		[...red, ...red],
+		[...red, ...red],
+		[0, 0, 0, 0, 0, 0, 0, 0],
+	]);
+
+	await expect(compose({x: 3, y: 0, width: 2, height: 2})).rejects.toThrow(
+		'export_crop (x = 3, y = 0, width = 2, height = 2) does not fit into the canvas of 4x4',
+	);
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+	rmSync(output);
+});
+
+test('Compositor should reuse decoded sources across commands', async () => {
+	const compositor = startTestCompositor();
+	const src = path.join(os.tmpdir(), 'asset-cache-source.png');
+	const output = path.join(os.tmpdir(), 'asset-cache-output.png');
+	await compositor.executeCommand('Compose', {
+		output: src,
+		width: 2,
+		height: 2,
+		layers: [],
+		background: '#f00',
+		output_format: 'Png',
+	});
+
+	// The same source at two different sizes
+	for (const size of [1, 3]) {
+		const result = JSON.parse(
+			(
+				await compositor.executeCommand('Compose', {
+					output,
+					width: size,
+					height: size,
+					layers: [
+						{
+							type: 'PngImage',
+							params: {src, x: 0, y: 0, width: size, height: size},
+						},
+					],
+					output_format: 'Png',
+				})
+			).toString('utf8'),
+		) as SuccessPayload;
+		expect([result.width, result.height]).toEqual([size, size]);
+		expect(readPngRows(readFileSync(output))[size - 1].slice(-4)).toEqual([
+			255, 0, 0, 255,
+		]);
+	}
+
+	const stats = JSON.parse(
+		(await compositor.executeCommand('GetAssetCacheStats', {})).toString(
+			'utf8',
+		),
+	) as AssetCacheStats;
+	expect(stats.misses).toBe(1);
+	expect(stats.hits).toBe(1);
+	expect(stats.entries).toBe(1);
+	expect(stats.size_in_bytes).toBe(2 * 2 * 4 + src.length);
+
+	// Data URIs are not cached, their key would be as large as their data
+	const dataUri = `data:image/png;base64,${readFileSync(src).toString('base64')}`;
+	for (let i = 0; i < 2; i++) {
+		await compositor.executeCommand('Compose', {
+			output,
+			width: 2,
+			height: 2,
+			layers: [
+				{
+					type: 'PngImage',
+					params: {src: dataUri, x: 0, y: 0, width: 2, height: 2},
+				},
+			],
+			output_format: 'Png',
+		});
+	}
+	const statsAfterDataUris = JSON.parse(
+		(await compositor.executeCommand('GetAssetCacheStats', {})).toString(
+			'utf8',
+		),
+	) as AssetCacheStats;
+	expect(statsAfterDataUris).toEqual(stats);
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+	rmSync(src);
+	rmSync(output);
+});
+
+test('Compositor should keep the corners of nine-slice images at their size', () => {
+	// A blue frame around a single red pixel
+	const src = path.join(os.tmpdir(), 'nine-slice.png');
+	composeToStdout({
+		output: src,
+		width: 3,
+		height: 3,
+		layers: [
+			{
+				type: 'Solid',
+				params: {fill: '#f00', x: 1, y: 1, width: 1, height: 1},
+			},
+		],
+		background: '#00f',
+		output_format: 'Png',
+	});
+	const draw = (nine_slice: NineSlice) =>
+		composeToStdout({
+			output: '-',
+			width: 5,
+			height: 4,
+			layers: [
+				{
+					type: 'PngImage',
+					params: {
+						src,
+						x: 0,
+						y: 0,
+						width: 5,
+						height: 4,
+						scaling: 'Nearest',
+						nine_slice,
+					},
+				},
+			],
+			output_format: 'Png',
+		});
+
+	const result = draw({left: 1, right: 1, top: 1, bottom: 1});
+	expect(result.status).toBe(0);
+	const blue = [0, 0, 255, 255];
+	const red = [255, 0, 0, 255];
+	const edge = [...blue, ...blue, ...blue, ...blue, ...blue];
+	const center = [...blue, ...red, ...red, ...red, ...blue];
+	expect(readPngRows(result.stdout)).toEqual([edge, center, center, edge]);
+
+	const invalid = draw({left: 2, right: 2, top: 1, bottom: 1});
+	expect(invalid.status).toBe(1);
+	expect(invalid.stderr.toString('utf-8')).toContain(
+		'nine_slice insets left = 2 and right = 2 exceed the width of the source, which is 3',
+	);
+	rmSync(src);
+});
+
+test('Compositor should adjust grayscale, brightness and contrast of images', () => {
+	const run = (command: ComposeCommand) => {
+		const result = composeToStdout(command);
+		expect(result.status).toBe(0);
+		return result.stdout;
+	};
+	const src = path.join(os.tmpdir(), 'adjustments.png');
+	run({
+		output: src,
+		width: 1,
+		height: 1,
+		layers: [],
+		background: [200, 100, 50, 255],
+		output_format: 'Png',
+	});
+	const draw = (adjustments: {
+		grayscale?: number;
+		brightness?: number;
+		contrast?: number;
+	}) =>
+		readSinglePixelPng(
+			run({
+				output: '-',
+				width: 1,
+				height: 1,
+				layers: [
+					{
+						type: 'PngImage',
+						params: {src, x: 0, y: 0, width: 1, height: 1, ...adjustments},
+					},
+				],
+				output_format: 'Png',
+			}),
+		);
+
+	expect(draw({grayscale: 0, brightness: 1, contrast: 1})).toEqual([
+		200, 100, 50, 255,
+	]);
+	// Rec. 709 luma
+	expect(draw({grayscale: 1})).toEqual([118, 118, 118, 255]);
+	// Clamped instead of wrapped
+	expect(draw({brightness: 2})).toEqual([255, 200, 100, 255]);
+	expect(draw({contrast: 0})).toEqual([128, 128, 128, 255]);
+	rmSync(src);
+});
+
+test('Compositor should draw video layers with opacity, rotation and blend_mode', () => {
+	const video = (params: {
+		opacity?: number;
+		rotation?: number;
+		blend_mode?: BlendMode;
+	}): Layer => ({
+		type: 'Video',
+		params: {
+			src: exampleVideos.framer24fps,
+			time: 1,
+			x: 0,
+			y: 0,
+			width: 4,
+			height: 4,
+			...params,
+		},
+	});
+	const draw = (layers: Layer[]) => {
+		const result = composeToStdout({
+			output: '-',
+			width: 4,
+			height: 4,
+			layers,
+			output_format: 'Png',
+		});
+		expect(result.status).toBe(0);
+		return readPngRows(result.stdout);
+	};
+	const row = (pixel: number[]) => new Array(4).fill(pixel).flat();
+
+	const normal = draw([video({})]);
+	expect(
+		draw([video({opacity: 0.5})]).map((r) => r.filter((_, i) => i % 4 === 3)),
+	).toEqual(new Array(4).fill([128, 128, 128, 128]));
+	expect(draw([video({opacity: 0})])).toEqual(
+		new Array(4).fill(row([0, 0, 0, 0])),
+	);
+
+	// Turned upside down, the last pixel comes first
+	expect(draw([video({rotation: 180})])).toEqual(
+		normal
+			.map((r) => [3, 2, 1, 0].flatMap((x) => r.slice(x * 4, x * 4 + 4)))
+			.reverse(),
+	);
+
+	// Multiplying with black gives black
+	const black: Layer = {
+		type: 'Solid',
+		params: {fill: '#000', x: 0, y: 0, width: 4, height: 4},
+	};
+	expect(draw([black, video({blend_mode: 'Multiply'})])).toEqual(
+		new Array(4).fill(row([0, 0, 0, 255])),
+	);
+});
+
+test('Compositor should draw the last frame for times after the end of a video', () => {
+	const draw = (time: number) => {
+		const result = composeToStdout({
+			output: '-',
+			width: 8,
+			height: 8,
+			layers: [
+				{
+					type: 'Video',
+					params: {
+						src: exampleVideos.framer24fps,
+						time,
+						x: 0,
+						y: 0,
+						width: 8,
+						height: 8,
+					},
+				},
+			],
+			output_format: 'Png',
+		});
+		expect(result.status).toBe(0);
+		return result.stdout;
+	};
+
+	// 4.167 seconds at 24 fps, the last frame starts at 4.125 seconds
+	expect(draw(1e6)).toEqual(draw(4.15));
+});
+
+test('Compositor should name the video layer that could not be read', () => {
+	const result = composeToStdout({
+		output: '-',
+		width: 1,
+		height: 1,
+		layers: [
+			{
+				type: 'Solid',
+				params: {fill: '#000', x: 0, y: 0, width: 1, height: 1},
+			},
+			{
+				type: 'Video',
+				params: {
+					src: exampleVideos.notafile,
+					time: 0,
+					x: 0,
+					y: 0,
+					width: 1,
+					height: 1,
+				},
+			},
+		],
+		output_format: 'Png',
+	});
+
+	expect(result.status).not.toBe(0);
+	expect(
+		(JSON.parse(result.stderr.toString('utf8')) as ErrorPayload).error,
+	).toContain(
+		`Layer 1: Could not extract frame at time 0 from video layer ${exampleVideos.notafile}`,
+	);
+});
+
+test('Compositor should draw text and report fonts that could not be read', () => {
+	const draw = (text: string, font_path: string) =>
+		composeToStdout({
+			output: '-',
+			width: 24,
+			height: 24,
+			layers: [
+				{
+					type: 'Text',
+					params: {
+						text,
+						x: 0,
+						y: 0,
+						font_size: 20,
+						color: [255, 0, 0, 255],
+						font_path,
+					},
+				},
+			],
+			output_format: 'Png',
+		});
+	const font = path.join(
+		__dirname,
+		'..',
+		'..',
+		'..',
+		'example',
+		'public',
+		'Roboto-Medium.ttf',
+	);
+
+	const result = draw('Ĥé', font);
+	expect(result.status).toBe(0);
+	const rows = readPngRows(result.stdout);
+	expect(rows.flat().some((value, i) => i % 4 === 3 && value > 0)).toBe(true);
+	// Every pixel that the text covers has its color
+	for (const row of rows) {
+		for (let x = 0; x < 24; x++) {
+			if (row[x * 4 + 3] > 0) {
+				expect(row.slice(x * 4, x * 4 + 3)).toEqual([255, 0, 0]);
+			}
+		}
+	}
+	// Multi-byte characters get their own glyphs
+	expect(result.stdout).not.toEqual(draw('He', font).stdout);
+
+	const missingFont = path.join(os.tmpdir(), 'missing-font.ttf');
+	const missing = draw('Hello', missingFont);
+	expect(missing.status).not.toBe(0);
+	expect(
+		(JSON.parse(missing.stderr.toString('utf8')) as ErrorPayload).error,
+	).toContain(`Layer 0: Could not read font file ${missingFont}`);
+});
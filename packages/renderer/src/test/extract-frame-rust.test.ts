@@ -1,6 +1,13 @@
+import {spawnSync} from 'node:child_process';
+import {existsSync, mkdtempSync, readFileSync, rmSync} from 'node:fs';
+import os from 'node:os';
+import path from 'node:path';
 import {interpolate} from 'remotion';
 import {expect, test} from 'vitest';
+import {serializeCommand} from '../compositor/compose';
 import {startLongRunningCompositor} from '../compositor/compositor';
+import {getExecutablePath} from '../compositor/get-executable-path';
+import type {SuccessPayload} from '../compositor/payloads';
 import {exampleVideos} from './example-videos';
 
 const BMP_HEADER_SIZE = 54;
@@ -683,3 +690,171 @@ test('Should not duplicate frames for iphoneVideo', async () => {
 
 	expect(isSame).toBe(false);
 });
+
+test('Should be able to extract multiple frames in one call', async () => {
+	const compositor = startLongRunningCompositor({
+		maximumFrameCacheItemsInBytes: null,
+		logLevel: 'info',
+		indent: false,
+		binariesDirectory: null,
+	});
+
+	const dir = mkdtempSync(path.join(os.tmpdir(), 'extract-frames-'));
+
+	const data = await compositor.executeCommand('ExtractFrames', {
+		src: exampleVideos.bigBuckBunny,
+		original_src: exampleVideos.bigBuckBunny,
+		times: [3, 1, 2, 1],
+		output_pattern: path.join(dir, 'frame-{index}.png'),
+		transparent: false,
+		tone_mapped: true,
+	});
+
+	// {index} is the position in times, also if they are not sorted
+	const outputs = JSON.parse(data.toString('utf8')) as string[];
+	expect(outputs).toEqual([
+		path.join(dir, 'frame-0.png'),
+		path.join(dir, 'frame-1.png'),
+		path.join(dir, 'frame-2.png'),
+		path.join(dir, 'frame-3.png'),
+	]);
+	for (const output of outputs) {
+		expect(existsSync(output)).toBe(true);
+	}
+	// The time 1 appears twice and is written to both of its outputs
+	expect(readFileSync(outputs[3])).toEqual(readFileSync(outputs[1]));
+	expect(readFileSync(outputs[0])).not.toEqual(readFileSync(outputs[1]));
+
+	try {
+		await compositor.executeCommand('ExtractFrames', {
+			src: exampleVideos.bigBuckBunny,
+			original_src: exampleVideos.bigBuckBunny,
+			times: [],
+			output_pattern: path.join(dir, 'frame-{index}.png'),
+			transparent: false,
+			tone_mapped: true,
+		});
+		throw new Error('should not be reached');
+	} catch (err) {
+		expect((err as Error).message).toContain(
+			'times must contain at least one timestamp',
+		);
+	}
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+	rmSync(dir, {recursive: true});
+});
+
+test('Should report what was written when extracting a frame to a file', async () => {
+	const compositor = startLongRunningCompositor({
+		maximumFrameCacheItemsInBytes: null,
+		logLevel: 'info',
+		indent: false,
+		binariesDirectory: null,
+	});
+
+	const dir = mkdtempSync(path.join(os.tmpdir(), 'extract-frame-'));
+	const output = path.join(dir, 'frame.bmp');
+
+	const data = await compositor.executeCommand('ExtractFrame', {
+		src: exampleVideos.bigBuckBunny,
+		original_src: exampleVideos.bigBuckBunny,
+		time: 40,
+		transparent: false,
+		tone_mapped: true,
+		output,
+	});
+	const result = JSON.parse(data.toString('utf8')) as SuccessPayload;
+
+	expect(result.output).toBe(output);
+	expect(result.width).toBe(1280);
+	expect(result.height).toBe(720);
+	expect(result.format).toBe('Bmp');
+	expect(result.bytes_written).toBe(1280 * 720 * 3 + BMP_HEADER_SIZE);
+	expect(Math.abs((result.frame_timestamp as number) - 40)).toBeLessThan(0.1);
+	expect(existsSync(output)).toBe(true);
+
+	rmSync(dir, {recursive: true});
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});
+
+test('Should be able to extract a frame by its number', async () => {
+	const compositor = startLongRunningCompositor({
+		maximumFrameCacheItemsInBytes: null,
+		logLevel: 'info',
+		indent: false,
+		binariesDirectory: null,
+	});
+
+	const dir = mkdtempSync(path.join(os.tmpdir(), 'extract-frame-'));
+	const output = path.join(dir, 'frame.bmp');
+
+	const data = await compositor.executeCommand('ExtractFrame', {
+		src: exampleVideos.bigBuckBunny,
+		original_src: exampleVideos.bigBuckBunny,
+		frame: 100,
+		fps: 25,
+		transparent: false,
+		tone_mapped: true,
+		output,
+	});
+	const result = JSON.parse(data.toString('utf8')) as SuccessPayload;
+	expect(result.frame).toBe(100);
+	expect(Math.abs((result.frame_timestamp as number) - 4)).toBeLessThan(0.1);
+
+	try {
+		await compositor.executeCommand('ExtractFrame', {
+			src: exampleVideos.bigBuckBunny,
+			original_src: exampleVideos.bigBuckBunny,
+			frame: 100000000,
+			fps: 25,
+			transparent: false,
+			tone_mapped: true,
+		});
+		throw new Error('should not be reached');
+	} catch (err) {
+		expect((err as Error).message).toContain('Frame 100000000 is out of range');
+	}
+
+	rmSync(dir, {recursive: true});
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});
+
+test('Should print progress to stderr when extracting multiple frames', () => {
+	const dir = mkdtempSync(path.join(os.tmpdir(), 'extract-frames-progress-'));
+	const command = serializeCommand('ExtractFrames', {
+		src: exampleVideos.bigBuckBunny,
+		original_src: exampleVideos.bigBuckBunny,
+		times: [1, 2, 3],
+		output_pattern: path.join(dir, 'frame-{index}.png'),
+		transparent: false,
+		tone_mapped: true,
+		progress: true,
+	});
+
+	const result = spawnSync(
+		getExecutablePath({
+			type: 'compositor',
+			indent: false,
+			logLevel: 'info',
+			binariesDirectory: null,
+		}),
+		[JSON.stringify(command)],
+	);
+
+	expect(result.status).toBe(0);
+	const lines = result.stderr
+		.toString('utf8')
+		.trim()
+		.split('\n')
+		.map((line) => JSON.parse(line));
+	expect(lines).toEqual([
+		{progress: {done: 1, total: 3}},
+		{progress: {done: 2, total: 3}},
+		{progress: {done: 3, total: 3}},
+	]);
+	rmSync(dir, {recursive: true});
+});
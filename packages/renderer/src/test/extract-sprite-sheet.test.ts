@@ -0,0 +1,102 @@
+import {rmSync} from 'node:fs';
+import os from 'node:os';
+import path from 'node:path';
+import {expect, test} from 'vitest';
+import {startLongRunningCompositor} from '../compositor/compositor';
+import type {SpriteSheet} from '../compositor/payloads';
+import {exampleVideos} from './example-videos';
+
+test('Should tile evenly spaced frames into a sprite sheet', async () => {
+	const compositor = startLongRunningCompositor({
+		maximumFrameCacheItemsInBytes: null,
+		logLevel: 'info',
+		indent: false,
+		binariesDirectory: null,
+	});
+	const output = path.join(os.tmpdir(), 'sprite-sheet.jpg');
+
+	const sheet: SpriteSheet = JSON.parse(
+		(
+			await compositor.executeCommand('ExtractSpriteSheet', {
+				input: exampleVideos.framer24fps,
+				columns: 4,
+				rows: 2,
+				thumb_width: 48,
+				thumb_height: 27,
+				start: 0,
+				end: 1,
+				output,
+				output_format: 'Jpeg',
+			})
+		).toString('utf-8'),
+	);
+	expect(sheet.width).toBe(192);
+	expect(sheet.height).toBe(54);
+	expect(sheet.cell_count).toBe(8);
+	// 24 frames in the first second, every third one gets a cell
+	expect(sheet.cells.map((c) => c.frame)).toEqual([0, 3, 6, 9, 12, 15, 18, 21]);
+	expect(sheet.cells[5]).toEqual({
+		column: 1,
+		row: 1,
+		x: 48,
+		y: 27,
+		frame: 15,
+		timestamp: 15 / 24,
+	});
+
+	// 6 frames for 9 cells, the last row is left out
+	const packed: SpriteSheet = JSON.parse(
+		(
+			await compositor.executeCommand('ExtractSpriteSheet', {
+				input: exampleVideos.framer24fps,
+				columns: 3,
+				rows: 3,
+				thumb_width: 48,
+				thumb_height: 27,
+				start: 0,
+				end: 0.25,
+				output,
+				output_format: 'Jpeg',
+			})
+		).toString('utf-8'),
+	);
+	expect(packed.cell_count).toBe(6);
+	expect(packed.rows).toBe(2);
+	expect(packed.height).toBe(54);
+	expect(packed.cells.map((c) => c.frame)).toEqual([0, 1, 2, 3, 4, 5]);
+	rmSync(output);
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});
+
+test('Should reject sprite sheets that are too large', async () => {
+	const compositor = startLongRunningCompositor({
+		maximumFrameCacheItemsInBytes: null,
+		logLevel: 'info',
+		indent: false,
+		binariesDirectory: null,
+	});
+	const extract = (thumb_width: number, max_pixels?: number) =>
+		compositor.executeCommand('ExtractSpriteSheet', {
+			input: exampleVideos.framer24fps,
+			columns: 4,
+			rows: 1,
+			thumb_width,
+			thumb_height: 27,
+			output: path.join(os.tmpdir(), 'sprite-sheet-too-large.png'),
+			output_format: 'Png',
+			max_pixels,
+		});
+
+	// 4 columns of 2^30 pixels do not fit into the width of an image
+	await expect(extract(2 ** 30)).rejects.toThrow(
+		'A sprite sheet of 4294967296x27 pixels is too large',
+	);
+	await expect(extract(48, 1000)).rejects.toThrow(
+		'The canvas has 5184 pixels (192x27), which exceeds max_pixels of 1000',
+	);
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});
@@ -3,9 +3,10 @@ import {callCompositor, serializeCommand} from '../compositor/compose';
 import {startLongRunningCompositor} from '../compositor/compositor';
 
 test('Should get Rust errors in a good way', async () => {
+	// Backtraces are only captured with verbose logging
 	const compositor = startLongRunningCompositor({
 		maximumFrameCacheItemsInBytes: null,
-		logLevel: 'info',
+		logLevel: 'verbose',
 		indent: false,
 		binariesDirectory: null,
 	});
@@ -93,6 +94,8 @@ test(
 			transparent: false,
 			tone_mapped: false,
 		});
+		// The binary inherits the environment
+		process.env.REMOTION_COMPOSITOR_BACKTRACE = '1';
 		try {
 			await callCompositor(JSON.stringify(command), false, 'info', null);
 			throw new Error('should not be reached');
@@ -103,6 +106,8 @@ test(
 			expect((err as Error).stack).toContain(
 				'remotion::opened_stream::open_stream',
 			);
+		} finally {
+			delete process.env.REMOTION_COMPOSITOR_BACKTRACE;
 		}
 	},
 	{retry: 2},
@@ -119,7 +124,7 @@ test('Invalid payloads will be handled', async () => {
 		await callCompositor(JSON.stringify(command), false, 'info', null);
 	} catch (err) {
 		expect((err as Error).message).toContain(
-			'Compositor error: missing field `time`',
+			'Compositor error: missing field `transparent`',
 		);
 	}
 });
@@ -0,0 +1,86 @@
+import path from 'node:path';
+import {expect, test} from 'vitest';
+import {startLongRunningCompositor} from '../compositor/compositor';
+import type {AudioWaveform} from '../compositor/payloads';
+import {exampleVideos} from './example-videos';
+
+test('Should return mono samples and an envelope of the audio', async () => {
+	const compositor = startLongRunningCompositor({
+		maximumFrameCacheItemsInBytes: null,
+		logLevel: 'info',
+		indent: false,
+		binariesDirectory: null,
+	});
+
+	const samples: AudioWaveform = JSON.parse(
+		(
+			await compositor.executeCommand('GetAudioWaveform', {
+				input: exampleVideos.webcam,
+				start: 1.5,
+				duration: 0.5,
+				sample_rate: 1000,
+			})
+		).toString('utf-8'),
+	);
+	expect(samples.has_audio).toBe(true);
+	expect(samples.envelope).toBe(null);
+	expect(samples.samples.length).toBe(500);
+	expect(samples.samples.every((s) => s >= -1 && s <= 1)).toBe(true);
+	// The webcam video is audible after 1 second
+	expect(samples.samples.some((s) => s !== 0)).toBe(true);
+
+	const envelope: AudioWaveform = JSON.parse(
+		(
+			await compositor.executeCommand('GetAudioWaveform', {
+				input: exampleVideos.webcam,
+				start: 1.5,
+				duration: 0.5,
+				sample_rate: 1000,
+				envelope_buckets: 10,
+			})
+		).toString('utf-8'),
+	);
+	expect(envelope.samples).toEqual([]);
+	expect(envelope.envelope?.length).toBe(10);
+	for (const bucket of envelope.envelope ?? []) {
+		expect(bucket.min).toBeLessThanOrEqual(bucket.max);
+	}
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});
+
+test('Should return an empty waveform for videos without audio', async () => {
+	const compositor = startLongRunningCompositor({
+		maximumFrameCacheItemsInBytes: null,
+		logLevel: 'info',
+		indent: false,
+		binariesDirectory: null,
+	});
+
+	const response = await compositor.executeCommand('GetAudioWaveform', {
+		input: path.join(
+			__dirname,
+			'..',
+			'..',
+			'..',
+			'example',
+			'src',
+			'resources',
+			'framer-24fps.mp4',
+		),
+		start: 0,
+		duration: 1,
+		envelope_buckets: 10,
+	});
+	const waveform: AudioWaveform = {
+		has_audio: false,
+		sample_rate: 8000,
+		samples: [],
+		envelope: [],
+	};
+	expect(JSON.parse(response.toString('utf-8'))).toEqual(waveform);
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});
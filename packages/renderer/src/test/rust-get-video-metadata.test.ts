@@ -2,7 +2,7 @@ import {existsSync} from 'node:fs';
 import path from 'node:path';
 import {expect, test} from 'vitest';
 import {startLongRunningCompositor} from '../compositor/compositor';
-import type {VideoMetadata} from '../compositor/payloads';
+import type {ProbeResult, VideoMetadata} from '../compositor/payloads';
 
 test('Should return video metadata', async () => {
 	const compositor = startLongRunningCompositor({
@@ -93,3 +93,43 @@ test('Should return an error due to using a audio file', async () => {
 		);
 	}
 });
+
+test('Should probe a video', async () => {
+	const compositor = startLongRunningCompositor({
+		maximumFrameCacheItemsInBytes: null,
+		logLevel: 'info',
+		indent: false,
+		binariesDirectory: null,
+	});
+
+	const videoFile = path.join(
+		__dirname,
+		'..',
+		'..',
+		'..',
+		'example',
+		'src',
+		'resources',
+		'framer-24fps.mp4',
+	);
+	const response = await compositor.executeCommand('Probe', {
+		input: videoFile,
+	});
+	const probe = JSON.parse(response.toString('utf-8')) as ProbeResult;
+
+	expect(probe.width).toBe(1080);
+	expect(probe.height).toBe(1080);
+	expect(probe.fps).toBe(24);
+	expect(probe.duration_seconds).toBeCloseTo(4.166667);
+	expect(probe.frame_count).toBe(100);
+
+	try {
+		await compositor.executeCommand('Probe', {input: 'not-a-video.mp4'});
+		throw new Error('should not be reached');
+	} catch (err) {
+		expect((err as Error).message).toContain('Could not probe not-a-video.mp4');
+	}
+
+	await compositor.finishCommands();
+	await compositor.waitForDone();
+});